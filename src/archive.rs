@@ -0,0 +1,319 @@
+// Zip and gzipped tar archives of whole folders, mainly so that backups take less space than the
+// plain folder copies made by file::back_up_folder_next_number_r(). Archives are named the same
+// way as those backups, like "Backup 2021-06-14 003.zip".
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use zip::write::SimpleFileOptions;
+
+use crate::*;
+use crate::date_time::date_for_file_name_now;
+use crate::file::{path_create_if_necessary_r, path_entries_r, path_exists_r, path_file_name_r, path_file_next_number_r, path_is_new_r, path_name};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    // The path within the archive using "/" as the separator, such as "Docs/Notes.txt".
+    pub name: String,
+    pub size: u64,
+    pub is_folder: bool,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn from_path_r<P>(path: P) -> Result<Self, String>
+        where P: AsRef<Path>
+    {
+        let file_name = path_file_name_r(&path)?.to_lowercase();
+        if file_name.ends_with(".zip") {
+            Ok(ArchiveFormat::Zip)
+        } else if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+            Ok(ArchiveFormat::TarGz)
+        } else {
+            Err(format!("\"{}\" isn't a .zip or .tar.gz file.", path_name(&path)))
+        }
+    }
+}
+
+pub fn archive_folder_r<S, D>(path_source_folder: S, path_archive_file: D, format: ArchiveFormat) -> Result<(), String>
+    where
+        S: AsRef<Path>,
+        D: AsRef<Path>,
+{
+    // Write the contents of the folder (but not the folder itself) to a new archive. If anything
+    // goes wrong the partly-written archive is removed.
+    path_exists_r(&path_source_folder)?;
+    path_is_new_r(&path_archive_file)?;
+    let mut entries = vec![];
+    add_folder_entries_r(path_source_folder.as_ref(), "", &mut entries)?;
+    let result = match format {
+        ArchiveFormat::Zip => write_zip_r(path_archive_file.as_ref(), &entries),
+        ArchiveFormat::TarGz => write_tar_gz_r(path_archive_file.as_ref(), &entries),
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(&path_archive_file);
+    }
+    err_context(result, &format!("archive_folder_r: \"{}\":", path_name(&path_archive_file)))
+}
+
+pub fn back_up_folder_archive_next_number_r<S, D>(path_source: S, path_dest_folder: D, prefix: &str, digits: usize, format: ArchiveFormat) -> Result<PathBuf, String>
+    where
+        S: AsRef<Path>,
+        D: AsRef<Path>,
+{
+    path_exists_r(&path_source)?;
+    path_create_if_necessary_r(&path_dest_folder)?;
+    let path_archive = path_file_next_number_r(path_dest_folder, prefix, format.extension(), digits)?;
+    archive_folder_r(path_source, &path_archive, format)?;
+    Ok(path_archive)
+}
+
+pub fn back_up_folder_archive_dated_next_number_r<S, D>(path_source: S, path_dest_folder: D, prefix: &str, digits: usize, format: ArchiveFormat) -> Result<PathBuf, String>
+    where
+        S: AsRef<Path>,
+        D: AsRef<Path>,
+{
+    let date_string = date_for_file_name_now();
+    let prefix = format!("{} {}", prefix, date_string);
+    back_up_folder_archive_next_number_r(path_source, path_dest_folder, &prefix, digits, format)
+}
+
+pub fn list_archive_r<P>(path_archive_file: P) -> Result<Vec<ArchiveEntry>, String>
+    where P: AsRef<Path>
+{
+    path_exists_r(&path_archive_file)?;
+    let context = format!("list_archive_r: \"{}\":", path_name(&path_archive_file));
+    let entries = match ArchiveFormat::from_path_r(&path_archive_file)? {
+        ArchiveFormat::Zip => list_zip_r(path_archive_file.as_ref()),
+        ArchiveFormat::TarGz => list_tar_gz_r(path_archive_file.as_ref()),
+    };
+    err_context(entries, &context)
+}
+
+pub fn extract_archive_r<S, D>(path_archive_file: S, path_dest_folder: D) -> Result<(), String>
+    where
+        S: AsRef<Path>,
+        D: AsRef<Path>,
+{
+    // Extract everything into the destination folder. Every name in the archive is checked first
+    // and if any of them is absolute or uses ".." to climb out of the destination, nothing is
+    // extracted. Links and other special entries in a tar file are skipped.
+    let context = format!("extract_archive_r: \"{}\":", path_name(&path_archive_file));
+    for entry in list_archive_r(&path_archive_file)? {
+        err_context(archive_entry_path_r(&entry.name), &context)?;
+    }
+    path_create_if_necessary_r(&path_dest_folder)?;
+    let result = match ArchiveFormat::from_path_r(&path_archive_file)? {
+        ArchiveFormat::Zip => extract_zip_r(path_archive_file.as_ref(), path_dest_folder.as_ref()),
+        ArchiveFormat::TarGz => extract_tar_gz_r(path_archive_file.as_ref(), path_dest_folder.as_ref()),
+    };
+    err_context(result, &context)
+}
+
+fn archive_entry_path_r(name: &str) -> Result<PathBuf, String> {
+    // The relative path an archive entry will be extracted to, or an error if the name could
+    // point anywhere outside the destination folder.
+    let mut path = PathBuf::new();
+    for component in Path::new(&name.replace('\\', "/")).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {},
+            _ => return Err(format!("The entry \"{}\" would be extracted outside the destination folder.", name)),
+        }
+    }
+    if path.as_os_str().is_empty() {
+        return Err(format!("The entry \"{}\" has no name.", name));
+    }
+    Ok(path)
+}
+
+fn add_folder_entries_r(path_folder: &Path, name_prefix: &str, entries: &mut Vec<(PathBuf, String, bool)>) -> Result<(), String> {
+    // Collect (path, name in archive, is folder) for everything under the folder, sorted so that
+    // the same folder always makes the same archive.
+    let mut paths = path_entries_r(path_folder)?;
+    paths.sort();
+    for path in paths {
+        let name = format!("{}{}", name_prefix, path_file_name_r(&path)?);
+        let metadata = rse!(fs::symlink_metadata(&path))?;
+        if metadata.is_dir() {
+            entries.push((path.clone(), name.clone(), true));
+            add_folder_entries_r(&path, &format!("{}/", name), entries)?;
+        } else if metadata.is_file() {
+            entries.push((path, name, false));
+        }
+    }
+    Ok(())
+}
+
+fn write_zip_r(path_archive_file: &Path, entries: &[(PathBuf, String, bool)]) -> Result<(), String> {
+    let mut zip = ZipWriter::new(rse!(File::create(path_archive_file))?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (path, name, is_folder) in entries.iter() {
+        if *is_folder {
+            rse!(zip.add_directory(name.as_str(), options))?;
+        } else {
+            rse!(zip.start_file(name.as_str(), options))?;
+            let mut file = rse!(File::open(path))?;
+            rse!(io::copy(&mut file, &mut zip))?;
+        }
+    }
+    rse!(zip.finish())?;
+    Ok(())
+}
+
+fn write_tar_gz_r(path_archive_file: &Path, entries: &[(PathBuf, String, bool)]) -> Result<(), String> {
+    let encoder = GzEncoder::new(rse!(File::create(path_archive_file))?, Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for (path, name, _) in entries.iter() {
+        // This handles both files and folders, and for folders adds only the folder entry itself.
+        rse!(builder.append_path_with_name(path, name))?;
+    }
+    let encoder = rse!(builder.into_inner())?;
+    rse!(encoder.finish())?;
+    Ok(())
+}
+
+fn list_zip_r(path_archive_file: &Path) -> Result<Vec<ArchiveEntry>, String> {
+    let mut zip = rse!(ZipArchive::new(rse!(File::open(path_archive_file))?))?;
+    let mut entries = vec![];
+    for index in 0..zip.len() {
+        let file = rse!(zip.by_index(index))?;
+        entries.push(ArchiveEntry {
+            name: file.name().trim_end_matches('/').to_string(),
+            size: file.size(),
+            is_folder: file.is_dir(),
+        });
+    }
+    Ok(entries)
+}
+
+fn list_tar_gz_r(path_archive_file: &Path) -> Result<Vec<ArchiveEntry>, String> {
+    let mut archive = tar::Archive::new(GzDecoder::new(rse!(File::open(path_archive_file))?));
+    let mut entries = vec![];
+    for entry in rse!(archive.entries())? {
+        let entry = rse!(entry)?;
+        let name = String::from_utf8_lossy(&entry.path_bytes()).trim_end_matches('/').to_string();
+        entries.push(ArchiveEntry {
+            name,
+            size: entry.header().size().unwrap_or(0),
+            is_folder: entry.header().entry_type().is_dir(),
+        });
+    }
+    Ok(entries)
+}
+
+fn extract_zip_r(path_archive_file: &Path, path_dest_folder: &Path) -> Result<(), String> {
+    let mut zip = rse!(ZipArchive::new(rse!(File::open(path_archive_file))?))?;
+    for index in 0..zip.len() {
+        let mut file = rse!(zip.by_index(index))?;
+        let path_dest = path_dest_folder.join(archive_entry_path_r(file.name())?);
+        if file.is_dir() {
+            rse!(fs::create_dir_all(&path_dest))?;
+        } else {
+            if let Some(path_parent) = path_dest.parent() {
+                rse!(fs::create_dir_all(path_parent))?;
+            }
+            let mut dest_file = rse!(File::create(&path_dest))?;
+            rse!(io::copy(&mut file, &mut dest_file))?;
+        }
+    }
+    Ok(())
+}
+
+fn extract_tar_gz_r(path_archive_file: &Path, path_dest_folder: &Path) -> Result<(), String> {
+    let mut archive = tar::Archive::new(GzDecoder::new(rse!(File::open(path_archive_file))?));
+    for entry in rse!(archive.entries())? {
+        let mut entry = rse!(entry)?;
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            continue;
+        }
+        let path_dest = path_dest_folder.join(archive_entry_path_r(&String::from_utf8_lossy(&entry.path_bytes()))?);
+        if entry_type.is_dir() {
+            rse!(fs::create_dir_all(&path_dest))?;
+        } else {
+            if let Some(path_parent) = path_dest.parent() {
+                rse!(fs::create_dir_all(path_parent))?;
+            }
+            rse!(entry.unpack(&path_dest))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::path_exists;
+    use crate::temp_workspace::TempWorkspace;
+
+    #[test]
+    fn test_archive_and_extract() {
+        let workspace = TempWorkspace::new("test_archive_and_extract");
+        let path_root = workspace.path();
+        let path_source = path_root.join("Source");
+        fs::create_dir_all(path_source.join("Docs").join("Empty")).unwrap();
+        fs::write(path_source.join("top.txt"), "top").unwrap();
+        fs::write(path_source.join("Docs").join("notes.txt"), "notes ".repeat(100)).unwrap();
+
+        for format in [ArchiveFormat::Zip, ArchiveFormat::TarGz].iter() {
+            let path_backups = path_root.join("Backups");
+            let path_archive = back_up_folder_archive_next_number_r(&path_source, &path_backups, "Backup", 3, *format).unwrap();
+            assert_eq!(format!("Backup 001.{}", format.extension()), path_file_name_r(&path_archive).unwrap());
+
+            let expected = vec![
+                ArchiveEntry { name: "Docs".to_string(), size: 0, is_folder: true },
+                ArchiveEntry { name: "Docs/Empty".to_string(), size: 0, is_folder: true },
+                ArchiveEntry { name: "Docs/notes.txt".to_string(), size: 600, is_folder: false },
+                ArchiveEntry { name: "top.txt".to_string(), size: 3, is_folder: false },
+            ];
+            assert_eq!(expected, list_archive_r(&path_archive).unwrap());
+
+            let path_dest = path_root.join("Dest");
+            extract_archive_r(&path_archive, &path_dest).unwrap();
+            assert_eq!("notes ".repeat(100), fs::read_to_string(path_dest.join("Docs").join("notes.txt")).unwrap());
+            assert_eq!("top", fs::read_to_string(path_dest.join("top.txt")).unwrap());
+            assert!(path_exists(path_dest.join("Docs").join("Empty")));
+
+            fs::remove_dir_all(&path_dest).unwrap();
+            fs::remove_dir_all(&path_backups).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_extract_rejects_path_traversal() {
+        let workspace = TempWorkspace::new("test_extract_rejects_path_traversal");
+        let path_root = workspace.path();
+        let path_archive = path_root.join("Evil.zip");
+        let mut zip = ZipWriter::new(File::create(&path_archive).unwrap());
+        zip.start_file("good.txt", SimpleFileOptions::default()).unwrap();
+        zip.start_file("../evil.txt", SimpleFileOptions::default()).unwrap();
+        zip.finish().unwrap();
+
+        let path_dest = path_root.join("Dest");
+        assert!(extract_archive_r(&path_archive, &path_dest).is_err());
+        assert!(!path_exists(path_dest.join("good.txt")));
+        assert!(!path_exists(path_root.join("evil.txt")));
+
+        assert!(archive_entry_path_r("/etc/passwd").is_err());
+        assert!(archive_entry_path_r(r"a\..\..\b").is_err());
+        assert_eq!(PathBuf::from("a").join("b"), archive_entry_path_r("./a/b/").unwrap());
+    }
+}
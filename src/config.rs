@@ -0,0 +1,729 @@
+// Configuration files in an INI/TOML-like format, replacing the combination of
+// parse::break_into_sections_by_line() and parse::parse_name_value_pairs(). A file looks like:
+//
+//     # Settings for the nightly import.
+//     name = "Import"        ; Trailing comments work too.
+//     enabled = yes
+//
+//     [paths]
+//     source = 'C:\Data\Incoming'
+//     backup.folder = "D:\\Backups"
+//     backup.keep = 10
+//     skip = ["tmp", "old"]
+//
+// Keys are looked up by their full dotted name such as "paths.backup.keep". Values may be quoted
+// strings (with escapes in double quotes but not in single quotes), whole or decimal numbers,
+// dates like 2021-06-14, booleans as understood by bool::string_to_bool(), arrays in brackets, or
+// unquoted text. The document keeps every line as it was written, so that after changing a few
+// values it can be written back with the comments and layout intact.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::{self, Display};
+use std::path::Path;
+
+use chrono::NaiveDate;
+
+use crate::bool::string_to_bool;
+use crate::date_time::{naive_date_from_multiple_formats, naive_date_from_sortable_format, naive_date_to_sortable_format};
+use crate::file::{path_name, read_file_to_string_r, write_file_atomic_r};
+use crate::*;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Date(NaiveDate),
+    Array(Vec<ConfigValue>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigDocument {
+    lines: Vec<ConfigLine>,
+    // "\r\n" if the text had Windows line endings, so that they're kept when it's written back.
+    line_ending: String,
+    trailing_newline: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum ConfigLine {
+    // A blank line or a comment, kept exactly as it was.
+    Other(String),
+    Section {
+        name: String,
+        text: String,
+    },
+    Entry {
+        // The full dotted key including the section name.
+        key: String,
+        value: ConfigValue,
+        // The text before the value, such as "  name = ", and after it, such as "  # comment",
+        // so that the line can be rebuilt with a new value.
+        prefix: String,
+        value_text: String,
+        suffix: String,
+    },
+}
+
+impl ConfigValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ConfigValue::String(_) => "string",
+            ConfigValue::Integer(_) => "integer",
+            ConfigValue::Float(_) => "number",
+            ConfigValue::Bool(_) => "bool",
+            ConfigValue::Date(_) => "date",
+            ConfigValue::Array(_) => "array",
+        }
+    }
+
+    pub fn to_config_text(&self) -> String {
+        // The value as it would be written in a file.
+        match self {
+            ConfigValue::String(value) => quote_config_string(value),
+            ConfigValue::Integer(value) => value.to_string(),
+            ConfigValue::Float(value) => format!("{:?}", value),
+            ConfigValue::Bool(value) => value.to_string(),
+            ConfigValue::Date(value) => naive_date_to_sortable_format(value),
+            ConfigValue::Array(values) => format!("[{}]", values.iter().map(|value| value.to_config_text()).collect::<Vec<_>>().join(", ")),
+        }
+    }
+}
+
+impl Display for ConfigValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigValue::String(value) => write!(f, "{}", value),
+            _ => write!(f, "{}", self.to_config_text()),
+        }
+    }
+}
+
+impl From<&str> for ConfigValue {
+    fn from(value: &str) -> Self {
+        ConfigValue::String(value.to_string())
+    }
+}
+
+impl From<i64> for ConfigValue {
+    fn from(value: i64) -> Self {
+        ConfigValue::Integer(value)
+    }
+}
+
+impl From<f64> for ConfigValue {
+    fn from(value: f64) -> Self {
+        ConfigValue::Float(value)
+    }
+}
+
+impl From<bool> for ConfigValue {
+    fn from(value: bool) -> Self {
+        ConfigValue::Bool(value)
+    }
+}
+
+impl From<NaiveDate> for ConfigValue {
+    fn from(value: NaiveDate) -> Self {
+        ConfigValue::Date(value)
+    }
+}
+
+impl ConfigDocument {
+    pub fn new() -> Self {
+        Self { lines: vec![], line_ending: "\n".to_string(), trailing_newline: true }
+    }
+
+    pub fn parse_r(text: &str) -> Result<Self, String> {
+        // Every problem in the text is reported, one per line of the message, rather than just the
+        // first.
+        let text = text.trim_start_matches('\u{feff}');
+        let line_ending = if text.contains("\r\n") { "\r\n" } else { "\n" };
+        let mut document = Self { lines: vec![], line_ending: line_ending.to_string(), trailing_newline: text.ends_with('\n') };
+        let mut errors = vec![];
+        let mut section = String::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            match parse_config_line(line, &section) {
+                Ok(config_line) => {
+                    match &config_line {
+                        ConfigLine::Section { name, .. } => section = name.clone(),
+                        ConfigLine::Entry { key, .. } => {
+                            if let Err(msg) = document.check_new_key_r(key) {
+                                errors.push(format!("Line {}: {}", line_number, msg));
+                                continue;
+                            }
+                        },
+                        ConfigLine::Other(_) => {},
+                    }
+                    document.lines.push(config_line);
+                },
+                Err((pos, msg)) => {
+                    let column = line[..pos].chars().count() + 1;
+                    errors.push(format!("Line {}, column {}: {}", line_number, column, msg));
+                },
+            }
+        }
+        if errors.is_empty() {
+            Ok(document)
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    pub fn read_r<P>(path: P) -> Result<Self, String>
+        where P: AsRef<Path>
+    {
+        let text = read_file_to_string_r(&path)?;
+        err_context(Self::parse_r(&text), &format!("ConfigDocument::read_r: \"{}\":", path_name(&path)))
+    }
+
+    pub fn write_r<P>(&self, path: P) -> Result<(), String>
+        where P: AsRef<Path>
+    {
+        write_file_atomic_r(path, &self.to_text())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = self.lines.iter().map(|line| line.text()).collect::<Vec<_>>().join(&self.line_ending);
+        if self.trailing_newline && !text.is_empty() {
+            text.push_str(&self.line_ending);
+        }
+        text
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        // Full dotted keys in the order they appear.
+        self.lines.iter().filter_map(|line| match line {
+            ConfigLine::Entry { key, .. } => Some(key.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    pub fn sections(&self) -> Vec<String> {
+        self.lines.iter().filter_map(|line| match line {
+            ConfigLine::Section { name, .. } => Some(name.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    pub fn section_map(&self, section: &str) -> BTreeMap<String, ConfigValue> {
+        // The values under a section or other dotted prefix, keyed by the rest of their names.
+        // An empty section gives every value.
+        let prefix = if section.is_empty() { "".to_string() } else { format!("{}.", section) };
+        self.entries()
+            .filter_map(|(key, value)| key.strip_prefix(&prefix).map(|key| (key.to_string(), value.clone())))
+            .collect()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ConfigValue> {
+        self.entries().find(|(entry_key, _)| *entry_key == key).map(|(_, value)| value)
+    }
+
+    pub fn get_r(&self, key: &str) -> Result<&ConfigValue, String> {
+        self.get(key).ok_or_else(|| format!("Config: no value for \"{}\".", key))
+    }
+
+    pub fn get_str_r(&self, key: &str) -> Result<&str, String> {
+        match self.get_r(key)? {
+            ConfigValue::String(value) => Ok(value),
+            value => Err(wrong_type(key, value, "a string")),
+        }
+    }
+
+    pub fn get_bool_r(&self, key: &str) -> Result<bool, String> {
+        // A quoted string like "yes" works as well as a bare yes.
+        match self.get_r(key)? {
+            ConfigValue::Bool(value) => Ok(*value),
+            ConfigValue::String(value) => err_context(string_to_bool(value), &format!("Config: \"{}\":", key)),
+            value => Err(wrong_type(key, value, "a bool")),
+        }
+    }
+
+    pub fn get_i64_r(&self, key: &str) -> Result<i64, String> {
+        match self.get_r(key)? {
+            ConfigValue::Integer(value) => Ok(*value),
+            value => Err(wrong_type(key, value, "a whole number")),
+        }
+    }
+
+    pub fn get_usize_r(&self, key: &str) -> Result<usize, String> {
+        let value = self.get_i64_r(key)?;
+        usize::try_from(value).map_err(|_| format!("Config: \"{}\" is {} but it can't be negative.", key, value))
+    }
+
+    pub fn get_f64_r(&self, key: &str) -> Result<f64, String> {
+        match self.get_r(key)? {
+            ConfigValue::Float(value) => Ok(*value),
+            ConfigValue::Integer(value) => Ok(*value as f64),
+            value => Err(wrong_type(key, value, "a number")),
+        }
+    }
+
+    pub fn get_date_r(&self, key: &str) -> Result<NaiveDate, String> {
+        // A quoted date may be in any of the formats known to date_time.
+        match self.get_r(key)? {
+            ConfigValue::Date(value) => Ok(*value),
+            ConfigValue::String(value) => err_context(naive_date_from_multiple_formats(value), &format!("Config: \"{}\":", key)),
+            value => Err(wrong_type(key, value, "a date")),
+        }
+    }
+
+    pub fn get_array_r(&self, key: &str) -> Result<&[ConfigValue], String> {
+        match self.get_r(key)? {
+            ConfigValue::Array(values) => Ok(values),
+            value => Err(wrong_type(key, value, "an array")),
+        }
+    }
+
+    pub fn get_string_array_r(&self, key: &str) -> Result<Vec<String>, String> {
+        self.get_array_r(key)?.iter().map(|value| match value {
+            ConfigValue::String(value) => Ok(value.clone()),
+            value => Err(wrong_type(key, value, "an array of strings")),
+        }).collect()
+    }
+
+    pub fn set_r<V>(&mut self, key: &str, value: V) -> Result<(), String>
+        where V: Into<ConfigValue>
+    {
+        // Change the value in place if the key is already there, keeping its comment. Otherwise
+        // add it to the end of the longest section whose name starts the key, or to a new section
+        // named for everything before the last dot.
+        let value = value.into();
+        let value_text = value.to_config_text();
+        for line in self.lines.iter_mut() {
+            if let ConfigLine::Entry { key: entry_key, value: entry_value, value_text: entry_value_text, .. } = line {
+                if entry_key == key {
+                    *entry_value = value;
+                    *entry_value_text = value_text;
+                    return Ok(());
+                }
+            }
+        }
+        self.check_new_key_r(key)?;
+        let section = self.sections().into_iter()
+            .filter(|section| key.starts_with(&format!("{}.", section)))
+            .max_by_key(|section| section.len());
+        let (section, local_key, index) = match section {
+            Some(section) => {
+                let index = self.section_end(&section);
+                let local_key = key[section.len() + 1..].to_string();
+                (section, local_key, index)
+            },
+            None => match key.rsplit_once('.') {
+                Some((parent, local_key)) => {
+                    if !self.lines.is_empty() {
+                        self.lines.push(ConfigLine::Other("".to_string()));
+                    }
+                    self.lines.push(ConfigLine::Section { name: parent.to_string(), text: format!("[{}]", parent) });
+                    (parent.to_string(), local_key.to_string(), self.lines.len())
+                },
+                None => ("".to_string(), key.to_string(), self.section_end("")),
+            },
+        };
+        let full_key = if section.is_empty() { local_key.clone() } else { format!("{}.{}", section, local_key) };
+        let line = ConfigLine::Entry { key: full_key, value, prefix: format!("{} = ", local_key), value_text, suffix: "".to_string() };
+        self.lines.insert(index, line);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        let count = self.lines.len();
+        self.lines.retain(|line| !matches!(line, ConfigLine::Entry { key: entry_key, .. } if entry_key == key));
+        self.lines.len() < count
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (&str, &ConfigValue)> {
+        self.lines.iter().filter_map(|line| match line {
+            ConfigLine::Entry { key, value, .. } => Some((key.as_str(), value)),
+            _ => None,
+        })
+    }
+
+    fn section_end(&self, section: &str) -> usize {
+        // Where to add a new entry to a section: after its last entry, or right after the header
+        // if it has none. The root section ends at the first header.
+        let start = if section.is_empty() {
+            0
+        } else {
+            self.lines.iter().position(|line| matches!(line, ConfigLine::Section { name, .. } if name == section)).map_or(self.lines.len(), |index| index + 1)
+        };
+        let end = self.lines[start..].iter().position(|line| matches!(line, ConfigLine::Section { .. })).map_or(self.lines.len(), |index| start + index);
+        self.lines[start..end].iter().rposition(|line| matches!(line, ConfigLine::Entry { .. })).map_or(start, |index| start + index + 1)
+    }
+
+    fn check_new_key_r(&self, key: &str) -> Result<(), String> {
+        // A key can't be defined twice, and a key can't be both a value and a table of other
+        // values, as with "a = 1" and "a.b = 2".
+        for existing in self.keys() {
+            if existing == key {
+                return Err(format!("The key \"{}\" is already defined.", key));
+            }
+            if key.starts_with(&format!("{}.", existing)) || existing.starts_with(&format!("{}.", key)) {
+                return Err(format!("The key \"{}\" conflicts with \"{}\".", key, existing));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for ConfigDocument {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConfigLine {
+    fn text(&self) -> String {
+        match self {
+            ConfigLine::Other(text) => text.clone(),
+            ConfigLine::Section { text, .. } => text.clone(),
+            ConfigLine::Entry { prefix, value_text, suffix, .. } => format!("{}{}{}", prefix, value_text, suffix),
+        }
+    }
+}
+
+fn wrong_type(key: &str, value: &ConfigValue, expected: &str) -> String {
+    format!("Config: \"{}\" is {} ({}) rather than {}.", key, value.type_name(), value.to_config_text(), expected)
+}
+
+// The parsing functions below return errors as a byte position in the line along with the
+// message, which parse_r() turns into a column number.
+
+fn parse_config_line(line: &str, section: &str) -> Result<ConfigLine, (usize, String)> {
+    let trimmed = line.trim();
+    let indent = line.len() - line.trim_start().len();
+    if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+        return Ok(ConfigLine::Other(line.to_string()));
+    }
+    if trimmed.starts_with("[[") {
+        return Err((indent, "Arrays of tables aren't supported.".to_string()));
+    }
+    if trimmed.starts_with('[') {
+        let close = line.find(']').ok_or((line.len(), "Expected \"]\" to end the section name.".to_string()))?;
+        check_comment_only(line, close + 1)?;
+        let name = parse_key(&line[indent + 1..close], indent + 1)?;
+        return Ok(ConfigLine::Section { name, text: line.to_string() });
+    }
+    let equals = find_outside_quotes(line, '=').ok_or((indent, "Expected a section like \"[name]\" or a value like \"name = value\".".to_string()))?;
+    let local_key = parse_key(&line[..equals], 0)?;
+    let key = if section.is_empty() { local_key } else { format!("{}.{}", section, local_key) };
+    let value_start = equals + 1 + (line[equals + 1..].len() - line[equals + 1..].trim_start().len());
+    let (value, value_end) = parse_value(line, value_start, false)?;
+    check_comment_only(line, value_end)?;
+    Ok(ConfigLine::Entry {
+        key,
+        value,
+        prefix: line[..value_start].to_string(),
+        value_text: line[value_start..value_end].to_string(),
+        suffix: line[value_end..].to_string(),
+    })
+}
+
+fn parse_key(text: &str, offset: usize) -> Result<String, (usize, String)> {
+    // A dotted key like "backup.folder" or "servers.\"web 1\".port", normalized so that the
+    // spacing around the dots and quotes around simple names don't matter.
+    let mut parts = vec![];
+    let mut pos = 0;
+    loop {
+        let rest = &text[pos..];
+        let skipped = rest.len() - rest.trim_start().len();
+        pos += skipped;
+        let (part, end) = if text[pos..].starts_with('"') {
+            match parse_value(text, pos, true)? {
+                (ConfigValue::String(part), end) => (part, end),
+                _ => unreachable!(),
+            }
+        } else {
+            let end = text[pos..].find('.').map_or(text.len(), |index| pos + index);
+            (text[pos..end].trim().to_string(), end)
+        };
+        if part.is_empty() {
+            return Err((offset + pos, "Expected a name.".to_string()));
+        }
+        if part.contains(['[', ']', '"', '\'', '=']) {
+            return Err((offset + pos, format!("The name \"{}\" has a character that needs quotes.", part)));
+        }
+        parts.push(part);
+        let after = &text[end..];
+        pos = end + (after.len() - after.trim_start().len());
+        if pos >= text.len() {
+            break;
+        }
+        if !text[pos..].starts_with('.') {
+            return Err((offset + pos, "Expected \".\" between the parts of a name.".to_string()));
+        }
+        pos += 1;
+    }
+    Ok(parts.iter().map(|part| if part.contains('.') { quote_config_string(part) } else { part.clone() }).collect::<Vec<_>>().join("."))
+}
+
+fn parse_value(line: &str, start: usize, in_array: bool) -> Result<(ConfigValue, usize), (usize, String)> {
+    // Returns the value and the position just after it.
+    let rest = &line[start..];
+    if rest.starts_with('"') {
+        return parse_basic_string(line, start);
+    }
+    if let Some(literal) = rest.strip_prefix('\'') {
+        let close = literal.find('\'').ok_or((start, "The string has no closing quote.".to_string()))?;
+        return Ok((ConfigValue::String(literal[..close].to_string()), start + close + 2));
+    }
+    if rest.starts_with('[') {
+        return parse_array(line, start);
+    }
+    // Unquoted text runs to a comment or the end of the line, or in an array to the next comma or
+    // bracket.
+    let end = if in_array {
+        rest.find([',', ']']).map_or(line.len(), |index| start + index)
+    } else {
+        comment_start(line, start).unwrap_or(line.len())
+    };
+    let token = line[start..end].trim_end();
+    if token.is_empty() {
+        return Err((start, "Expected a value.".to_string()));
+    }
+    Ok((parse_bare_value(token), start + token.len()))
+}
+
+fn parse_bare_value(token: &str) -> ConfigValue {
+    if token.chars().all(|c| c.is_ascii_digit() || "+-_".contains(c)) {
+        if let Ok(value) = token.replace('_', "").parse::<i64>() {
+            return ConfigValue::Integer(value);
+        }
+    }
+    if token.chars().any(|c| c.is_ascii_digit()) && token.chars().all(|c| c.is_ascii_digit() || "+-_.eE".contains(c)) {
+        if let Ok(value) = token.replace('_', "").parse::<f64>() {
+            return ConfigValue::Float(value);
+        }
+    }
+    if let Ok(date) = naive_date_from_sortable_format(token) {
+        return ConfigValue::Date(date);
+    }
+    if let Ok(value) = string_to_bool(token) {
+        return ConfigValue::Bool(value);
+    }
+    ConfigValue::String(token.to_string())
+}
+
+fn parse_basic_string(line: &str, start: usize) -> Result<(ConfigValue, usize), (usize, String)> {
+    // A double-quoted string with backslash escapes. start is the position of the opening quote.
+    let mut value = String::new();
+    let mut chars = line[start + 1..].char_indices().map(|(index, c)| (start + 1 + index, c));
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '"' => return Ok((ConfigValue::String(value), pos + 1)),
+            '\\' => {
+                let (_, escaped) = chars.next().ok_or((pos, "The string ends with a backslash.".to_string()))?;
+                match escaped {
+                    '"' => value.push('"'),
+                    '\\' => value.push('\\'),
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    'r' => value.push('\r'),
+                    'u' | 'U' => {
+                        let digit_count = if escaped == 'u' { 4 } else { 8 };
+                        let digits = chars.by_ref().take(digit_count).map(|(_, c)| c).collect::<String>();
+                        let c = u32::from_str_radix(&digits, 16).ok().filter(|_| digits.len() == digit_count).and_then(char::from_u32)
+                            .ok_or((pos, format!("\"\\{}{}\" isn't a valid character code.", escaped, digits)))?;
+                        value.push(c);
+                    },
+                    _ => return Err((pos, format!("Unknown escape sequence \"\\{}\".", escaped))),
+                }
+            },
+            _ => value.push(c),
+        }
+    }
+    Err((start, "The string has no closing quote.".to_string()))
+}
+
+fn parse_array(line: &str, start: usize) -> Result<(ConfigValue, usize), (usize, String)> {
+    // Values separated by commas, which may themselves be arrays. A trailing comma is allowed.
+    let mut values = vec![];
+    let mut pos = start + 1;
+    loop {
+        pos = skip_spaces(line, pos);
+        if line[pos..].starts_with(']') {
+            return Ok((ConfigValue::Array(values), pos + 1));
+        }
+        if pos >= line.len() {
+            return Err((start, "The array has no closing \"]\".".to_string()));
+        }
+        let (value, end) = parse_value(line, pos, true)?;
+        values.push(value);
+        pos = skip_spaces(line, end);
+        if pos >= line.len() {
+            return Err((start, "The array has no closing \"]\".".to_string()));
+        }
+        if line[pos..].starts_with(',') {
+            pos += 1;
+        } else if !line[pos..].starts_with(']') {
+            return Err((pos, "Expected \",\" or \"]\" in the array.".to_string()));
+        }
+    }
+}
+
+fn skip_spaces(line: &str, pos: usize) -> usize {
+    let rest = &line[pos..];
+    pos + rest.len() - rest.trim_start().len()
+}
+
+fn check_comment_only(line: &str, pos: usize) -> Result<(), (usize, String)> {
+    // After a value or section name there can only be spaces and a comment.
+    let pos = skip_spaces(line, pos);
+    if pos < line.len() && !line[pos..].starts_with(['#', ';']) {
+        Err((pos, "Unexpected text after the value. Use quotes if it's part of the value.".to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+fn comment_start(line: &str, start: usize) -> Option<usize> {
+    // In unquoted text a comment has to have a space before it so that a value like "#fff" or
+    // "a;b" isn't cut short.
+    let rest = &line[start..];
+    if rest.starts_with(['#', ';']) {
+        return Some(start);
+    }
+    rest.char_indices()
+        .find(|(index, c)| (*c == '#' || *c == ';') && rest[..*index].ends_with([' ', '\t']))
+        .map(|(index, _)| start + index)
+}
+
+fn find_outside_quotes(text: &str, target: char) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' && q == '"' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            },
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == target => return Some(index),
+            None => {},
+        }
+    }
+    None
+}
+
+fn quote_config_string(value: &str) -> String {
+    let mut quoted = "\"".to_string();
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"# Settings for the nightly import.
+name = "Import \"nightly\""   ; Trailing comment.
+enabled = yes
+color = blue#2
+
+[paths]
+source = 'C:\Data\Incoming'
+backup.folder = "D:\\Backups"
+backup.keep = 1_000
+skip = ["tmp", 'old', [1, 2.5], ]
+started = 2021-06-14
+title = The Big Import # not part of the title
+"#;
+
+    #[test]
+    fn test_parse_config() {
+        let config = ConfigDocument::parse_r(SAMPLE).unwrap();
+        assert_eq!("Import \"nightly\"", config.get_str_r("name").unwrap());
+        assert!(config.get_bool_r("enabled").unwrap());
+        assert_eq!("blue#2", config.get_str_r("color").unwrap());
+        assert_eq!(r"C:\Data\Incoming", config.get_str_r("paths.source").unwrap());
+        assert_eq!(r"D:\Backups", config.get_str_r("paths.backup.folder").unwrap());
+        assert_eq!(1000, config.get_usize_r("paths.backup.keep").unwrap());
+        assert_eq!(1000.0, config.get_f64_r("paths.backup.keep").unwrap());
+        assert_eq!(NaiveDate::from_ymd_opt(2021, 6, 14).unwrap(), config.get_date_r("paths.started").unwrap());
+        assert_eq!("The Big Import", config.get_str_r("paths.title").unwrap());
+        let skip = config.get_array_r("paths.skip").unwrap();
+        assert_eq!(3, skip.len());
+        assert_eq!(ConfigValue::Array(vec![ConfigValue::Integer(1), ConfigValue::Float(2.5)]), skip[2]);
+        assert!(config.get_string_array_r("paths.skip").is_err());
+        assert_eq!(vec!["paths".to_string()], config.sections());
+        assert_eq!(vec!["folder", "keep"], config.section_map("paths.backup").keys().collect::<Vec<_>>());
+
+        assert!(config.get_r("missing").is_err());
+        assert_eq!("Config: \"name\" is string (\"Import \\\"nightly\\\"\") rather than a whole number.", config.get_i64_r("name").unwrap_err());
+        // Nothing was changed, so the text comes back exactly as it was.
+        assert_eq!(SAMPLE, config.to_text());
+    }
+
+    #[test]
+    fn test_edit_config() {
+        let mut config = ConfigDocument::parse_r(SAMPLE).unwrap();
+        config.set_r("enabled", false).unwrap();
+        config.set_r("paths.backup.keep", 5).unwrap();
+        config.set_r("paths.note", "Line one\nLine two").unwrap();
+        config.set_r("limits.max_size", 2.0).unwrap();
+        config.set_r("version", 3).unwrap();
+        assert!(config.remove("color"));
+        assert!(!config.remove("color"));
+        assert!(config.set_r("paths.backup", "x").is_err());
+
+        let text = config.to_text();
+        assert!(text.contains("enabled = false\nversion = 3\n\n[paths]"));
+        assert!(text.contains("name = \"Import \\\"nightly\\\"\"   ; Trailing comment."));
+        assert!(text.contains("backup.keep = 5\n"));
+        assert!(text.contains("title = The Big Import # not part of the title\nnote = \"Line one\\nLine two\"\n\n[limits]\nmax_size = 2.0\n"));
+        let reread = ConfigDocument::parse_r(&text).unwrap();
+        assert_eq!("Line one\nLine two", reread.get_str_r("paths.note").unwrap());
+        assert_eq!(2.0, reread.get_f64_r("limits.max_size").unwrap());
+
+        // Windows line endings are kept, both for unchanged lines and new ones.
+        let crlf = SAMPLE.replace('\n', "\r\n");
+        let mut config = ConfigDocument::parse_r(&crlf).unwrap();
+        assert_eq!(crlf, config.to_text());
+        config.set_r("added.key", 1).unwrap();
+        assert!(config.to_text().ends_with("\r\n\r\n[added]\r\nkey = 1\r\n"));
+        assert!(!config.to_text().replace("\r\n", "").contains('\n'));
+
+        assert_eq!(ConfigDocument::new(), ConfigDocument::default());
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let text = "a = 1\na = 2\n[b\nc = \"unclosed\nd = [1, 2\ne = \"x\" y\nf\n[[g]]\nh.i = \"\\q\"\na.b = 3";
+        let errors = ConfigDocument::parse_r(text).unwrap_err();
+        let expected = vec![
+            "Line 2: The key \"a\" is already defined.",
+            "Line 3, column 3: Expected \"]\" to end the section name.",
+            "Line 4, column 5: The string has no closing quote.",
+            "Line 5, column 5: The array has no closing \"]\".",
+            "Line 6, column 9: Unexpected text after the value. Use quotes if it's part of the value.",
+            "Line 7, column 1: Expected a section like \"[name]\" or a value like \"name = value\".",
+            "Line 8, column 1: Arrays of tables aren't supported.",
+            "Line 9, column 8: Unknown escape sequence \"\\q\".",
+            "Line 10: The key \"a.b\" conflicts with \"a\".",
+        ];
+        assert_eq!(expected, errors.lines().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,758 @@
+// A small in-memory table stored by column, for filtering, sorting, grouping and joining tabular
+// data such as a TSV file read with tab::DelimitedReader and schema::TableSchema, instead of
+// writing the same loops over rows each time. Every column is typed and any cell may be missing.
+// Operations return a new DataFrame rather than changing the one they're called on.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+use chrono::NaiveDate;
+
+use crate::date_time::naive_date_to_sortable_format;
+use crate::group::Grouper;
+use crate::schema::{CellError, CellType, CellValue, DynamicRow, TableSchema};
+use crate::tab::DelimitedRecord;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    Usize,
+    F64,
+    String,
+    Date,
+    Bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Column {
+    Usize(Vec<Option<usize>>),
+    F64(Vec<Option<f64>>),
+    String(Vec<Option<String>>),
+    Date(Vec<Option<NaiveDate>>),
+    Bool(Vec<Option<bool>>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FrameValue {
+    Null,
+    Usize(usize),
+    F64(f64),
+    String(String),
+    Date(NaiveDate),
+    Bool(bool),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DataFrame {
+    names: Vec<String>,
+    columns: Vec<Column>,
+}
+
+// A view of one row, mainly for the predicate passed to DataFrame::filter().
+#[derive(Clone, Copy)]
+pub struct FrameRow<'a> {
+    frame: &'a DataFrame,
+    index: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AggregateFunction {
+    // The number of cells in the group that aren't missing.
+    Count,
+    Sum,
+    Min,
+    Max,
+    Mean,
+    Median,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Aggregate {
+    pub column: String,
+    pub function: AggregateFunction,
+    // The name of the column in the result.
+    pub name: String,
+}
+
+impl Column {
+    pub fn new(column_type: ColumnType) -> Self {
+        match column_type {
+            ColumnType::Usize => Column::Usize(vec![]),
+            ColumnType::F64 => Column::F64(vec![]),
+            ColumnType::String => Column::String(vec![]),
+            ColumnType::Date => Column::Date(vec![]),
+            ColumnType::Bool => Column::Bool(vec![]),
+        }
+    }
+
+    pub fn column_type(&self) -> ColumnType {
+        match self {
+            Column::Usize(_) => ColumnType::Usize,
+            Column::F64(_) => ColumnType::F64,
+            Column::String(_) => ColumnType::String,
+            Column::Date(_) => ColumnType::Date,
+            Column::Bool(_) => ColumnType::Bool,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Column::Usize(values) => values.len(),
+            Column::F64(values) => values.len(),
+            Column::String(values) => values.len(),
+            Column::Date(values) => values.len(),
+            Column::Bool(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn value(&self, index: usize) -> FrameValue {
+        let value = match self {
+            Column::Usize(values) => values[index].map(FrameValue::Usize),
+            Column::F64(values) => values[index].map(FrameValue::F64),
+            Column::String(values) => values[index].clone().map(FrameValue::String),
+            Column::Date(values) => values[index].map(FrameValue::Date),
+            Column::Bool(values) => values[index].map(FrameValue::Bool),
+        };
+        value.unwrap_or(FrameValue::Null)
+    }
+
+    pub fn push_r(&mut self, value: FrameValue) -> Result<(), String> {
+        // A usize may go in an F64 column, but otherwise the value has to match the column type.
+        match (self, value) {
+            (Column::Usize(values), FrameValue::Null) => values.push(None),
+            (Column::F64(values), FrameValue::Null) => values.push(None),
+            (Column::String(values), FrameValue::Null) => values.push(None),
+            (Column::Date(values), FrameValue::Null) => values.push(None),
+            (Column::Bool(values), FrameValue::Null) => values.push(None),
+            (Column::Usize(values), FrameValue::Usize(value)) => values.push(Some(value)),
+            (Column::F64(values), FrameValue::F64(value)) => values.push(Some(value)),
+            (Column::F64(values), FrameValue::Usize(value)) => values.push(Some(value as f64)),
+            (Column::String(values), FrameValue::String(value)) => values.push(Some(value)),
+            (Column::Date(values), FrameValue::Date(value)) => values.push(Some(value)),
+            (Column::Bool(values), FrameValue::Bool(value)) => values.push(Some(value)),
+            (column, value) => return Err(format!("Can't add {:?} to a column of type {:?}.", value, column.column_type())),
+        }
+        Ok(())
+    }
+
+    fn take(&self, indexes: &[Option<usize>]) -> Self {
+        // The values at the given row indexes in order. None gives a missing value, as for the
+        // rows from a left join that have nothing to join to.
+        fn take_values<T: Clone>(values: &[Option<T>], indexes: &[Option<usize>]) -> Vec<Option<T>> {
+            indexes.iter().map(|index| index.and_then(|index| values[index].clone())).collect()
+        }
+        match self {
+            Column::Usize(values) => Column::Usize(take_values(values, indexes)),
+            Column::F64(values) => Column::F64(take_values(values, indexes)),
+            Column::String(values) => Column::String(take_values(values, indexes)),
+            Column::Date(values) => Column::Date(take_values(values, indexes)),
+            Column::Bool(values) => Column::Bool(take_values(values, indexes)),
+        }
+    }
+}
+
+macro_rules! column_from {
+    ($type:ty, $variant:ident) => {
+        impl From<Vec<$type>> for Column {
+            fn from(values: Vec<$type>) -> Self {
+                Column::$variant(values.into_iter().map(Some).collect())
+            }
+        }
+
+        impl From<Vec<Option<$type>>> for Column {
+            fn from(values: Vec<Option<$type>>) -> Self {
+                Column::$variant(values)
+            }
+        }
+    };
+}
+
+column_from!(usize, Usize);
+column_from!(f64, F64);
+column_from!(String, String);
+column_from!(NaiveDate, Date);
+column_from!(bool, Bool);
+
+impl From<Vec<f32>> for Column {
+    fn from(values: Vec<f32>) -> Self {
+        Column::F64(values.into_iter().map(|value| Some(value as f64)).collect())
+    }
+}
+
+impl From<Vec<&str>> for Column {
+    fn from(values: Vec<&str>) -> Self {
+        Column::String(values.into_iter().map(|value| Some(value.to_string())).collect())
+    }
+}
+
+impl FrameValue {
+    pub fn is_null(&self) -> bool {
+        *self == FrameValue::Null
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            FrameValue::Usize(value) => Some(*value as f64),
+            FrameValue::F64(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn compare(&self, other: &Self) -> Ordering {
+        // A total order so that rows can be sorted and grouped. Missing values come after
+        // everything else, and values of different types are ordered by type.
+        match (self, other) {
+            (FrameValue::Null, FrameValue::Null) => Ordering::Equal,
+            (FrameValue::Null, _) => Ordering::Greater,
+            (_, FrameValue::Null) => Ordering::Less,
+            (FrameValue::Usize(a), FrameValue::Usize(b)) => a.cmp(b),
+            (FrameValue::F64(a), FrameValue::F64(b)) => a.total_cmp(b),
+            (FrameValue::String(a), FrameValue::String(b)) => a.cmp(b),
+            (FrameValue::Date(a), FrameValue::Date(b)) => a.cmp(b),
+            (FrameValue::Bool(a), FrameValue::Bool(b)) => a.cmp(b),
+            (a, b) => a.type_order().cmp(&b.type_order()),
+        }
+    }
+
+    fn type_order(&self) -> usize {
+        match self {
+            FrameValue::Usize(_) => 0,
+            FrameValue::F64(_) => 1,
+            FrameValue::String(_) => 2,
+            FrameValue::Date(_) => 3,
+            FrameValue::Bool(_) => 4,
+            FrameValue::Null => 5,
+        }
+    }
+
+    fn from_cell_value(value: &CellValue) -> Self {
+        match value {
+            CellValue::Empty => FrameValue::Null,
+            CellValue::String(value) => FrameValue::String(value.clone()),
+            CellValue::Usize(value) => FrameValue::Usize(*value),
+            CellValue::F32(value) => FrameValue::F64(*value as f64),
+            CellValue::Date(value) => FrameValue::Date(*value),
+            CellValue::Bool(value) => FrameValue::Bool(*value),
+        }
+    }
+}
+
+impl Display for FrameValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameValue::Null => write!(f, ""),
+            FrameValue::Usize(value) => write!(f, "{}", value),
+            FrameValue::F64(value) => write!(f, "{}", value),
+            FrameValue::String(value) => write!(f, "{}", value),
+            FrameValue::Date(value) => write!(f, "{}", naive_date_to_sortable_format(value)),
+            FrameValue::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl Aggregate {
+    pub fn new(column: &str, function: AggregateFunction) -> Self {
+        // The result column is named like "Price Mean". Use struct update syntax to pick a
+        // different name.
+        Self {
+            column: column.to_string(),
+            function,
+            name: format!("{} {:?}", column, function),
+        }
+    }
+}
+
+impl DataFrame {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_columns_r(columns: Vec<(&str, Column)>) -> Result<Self, String> {
+        let mut frame = Self::new();
+        for (name, column) in columns {
+            frame.add_column_r(name, column)?;
+        }
+        Ok(frame)
+    }
+
+    pub fn from_rows_r(schema: &TableSchema, rows: &[DynamicRow]) -> Result<Self, String> {
+        // One column for each column in the schema, typed to match. Price and F32 columns become
+        // F64 columns.
+        let mut frame = Self::new();
+        for spec in schema.columns.iter() {
+            let column_type = match spec.cell_type {
+                CellType::String => ColumnType::String,
+                CellType::Usize => ColumnType::Usize,
+                CellType::F32 | CellType::Price => ColumnType::F64,
+                CellType::Date => ColumnType::Date,
+                CellType::Bool => ColumnType::Bool,
+            };
+            let mut column = Column::new(column_type);
+            for row in rows.iter() {
+                let value = row.get(&spec.name).map_or(FrameValue::Null, FrameValue::from_cell_value);
+                column.push_r(value)?;
+            }
+            frame.add_column_r(&spec.name, column)?;
+        }
+        Ok(frame)
+    }
+
+    pub fn from_records_r<'a, I>(schema: &TableSchema, header: Option<&[String]>, records: I) -> Result<(Self, Vec<CellError>), String>
+        where I: IntoIterator<Item = &'a DelimitedRecord>
+    {
+        // As with TableSchema::map_records(), rows with bad cells are left out and their errors
+        // returned alongside the frame.
+        let (rows, errors) = schema.map_records(header, records)?;
+        Ok((Self::from_rows_r(schema, &rows)?, errors))
+    }
+
+    pub fn add_column_r(&mut self, name: &str, column: Column) -> Result<(), String> {
+        if self.names.iter().any(|existing| existing == name) {
+            return Err(format!("DataFrame: there's already a column named \"{}\".", name));
+        }
+        if !self.columns.is_empty() && column.len() != self.len() {
+            return Err(format!("DataFrame: column \"{}\" has {} values but the other columns have {}.", name, column.len(), self.len()));
+        }
+        self.names.push(name.to_string());
+        self.columns.push(column);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        // The number of rows.
+        self.columns.first().map_or(0, |column| column.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn column_names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn column_index_r(&self, name: &str) -> Result<usize, String> {
+        self.names.iter().position(|existing| existing == name)
+            .ok_or_else(|| format!("DataFrame: no column named \"{}\".", name))
+    }
+
+    pub fn column_r(&self, name: &str) -> Result<&Column, String> {
+        Ok(&self.columns[self.column_index_r(name)?])
+    }
+
+    pub fn value_r(&self, row_index: usize, name: &str) -> Result<FrameValue, String> {
+        if row_index >= self.len() {
+            return Err(format!("DataFrame: row {} is past the end of the {} rows.", row_index, self.len()));
+        }
+        Ok(self.column_r(name)?.value(row_index))
+    }
+
+    pub fn row(&self, index: usize) -> FrameRow<'_> {
+        FrameRow { frame: self, index }
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = FrameRow<'_>> {
+        (0..self.len()).map(move |index| self.row(index))
+    }
+
+    pub fn filter<F>(&self, mut predicate: F) -> Self
+        where F: FnMut(&FrameRow) -> bool
+    {
+        let indexes = self.rows().filter(|row| predicate(row)).map(|row| Some(row.index)).collect::<Vec<_>>();
+        self.take(&indexes)
+    }
+
+    pub fn select_r(&self, names: &[&str]) -> Result<Self, String> {
+        // Only the given columns, in the given order.
+        let mut frame = Self::new();
+        for name in names.iter() {
+            frame.add_column_r(name, self.column_r(name)?.clone())?;
+        }
+        Ok(frame)
+    }
+
+    pub fn sort_r(&self, keys: &[(&str, SortOrder)]) -> Result<Self, String> {
+        // A stable sort on one or more columns, with missing values last either way.
+        let key_columns = keys.iter()
+            .map(|(name, order)| Ok((self.column_r(name)?, *order)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let mut indexes = (0..self.len()).collect::<Vec<_>>();
+        indexes.sort_by(|a, b| {
+            key_columns.iter().fold(Ordering::Equal, |ordering, (column, order)| {
+                ordering.then_with(|| {
+                    let (value_a, value_b) = (column.value(*a), column.value(*b));
+                    match (order, value_a.is_null() || value_b.is_null()) {
+                        (SortOrder::Descending, false) => value_a.compare(&value_b).reverse(),
+                        _ => value_a.compare(&value_b),
+                    }
+                })
+            })
+        });
+        Ok(self.take(&indexes.into_iter().map(Some).collect::<Vec<_>>()))
+    }
+
+    pub fn group_by_r(&self, key_names: &[&str], aggregates: &[Aggregate]) -> Result<Self, String> {
+        // One row for each distinct combination of the key columns, sorted by those columns, with
+        // one column for each aggregate. Missing values are skipped by the aggregates, and a group
+        // with no values gets a missing result.
+        let keys = key_names.iter().map(|name| (*name, SortOrder::Ascending)).collect::<Vec<_>>();
+        let sorted = self.sort_r(&keys)?;
+        let key_indexes = key_names.iter().map(|name| sorted.column_index_r(name)).collect::<Result<Vec<_>, String>>()?;
+        let mut groups: Vec<Vec<usize>> = vec![];
+        for index in 0..sorted.len() {
+            let same_group = groups.last().is_some_and(|group| {
+                key_indexes.iter().all(|key_index| {
+                    let column = &sorted.columns[*key_index];
+                    column.value(group[0]).compare(&column.value(index)) == Ordering::Equal
+                })
+            });
+            if same_group {
+                groups.last_mut().unwrap().push(index);
+            } else {
+                groups.push(vec![index]);
+            }
+        }
+
+        let first_indexes = groups.iter().map(|group| Some(group[0])).collect::<Vec<_>>();
+        let mut frame = Self::new();
+        for (name, key_index) in key_names.iter().zip(key_indexes.iter()) {
+            frame.add_column_r(name, sorted.columns[*key_index].take(&first_indexes))?;
+        }
+        for aggregate in aggregates.iter() {
+            let source = sorted.column_r(&aggregate.column)?;
+            let mut column = Column::new(aggregate_column_type_r(source.column_type(), aggregate.function)?);
+            for group in groups.iter() {
+                column.push_r(aggregate_values(source, group, aggregate.function))?;
+            }
+            frame.add_column_r(&aggregate.name, column)?;
+        }
+        Ok(frame)
+    }
+
+    pub fn group_counts_r(&self, name: &str) -> Result<Grouper<String>, String> {
+        // The number of rows with each value of the column, with missing values counted as "".
+        let column = self.column_r(name)?;
+        let mut grouper = Grouper::new(name);
+        for index in 0..self.len() {
+            grouper.record_entry(&column.value(index).to_string());
+        }
+        Ok(grouper)
+    }
+
+    pub fn inner_join_r(&self, other: &DataFrame, left_keys: &[&str], right_keys: &[&str]) -> Result<Self, String> {
+        self.join_r(other, left_keys, right_keys, false)
+    }
+
+    pub fn left_join_r(&self, other: &DataFrame, left_keys: &[&str], right_keys: &[&str]) -> Result<Self, String> {
+        // Every row of this frame is kept. Those with no match get missing values in the columns
+        // from the other frame.
+        self.join_r(other, left_keys, right_keys, true)
+    }
+
+    fn join_r(&self, other: &DataFrame, left_keys: &[&str], right_keys: &[&str], keep_unmatched: bool) -> Result<Self, String> {
+        // The result has all of the columns from this frame followed by those from the other
+        // frame except its key columns. A name that's already used gets "_right" added, then a
+        // number if that's taken too. As in SQL, a row with a missing key never matches anything.
+        if left_keys.is_empty() || left_keys.len() != right_keys.len() {
+            return Err("DataFrame: a join needs the same number of key columns on each side.".to_string());
+        }
+        let left_columns = left_keys.iter().map(|name| self.column_r(name)).collect::<Result<Vec<_>, String>>()?;
+        let right_columns = right_keys.iter().map(|name| other.column_r(name)).collect::<Result<Vec<_>, String>>()?;
+        for ((left_name, left), (right_name, right)) in left_keys.iter().zip(left_columns.iter()).zip(right_keys.iter().zip(right_columns.iter())) {
+            if left.column_type() != right.column_type() {
+                return Err(format!("DataFrame: can't join \"{}\" ({:?}) to \"{}\" ({:?}).", left_name, left.column_type(), right_name, right.column_type()));
+            }
+        }
+
+        let mut right_rows: HashMap<String, Vec<usize>> = HashMap::new();
+        for index in 0..other.len() {
+            if let Some(key) = join_key(&right_columns, index) {
+                right_rows.entry(key).or_default().push(index);
+            }
+        }
+        let mut left_indexes = vec![];
+        let mut right_indexes = vec![];
+        for index in 0..self.len() {
+            match join_key(&left_columns, index).and_then(|key| right_rows.get(&key)) {
+                Some(matches) => {
+                    for right_index in matches.iter() {
+                        left_indexes.push(Some(index));
+                        right_indexes.push(Some(*right_index));
+                    }
+                },
+                None if keep_unmatched => {
+                    left_indexes.push(Some(index));
+                    right_indexes.push(None);
+                },
+                None => {},
+            }
+        }
+
+        let mut frame = self.take(&left_indexes);
+        for (name, column) in other.names.iter().zip(other.columns.iter()) {
+            if !right_keys.contains(&name.as_str()) {
+                let mut unique_name = name.clone();
+                let mut number = 1;
+                while frame.names.contains(&unique_name) {
+                    unique_name = if number == 1 { format!("{}_right", name) } else { format!("{}_right_{}", name, number) };
+                    number += 1;
+                }
+                let name = unique_name;
+                frame.add_column_r(&name, column.take(&right_indexes))?;
+            }
+        }
+        Ok(frame)
+    }
+
+    fn take(&self, indexes: &[Option<usize>]) -> Self {
+        Self {
+            names: self.names.clone(),
+            columns: self.columns.iter().map(|column| column.take(indexes)).collect(),
+        }
+    }
+}
+
+impl <'a> FrameRow<'a> {
+    // The getters return None both for a missing value and for a column name that doesn't exist,
+    // so that a filter predicate can be a simple expression.
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn get(&self, name: &str) -> FrameValue {
+        self.column(name).map_or(FrameValue::Null, |column| column.value(self.index))
+    }
+
+    pub fn usize(&self, name: &str) -> Option<usize> {
+        match self.column(name)? {
+            Column::Usize(values) => values[self.index],
+            _ => None,
+        }
+    }
+
+    pub fn f64(&self, name: &str) -> Option<f64> {
+        // Works for usize columns too.
+        self.get(name).as_f64()
+    }
+
+    pub fn str(&self, name: &str) -> Option<&'a str> {
+        match self.column(name)? {
+            Column::String(values) => values[self.index].as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn date(&self, name: &str) -> Option<NaiveDate> {
+        match self.column(name)? {
+            Column::Date(values) => values[self.index],
+            _ => None,
+        }
+    }
+
+    pub fn bool(&self, name: &str) -> Option<bool> {
+        match self.column(name)? {
+            Column::Bool(values) => values[self.index],
+            _ => None,
+        }
+    }
+
+    fn column(&self, name: &str) -> Option<&'a Column> {
+        self.frame.column_r(name).ok()
+    }
+}
+
+fn join_key(columns: &[&Column], index: usize) -> Option<String> {
+    // The key values for a row combined into one string for hashing, or None if any are missing.
+    let mut parts = vec![];
+    for column in columns.iter() {
+        let value = column.value(index);
+        if value.is_null() {
+            return None;
+        }
+        parts.push(format!("{:?}", value));
+    }
+    Some(parts.join("\u{1}"))
+}
+
+fn aggregate_column_type_r(column_type: ColumnType, function: AggregateFunction) -> Result<ColumnType, String> {
+    let numeric = matches!(column_type, ColumnType::Usize | ColumnType::F64);
+    match function {
+        AggregateFunction::Count => Ok(ColumnType::Usize),
+        AggregateFunction::Min | AggregateFunction::Max => Ok(column_type),
+        AggregateFunction::Sum if numeric => Ok(column_type),
+        AggregateFunction::Mean | AggregateFunction::Median if numeric => Ok(ColumnType::F64),
+        _ => Err(format!("DataFrame: can't take the {:?} of a column of type {:?}.", function, column_type)),
+    }
+}
+
+fn aggregate_values(column: &Column, indexes: &[usize], function: AggregateFunction) -> FrameValue {
+    let values = indexes.iter().map(|index| column.value(*index)).filter(|value| !value.is_null()).collect::<Vec<_>>();
+    if function == AggregateFunction::Count {
+        return FrameValue::Usize(values.len());
+    }
+    if values.is_empty() {
+        return FrameValue::Null;
+    }
+    if let Column::Usize(_) = column {
+        let mut numbers = values.iter().filter_map(|value| match value { FrameValue::Usize(value) => Some(*value), _ => None }).collect::<Vec<_>>();
+        // The mean and median are worked out here rather than with StatsUsize, which uses f32 and
+        // so loses precision above 2^24.
+        let count = numbers.len();
+        return match function {
+            AggregateFunction::Sum => FrameValue::Usize(numbers.iter().sum()),
+            AggregateFunction::Min => FrameValue::Usize(*numbers.iter().min().unwrap()),
+            AggregateFunction::Max => FrameValue::Usize(*numbers.iter().max().unwrap()),
+            AggregateFunction::Mean => FrameValue::F64(numbers.iter().map(|number| *number as f64).sum::<f64>() / count as f64),
+            AggregateFunction::Median => {
+                numbers.sort_unstable();
+                let median = if count % 2 == 0 { (numbers[count / 2 - 1] as f64 + numbers[count / 2] as f64) / 2.0 } else { numbers[count / 2] as f64 };
+                FrameValue::F64(median)
+            },
+            AggregateFunction::Count => unreachable!(),
+        };
+    }
+    match function {
+        AggregateFunction::Min => values.into_iter().min_by(|a, b| a.compare(b)).unwrap(),
+        AggregateFunction::Max => values.into_iter().max_by(|a, b| a.compare(b)).unwrap(),
+        _ => {
+            // Only F64 columns get here since aggregate_column_type_r() rejects the others.
+            let mut numbers = values.iter().filter_map(|value| value.as_f64()).collect::<Vec<_>>();
+            let sum = numbers.iter().sum::<f64>();
+            match function {
+                AggregateFunction::Sum => FrameValue::F64(sum),
+                AggregateFunction::Mean => FrameValue::F64(sum / numbers.len() as f64),
+                _ => {
+                    numbers.sort_by(|a, b| a.total_cmp(b));
+                    let count = numbers.len();
+                    let median = if count % 2 == 0 { (numbers[count / 2 - 1] + numbers[count / 2]) / 2.0 } else { numbers[count / 2] };
+                    FrameValue::F64(median)
+                },
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use crate::schema::ColumnSpec;
+    use crate::tab::{DelimitedFormat, DelimitedReader};
+
+    fn sales_frame() -> DataFrame {
+        DataFrame::from_columns_r(vec![
+            ("Region", Column::from(vec!["West", "East", "West", "East", "North"])),
+            ("Units", Column::from(vec![Some(3usize), Some(5), None, Some(1), Some(4)])),
+            ("Price", Column::from(vec![2.5, 1.0, 4.0, 3.0, 2.0])),
+        ]).unwrap()
+    }
+
+    #[test]
+    fn test_filter_sort_select() {
+        let mut frame = sales_frame();
+        assert_eq!(5, frame.len());
+        assert!(frame.add_column_r("Short", Column::from(vec![1usize])).is_err());
+
+        let west = frame.filter(|row| row.str("Region") == Some("West"));
+        assert_eq!(2, west.len());
+        let cheap = frame.filter(|row| row.f64("Price").is_some_and(|price| price < 2.5));
+        assert_eq!(vec![FrameValue::String("East".to_string()), FrameValue::String("North".to_string())],
+            cheap.rows().map(|row| row.get("Region")).collect::<Vec<_>>());
+
+        // The missing value stays last whichever way the column is sorted.
+        let sorted = frame.sort_r(&[("Units", SortOrder::Descending)]).unwrap();
+        assert_eq!(vec![Some(5), Some(4), Some(3), Some(1), None], sorted.rows().map(|row| row.usize("Units")).collect::<Vec<_>>());
+        let sorted = frame.sort_r(&[("Region", SortOrder::Ascending), ("Price", SortOrder::Descending)]).unwrap();
+        assert_eq!(vec![3.0, 1.0, 2.0, 4.0, 2.5], sorted.rows().map(|row| row.f64("Price").unwrap()).collect::<Vec<_>>());
+
+        let selected = frame.select_r(&["Price", "Region"]).unwrap();
+        assert_eq!(str_to_string_vector(&["Price", "Region"]), selected.column_names());
+        assert!(frame.select_r(&["Missing"]).is_err());
+        assert!(frame.sort_r(&[("Missing", SortOrder::Ascending)]).is_err());
+    }
+
+    #[test]
+    fn test_group_by() {
+        let frame = sales_frame();
+        let grouped = frame.group_by_r(&["Region"], &[
+            Aggregate::new("Units", AggregateFunction::Count),
+            Aggregate::new("Units", AggregateFunction::Sum),
+            Aggregate::new("Price", AggregateFunction::Mean),
+            Aggregate { name: "Top Price".to_string(), ..Aggregate::new("Price", AggregateFunction::Max) },
+        ]).unwrap();
+        assert_eq!(str_to_string_vector(&["Region", "Units Count", "Units Sum", "Price Mean", "Top Price"]), grouped.column_names());
+        assert_eq!(3, grouped.len());
+        let west = grouped.row(2);
+        assert_eq!(Some("West"), west.str("Region"));
+        assert_eq!(Some(1), west.usize("Units Count"));
+        assert_eq!(Some(3), west.usize("Units Sum"));
+        assert_eq!(Some(3.25), west.f64("Price Mean"));
+        assert_eq!(Some(4.0), west.f64("Top Price"));
+        assert!(frame.group_by_r(&["Region"], &[Aggregate::new("Region", AggregateFunction::Sum)]).is_err());
+
+        // Large whole numbers keep their precision, which they wouldn't in an f32.
+        let big = DataFrame::from_columns_r(vec![
+            ("Key", Column::from(vec!["a", "a", "a"])),
+            ("Bytes", Column::from(vec![Some(100_000_001usize), Some(100_000_002), Some(100_000_004)])),
+        ]).unwrap();
+        let grouped = big.group_by_r(&["Key"], &[Aggregate::new("Bytes", AggregateFunction::Mean), Aggregate::new("Bytes", AggregateFunction::Median)]).unwrap();
+        assert!((grouped.row(0).f64("Bytes Mean").unwrap() - 300_000_007.0 / 3.0).abs() < 1e-6);
+        assert_eq!(Some(100_000_002.0), grouped.row(0).f64("Bytes Median"));
+
+        let grouper = frame.group_counts_r("Region").unwrap();
+        assert_eq!(3, grouper.entry_count());
+        assert_eq!(Some(2), grouper.max_count());
+    }
+
+    #[test]
+    fn test_join() {
+        let frame = sales_frame();
+        let managers = DataFrame::from_columns_r(vec![
+            ("Name", Column::from(vec!["West", "East", "East"])),
+            ("Manager", Column::from(vec!["Ann", "Bob", "Cy"])),
+            ("Price", Column::from(vec![Some(1.0), None, None])),
+        ]).unwrap();
+        let inner = frame.inner_join_r(&managers, &["Region"], &["Name"]).unwrap();
+        assert_eq!(str_to_string_vector(&["Region", "Units", "Price", "Manager", "Price_right"]), inner.column_names());
+        // Each East row matches two managers and North matches none.
+        assert_eq!(6, inner.len());
+        let left = frame.left_join_r(&managers, &["Region"], &["Name"]).unwrap();
+        assert_eq!(7, left.len());
+        let north = left.filter(|row| row.str("Region") == Some("North"));
+        assert_eq!(FrameValue::Null, north.value_r(0, "Manager").unwrap());
+        assert!(frame.inner_join_r(&managers, &["Units"], &["Name"]).is_err());
+
+        // A clashing name gets a number when the name with "_right" is taken too.
+        let joined = inner.inner_join_r(&managers, &["Region", "Manager"], &["Name", "Manager"]).unwrap();
+        assert_eq!(str_to_string_vector(&["Region", "Units", "Price", "Manager", "Price_right", "Price_right_2"]), joined.column_names());
+    }
+
+    #[test]
+    fn test_from_records() {
+        let text = "Item\tCount\tSold\nA\t2\t03/15/21\nB\tlots\t03/16/21\nC\t\t03/17/21";
+        let mut reader = DelimitedReader::from_text(text, DelimitedFormat::tsv()).unwrap();
+        let records = reader.by_ref().collect::<Result<Vec<_>, String>>().unwrap();
+        let schema = TableSchema::new(vec![
+            ColumnSpec::new("Item", CellType::String),
+            ColumnSpec { optional: true, ..ColumnSpec::new("Count", CellType::Usize) },
+            ColumnSpec::new("Sold", CellType::Date),
+        ]);
+        let (frame, errors) = DataFrame::from_records_r(&schema, reader.header(), &records).unwrap();
+        assert_eq!(2, frame.len());
+        assert_eq!(1, errors.len());
+        assert_eq!(ColumnType::Date, frame.column_r("Sold").unwrap().column_type());
+        assert_eq!(None, frame.row(1).usize("Count"));
+        assert_eq!(NaiveDate::from_ymd_opt(2021, 3, 17), frame.row(1).date("Sold"));
+    }
+}
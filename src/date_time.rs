@@ -79,6 +79,14 @@ pub fn naive_date_from_multiple_formats(value: &str) -> Result<NaiveDate, String
     }
 }
 
+pub fn naive_date_from_formats(value: &str, formats: &[&str]) -> Result<NaiveDate, String> {
+    // Try each chrono format string in order, such as "%m/%d/%y" for "01/03/22".
+    formats.iter()
+        .find_map(|format| NaiveDate::parse_from_str(value.trim(), format).ok())
+        .ok_or_else(|| format!("Error trying to parse \"{}\" as a date using the formats {}.", value,
+            formats.iter().map(|format| format!("\"{}\"", format)).collect::<Vec<_>>().join(", ")))
+}
+
 // Like "Jan 3, 2022".
 pub fn naive_date_to_mon_format(date: &NaiveDate) -> String {
     date.format(FORMAT_DATE_MON).to_string()
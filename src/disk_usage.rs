@@ -0,0 +1,213 @@
+// A du-style scan of a folder that builds a Tree of its subfolders, each with the total size and
+// number of files in it and everything below it, for finding out what's filling a disk.
+
+use std::cell::RefCell;
+use std::cmp::{Ordering, Reverse};
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::*;
+use crate::file::{path_exists_r, path_name};
+use crate::format::{format_bytes, format_count, format_indent_tab};
+use crate::tree::{Tree, TreeNode};
+
+#[derive(Clone, Debug)]
+pub struct FolderUsage {
+    pub path: PathBuf,
+    // Just the files directly in this folder.
+    pub own_size: u64,
+    pub own_file_count: usize,
+    // This folder and all of its subfolders.
+    pub size: u64,
+    pub file_count: usize,
+}
+
+pub struct DiskUsage {
+    pub tree: Tree<FolderUsage>,
+    pub root: FolderUsage,
+    // Folders that couldn't be read, such as those without permission. The sizes above them are
+    // missing whatever was inside.
+    pub errors: Vec<String>,
+}
+
+impl FolderUsage {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            own_size: 0,
+            own_file_count: 0,
+            size: 0,
+            file_count: 0,
+        }
+    }
+}
+
+// A folder's path identifies it in the tree, so that's all that's compared.
+impl PartialEq for FolderUsage {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Eq for FolderUsage {}
+
+impl PartialOrd for FolderUsage {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FolderUsage {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.path.cmp(&other.path)
+    }
+}
+
+impl Display for FolderUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} in {} files", path_name(&self.path), format_bytes(self.size), format_count(self.file_count))
+    }
+}
+
+impl DiskUsage {
+    pub fn scan_r<P>(path_folder: P) -> Result<Self, String>
+        where P: AsRef<Path>
+    {
+        path_exists_r(&path_folder)?;
+        let mut folders = vec![];
+        let mut pairs = vec![];
+        let mut errors = vec![];
+        let root = scan_folder(path_folder.as_ref().to_path_buf(), &mut folders, &mut pairs, &mut errors);
+        let tree = Tree::create_with_items(folders, pairs, true);
+        Ok(Self { tree, root, errors })
+    }
+
+    pub fn largest_folders(&self, count: usize) -> Vec<FolderUsage> {
+        // The folders holding the most data in files directly inside them, which unlike the
+        // cumulative sizes doesn't just list the ancestors of one big folder.
+        let mut folders = self.tree.node_map.keys().cloned().collect::<Vec<_>>();
+        folders.sort_by_key(|folder| (Reverse(folder.own_size), folder.path.clone()));
+        folders.truncate(count);
+        folders
+    }
+
+    pub fn report_by_size(&self, max_count: usize) {
+        for line in self.report_by_size_lines(max_count) {
+            println!("{}", line);
+        }
+    }
+
+    pub fn report_by_size_lines(&self, max_count: usize) -> Vec<String> {
+        // Show the biggest subtrees first at each level, going only as deep as will fit in about
+        // max_count lines. The root folder is the tree's only top node, so it comes first.
+        let max_depth = self.tree.max_depth_for_max_count(max_count);
+        let mut lines = vec!["".to_string()];
+        if !self.errors.is_empty() {
+            lines.push(format!("{} folders couldn't be read.", format_count(self.errors.len())));
+        }
+        Self::add_report_by_size_lines(&self.tree.top_nodes, max_depth, &mut lines);
+        lines
+    }
+
+    fn add_report_by_size_lines(list: &[Rc<RefCell<TreeNode<FolderUsage>>>], max_depth: usize, lines: &mut Vec<String>) {
+        let mut list = list.to_vec();
+        list.sort_by_cached_key(|node_rc| {
+            let node = b!(node_rc);
+            (Reverse(node.item.size), node.item.path.clone())
+        });
+        for node_rc in list.iter() {
+            let node = b!(node_rc);
+            lines.push(format_indent_tab(node.depth(), &node.item.to_string()));
+            if node.depth() < max_depth {
+                Self::add_report_by_size_lines(&node.child_nodes, max_depth, lines);
+            }
+        }
+    }
+}
+
+fn scan_folder(path: PathBuf, folders: &mut Vec<FolderUsage>, pairs: &mut Vec<(FolderUsage, FolderUsage)>, errors: &mut Vec<String>) -> FolderUsage {
+    // Symbolic links aren't followed, so nothing is counted twice and a link loop can't cause
+    // endless recursion.
+    let mut usage = FolderUsage::new(path.clone());
+    let mut subfolders = vec![];
+    match fs::read_dir(&path) {
+        Ok(entries) => {
+            for entry in entries {
+                let (entry_path, metadata) = match entry.and_then(|entry| entry.metadata().map(|metadata| (entry.path(), metadata))) {
+                    Ok(path_and_metadata) => path_and_metadata,
+                    Err(err) => {
+                        errors.push(format!("\"{}\": {}", path_name(&path), err));
+                        continue;
+                    }
+                };
+                if metadata.is_dir() {
+                    subfolders.push(scan_folder(entry_path, folders, pairs, errors));
+                } else if metadata.is_file() {
+                    usage.own_size += metadata.len();
+                    usage.own_file_count += 1;
+                }
+            }
+        },
+        Err(err) => errors.push(format!("\"{}\": {}", path_name(&path), err)),
+    }
+    usage.size = usage.own_size + subfolders.iter().map(|subfolder| subfolder.size).sum::<u64>();
+    usage.file_count = usage.own_file_count + subfolders.iter().map(|subfolder| subfolder.file_count).sum::<usize>();
+    // The pairs are added once this folder's totals are known so that the copies of the items in
+    // the tree are complete.
+    for subfolder in subfolders {
+        pairs.push((usage.clone(), subfolder));
+    }
+    folders.push(usage.clone());
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_workspace::{TempWorkspace, spec_file, spec_folder};
+
+    #[test]
+    fn test_scan_r() {
+        let workspace = TempWorkspace::with_spec("test_scan_r", &[
+            spec_file("top.txt", "12345"),
+            spec_folder("Big", vec![
+                spec_file("a.bin", &"0".repeat(1_000)),
+                spec_folder("Bigger", vec![spec_file("b.bin", &"0".repeat(3_000))]),
+            ]),
+            spec_folder("Small", vec![spec_file("c.txt", "1")]),
+        ]);
+        let path_root = workspace.path();
+
+        let usage = DiskUsage::scan_r(path_root).unwrap();
+        assert!(usage.errors.is_empty());
+        assert_eq!(4_006, usage.root.size);
+        assert_eq!(4, usage.root.file_count);
+        assert_eq!(5, usage.root.own_size);
+        assert_eq!(4, usage.tree.node_count());
+        assert_eq!(3, usage.tree.height());
+
+        let big = usage.tree.node_map.keys().find(|folder| folder.path.ends_with("Big")).unwrap();
+        assert_eq!(4_000, big.size);
+        assert_eq!(2, big.file_count);
+
+        let largest = usage.largest_folders(2);
+        assert!(largest[0].path.ends_with("Bigger"));
+        assert!(largest[1].path.ends_with("Big"));
+        let lines = usage.report_by_size_lines(10);
+        assert_eq!(5, lines.len());
+        assert_eq!(usage.root.to_string(), lines[1]);
+        // The biggest folder comes first at each level, indented under its parent.
+        let depth = |line: &str| line.len() - line.trim_start_matches('\t').len();
+        assert_eq!(vec![0, 1, 2, 1], lines[1..].iter().map(|line| depth(line)).collect::<Vec<_>>());
+        assert!(lines[2].ends_with("Big: 3.9 KB in 2 files"));
+        assert!(lines[3].contains("Bigger: "));
+        assert!(lines[4].contains("Small: "));
+
+        // A folder with no subfolders is still a valid tree.
+        let usage = DiskUsage::scan_r(path_root.join("Small")).unwrap();
+        assert_eq!(1, usage.tree.node_count());
+        assert_eq!(1, usage.root.size);
+    }
+}
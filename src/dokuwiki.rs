@@ -0,0 +1,880 @@
+// DokuWiki markup, for pages like those handled by parse::replace_within_delimiters_rc() where a
+// table row looks like:
+//
+//     | [[tools:nav:attributes#Narrator|Narrator]] | [[tools:nav:attribute_values#Mark Steinberg|Mark Steinberg]] |
+//
+// A page is parsed into a list of blocks (headings, paragraphs, lists, tables, code and so on)
+// whose text is in turn parsed into inline elements such as links and bold or italic text. The
+// writer turns these back into wiki markup. It normalizes things like the spacing in table cells,
+// so to change only the links in a set of page files and leave everything else exactly as it was,
+// use rewrite_links_r() or rewrite_links_in_folder_r() instead of parsing and writing the pages.
+
+use std::path::{Path, PathBuf};
+
+use crate::*;
+use crate::file::{path_entries_r, path_name, read_file_to_string_r, write_file_atomic_r};
+use crate::parse::tokenize_delimited_rc;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WikiPage {
+    pub blocks: Vec<WikiBlock>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WikiBlock {
+    // Level 1 is the largest heading, written with six equal signs on each side.
+    Heading { level: usize, title: String },
+    // One list of inline elements for each line of the paragraph.
+    Paragraph(Vec<Vec<WikiInline>>),
+    List(Vec<WikiListItem>),
+    Table(Vec<Vec<WikiTableCell>>),
+    // A <code> or <file> block. The attributes are whatever follows the tag name, such as the
+    // language and file name in <file rust main.rs>.
+    Code { tag: String, attributes: String, text: String },
+    // Lines indented by two spaces that aren't list items, without the indent.
+    Preformatted(Vec<String>),
+    HorizontalRule,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WikiListItem {
+    // 1 for a top-level item, which is indented by two spaces.
+    pub depth: usize,
+    pub ordered: bool,
+    pub content: Vec<WikiInline>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WikiTableCell {
+    pub header: bool,
+    pub content: Vec<WikiInline>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WikiInline {
+    Text(String),
+    Bold(Vec<WikiInline>),
+    Italic(Vec<WikiInline>),
+    Underline(Vec<WikiInline>),
+    Monospace(Vec<WikiInline>),
+    Link(WikiLink),
+    // An image or other media such as {{wiki:logo.png?200}}, without the braces.
+    Media(String),
+    // Text between %% markers, which isn't parsed.
+    NoWiki(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WikiLink {
+    // For [[tools:nav:attributes#Narrator|Narrator]] the namespace is ["tools", "nav"], the page
+    // is "attributes", the anchor is "Narrator", and the label is "Narrator". An absolute link like
+    // [[:start]] has an empty first namespace part. For an external link like
+    // [[https://example.com|Example]] the namespace is empty and the page is the whole URL.
+    pub namespace: Vec<String>,
+    pub page: String,
+    pub anchor: Option<String>,
+    pub label: Option<String>,
+}
+
+impl WikiPage {
+    pub fn parse_r(text: &str) -> Result<Self, String> {
+        let text = text.trim_start_matches('\u{feff}');
+        let lines = text.lines().collect::<Vec<_>>();
+        let mut blocks: Vec<WikiBlock> = vec![];
+        let mut line_index = 0;
+        while line_index < lines.len() {
+            let line = lines[line_index];
+            let line_number = line_index + 1;
+            let line_context = format!("Line {}:", line_number);
+            let trimmed = line.trim();
+            line_index += 1;
+            if trimmed.is_empty() {
+                continue;
+            }
+            if let Some((tag, attributes, rest)) = parse_code_start(trimmed) {
+                let close = format!("</{}>", tag);
+                let mut code_lines = vec![];
+                let mut remainder = rest;
+                loop {
+                    if let Some(pos) = remainder.find(&close) {
+                        if !remainder[..pos].is_empty() {
+                            code_lines.push(remainder[..pos].to_string());
+                        }
+                        break;
+                    }
+                    if !remainder.is_empty() || !code_lines.is_empty() {
+                        code_lines.push(remainder.to_string());
+                    }
+                    if line_index >= lines.len() {
+                        return Err(format!("{} The <{}> block has no closing {}.", line_context, tag, close));
+                    }
+                    remainder = lines[line_index];
+                    line_index += 1;
+                }
+                blocks.push(WikiBlock::Code { tag, attributes, text: code_lines.join("\n") });
+                continue;
+            }
+            if let Some((level, title)) = parse_heading(trimmed) {
+                blocks.push(WikiBlock::Heading { level, title });
+                continue;
+            }
+            if trimmed.len() >= 4 && trimmed.chars().all(|c| c == '-') {
+                blocks.push(WikiBlock::HorizontalRule);
+                continue;
+            }
+            if let Some(item) = parse_list_item(line) {
+                let item = WikiListItem { depth: item.0, ordered: item.1, content: err_context(parse_inline_r(item.2), &line_context)? };
+                match blocks.last_mut() {
+                    Some(WikiBlock::List(items)) if !previous_line_blank(&lines, line_index - 1) => items.push(item),
+                    _ => blocks.push(WikiBlock::List(vec![item])),
+                }
+                continue;
+            }
+            if trimmed.starts_with(['|', '^']) {
+                let row = err_context(parse_table_row_r(trimmed), &line_context)?;
+                match blocks.last_mut() {
+                    Some(WikiBlock::Table(rows)) if !previous_line_blank(&lines, line_index - 1) => rows.push(row),
+                    _ => blocks.push(WikiBlock::Table(vec![row])),
+                }
+                continue;
+            }
+            if line.starts_with("  ") || line.starts_with('\t') {
+                let preformatted = line.strip_prefix("  ").unwrap_or(&line[1..]).to_string();
+                match blocks.last_mut() {
+                    Some(WikiBlock::Preformatted(preformatted_lines)) if !previous_line_blank(&lines, line_index - 1) => preformatted_lines.push(preformatted),
+                    _ => blocks.push(WikiBlock::Preformatted(vec![preformatted])),
+                }
+                continue;
+            }
+            let content = err_context(parse_inline_r(trimmed), &line_context)?;
+            match blocks.last_mut() {
+                Some(WikiBlock::Paragraph(paragraph_lines)) if !previous_line_blank(&lines, line_index - 1) => paragraph_lines.push(content),
+                _ => blocks.push(WikiBlock::Paragraph(vec![content])),
+            }
+        }
+        Ok(Self { blocks })
+    }
+
+    pub fn read_r<P>(path: P) -> Result<Self, String>
+        where P: AsRef<Path>
+    {
+        let text = read_file_to_string_r(&path)?;
+        err_context(Self::parse_r(&text), &format!("WikiPage::read_r: \"{}\":", path_name(&path)))
+    }
+
+    pub fn write_r<P>(&self, path: P) -> Result<(), String>
+        where P: AsRef<Path>
+    {
+        write_file_atomic_r(path, &self.to_markup())
+    }
+
+    pub fn to_markup(&self) -> String {
+        // Blocks are separated by blank lines, which is also what keeps two lists or tables in a
+        // row from running together.
+        let mut text = self.blocks.iter().map(|block| block.to_markup()).collect::<Vec<_>>().join("\n\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn headings(&self) -> Vec<(usize, &str)> {
+        self.blocks.iter().filter_map(|block| match block {
+            WikiBlock::Heading { level, title } => Some((*level, title.as_str())),
+            _ => None,
+        }).collect()
+    }
+
+    pub fn links(&self) -> Vec<&WikiLink> {
+        let mut links = vec![];
+        for block in self.blocks.iter() {
+            block.for_each_inline(&mut |inline| {
+                if let WikiInline::Link(link) = inline {
+                    links.push(link);
+                }
+            });
+        }
+        links
+    }
+
+    pub fn links_mut(&mut self) -> Vec<&mut WikiLink> {
+        let mut links = vec![];
+        for block in self.blocks.iter_mut() {
+            block.for_each_inline_mut(&mut |inline| {
+                if let WikiInline::Link(link) = inline {
+                    links.push(link);
+                }
+            });
+        }
+        links
+    }
+}
+
+impl WikiBlock {
+    pub fn to_markup(&self) -> String {
+        match self {
+            WikiBlock::Heading { level, title } => {
+                let equals = "=".repeat(7 - level.clamp(&1, &5));
+                format!("{} {} {}", equals, title, equals)
+            },
+            WikiBlock::Paragraph(lines) => lines.iter().map(|line| inline_to_markup(line)).collect::<Vec<_>>().join("\n"),
+            WikiBlock::List(items) => items.iter()
+                .map(|item| format!("{}{} {}", "  ".repeat(item.depth.max(1)), if item.ordered { "-" } else { "*" }, inline_to_markup(&item.content)))
+                .collect::<Vec<_>>().join("\n"),
+            WikiBlock::Table(rows) => rows.iter().map(|row| {
+                let mut line = String::new();
+                for cell in row.iter() {
+                    line.push_str(if cell.header { "^ " } else { "| " });
+                    line.push_str(&inline_to_markup(&cell.content));
+                    line.push(' ');
+                }
+                // The row ends with the separator that matches the last cell.
+                line.push(if row.last().is_some_and(|cell| cell.header) { '^' } else { '|' });
+                line
+            }).collect::<Vec<_>>().join("\n"),
+            WikiBlock::Code { tag, attributes, text } => {
+                let open = if attributes.is_empty() { format!("<{}>", tag) } else { format!("<{} {}>", tag, attributes) };
+                format!("{}\n{}\n</{}>", open, text, tag)
+            },
+            WikiBlock::Preformatted(lines) => lines.iter().map(|line| format!("  {}", line)).collect::<Vec<_>>().join("\n"),
+            WikiBlock::HorizontalRule => "----".to_string(),
+        }
+    }
+
+    fn for_each_inline<'a, F>(&'a self, f: &mut F)
+        where F: FnMut(&'a WikiInline)
+    {
+        let lists: Vec<&Vec<WikiInline>> = match self {
+            WikiBlock::Paragraph(lines) => lines.iter().collect(),
+            WikiBlock::List(items) => items.iter().map(|item| &item.content).collect(),
+            WikiBlock::Table(rows) => rows.iter().flat_map(|row| row.iter().map(|cell| &cell.content)).collect(),
+            _ => vec![],
+        };
+        for list in lists {
+            for_each_inline_recursive(list, f);
+        }
+    }
+
+    fn for_each_inline_mut<'a, F>(&'a mut self, f: &mut F)
+        where F: FnMut(&'a mut WikiInline)
+    {
+        let lists: Vec<&mut Vec<WikiInline>> = match self {
+            WikiBlock::Paragraph(lines) => lines.iter_mut().collect(),
+            WikiBlock::List(items) => items.iter_mut().map(|item| &mut item.content).collect(),
+            WikiBlock::Table(rows) => rows.iter_mut().flat_map(|row| row.iter_mut().map(|cell| &mut cell.content)).collect(),
+            _ => vec![],
+        };
+        for list in lists {
+            for_each_inline_recursive_mut(list, f);
+        }
+    }
+}
+
+impl WikiInline {
+    pub fn to_markup(&self) -> String {
+        match self {
+            WikiInline::Text(text) => text.clone(),
+            WikiInline::Bold(content) => format!("**{}**", inline_to_markup(content)),
+            WikiInline::Italic(content) => format!("//{}//", inline_to_markup(content)),
+            WikiInline::Underline(content) => format!("__{}__", inline_to_markup(content)),
+            WikiInline::Monospace(content) => format!("''{}''", inline_to_markup(content)),
+            WikiInline::Link(link) => link.to_markup(),
+            WikiInline::Media(text) => format!("{{{{{}}}}}", text),
+            WikiInline::NoWiki(text) => format!("%%{}%%", text),
+        }
+    }
+
+    pub fn plain_text(&self) -> String {
+        // The text as it would be shown, without markup. A link shows its label if it has one.
+        match self {
+            WikiInline::Text(text) | WikiInline::NoWiki(text) => text.clone(),
+            WikiInline::Bold(content) | WikiInline::Italic(content) | WikiInline::Underline(content) | WikiInline::Monospace(content) =>
+                content.iter().map(|inline| inline.plain_text()).collect(),
+            WikiInline::Link(link) => link.label.clone().unwrap_or_else(|| link.target()),
+            WikiInline::Media(_) => "".to_string(),
+        }
+    }
+}
+
+impl WikiLink {
+    pub fn new(id: &str, anchor: Option<&str>, label: Option<&str>) -> Self {
+        let (namespace, page) = split_page_id(id);
+        Self { namespace, page, anchor: anchor.map(|anchor| anchor.to_string()), label: label.map(|label| label.to_string()) }
+    }
+
+    pub fn parse(text: &str) -> Self {
+        // The text between "[[" and "]]". Only the first pipe separates the target from the label,
+        // since the label may have pipes of its own.
+        let (target, label) = match text.split_once('|') {
+            Some((target, label)) => (target.trim(), Some(label.trim().to_string())),
+            None => (text.trim(), None),
+        };
+        if is_external(target) {
+            return Self { namespace: vec![], page: target.to_string(), anchor: None, label };
+        }
+        let (id, anchor) = match target.split_once('#') {
+            Some((id, anchor)) => (id, Some(anchor.to_string())),
+            None => (target, None),
+        };
+        let (namespace, page) = split_page_id(id);
+        Self { namespace, page, anchor, label }
+    }
+
+    pub fn is_external(&self) -> bool {
+        self.namespace.is_empty() && is_external(&self.page)
+    }
+
+    pub fn id(&self) -> String {
+        // The page ID as written, such as "tools:nav:attributes".
+        self.namespace.iter().map(|part| part.as_str()).chain(std::iter::once(self.page.as_str())).collect::<Vec<_>>().join(":")
+    }
+
+    pub fn set_id(&mut self, id: &str) {
+        let (namespace, page) = split_page_id(id);
+        self.namespace = namespace;
+        self.page = page;
+    }
+
+    pub fn target(&self) -> String {
+        match &self.anchor {
+            Some(anchor) => format!("{}#{}", self.id(), anchor),
+            None => self.id(),
+        }
+    }
+
+    pub fn resolved_id(&self, current_namespace: &str) -> String {
+        // The full, normalized ID of the page this link points to from a page in the given
+        // namespace. As in DokuWiki, an ID without a colon is in the current namespace, one
+        // starting with "." or ".." is relative to it, and any other ID is from the root.
+        let id = self.id();
+        let mut parts: Vec<String> = if id.starts_with('.') || !id.contains(':') {
+            split_namespace(current_namespace)
+        } else {
+            vec![]
+        };
+        for part in id.split(':') {
+            match part.trim() {
+                "" | "." => {},
+                ".." => { parts.pop(); },
+                part => parts.push(part.to_string()),
+            }
+        }
+        normalize_page_id(&parts.join(":"))
+    }
+
+    pub fn to_markup(&self) -> String {
+        match &self.label {
+            Some(label) => format!("[[{}|{}]]", self.target(), label),
+            None => format!("[[{}]]", self.target()),
+        }
+    }
+}
+
+pub fn normalize_page_id(id: &str) -> String {
+    // DokuWiki stores page names in lowercase with underscores in place of spaces, and an ID may
+    // be written with a leading colon to make it absolute.
+    id.trim().trim_start_matches(':').to_lowercase().split(':').map(|part| part.trim().replace(' ', "_")).collect::<Vec<_>>().join(":")
+}
+
+pub fn page_id_to_path<P>(pages_folder: P, id: &str) -> PathBuf
+    where P: AsRef<Path>
+{
+    // "tools:nav:attributes" is stored in "{pages_folder}/tools/nav/attributes.txt".
+    let mut path = pages_folder.as_ref().to_path_buf();
+    for part in normalize_page_id(id).split(':') {
+        path.push(part);
+    }
+    path.set_extension("txt");
+    path
+}
+
+pub fn path_to_page_id_r<P, F>(pages_folder: P, path: F) -> Result<String, String>
+    where
+        P: AsRef<Path>,
+        F: AsRef<Path>,
+{
+    let relative = path.as_ref().strip_prefix(pages_folder.as_ref())
+        .map_err(|_| format!("path_to_page_id_r: \"{}\" is not in \"{}\".", path_name(&path), path_name(&pages_folder)))?;
+    let parts = relative.with_extension("").iter().map(|part| part.to_string_lossy().to_string()).collect::<Vec<_>>();
+    Ok(parts.join(":"))
+}
+
+pub fn page_files_r<P>(pages_folder: P) -> Result<Vec<PathBuf>, String>
+    where P: AsRef<Path>
+{
+    // All of the page files in the folder and its subfolders, sorted by path.
+    let mut files = vec![];
+    for path in path_entries_r(&pages_folder)? {
+        if path.is_dir() {
+            files.append(&mut page_files_r(&path)?);
+        } else if path.extension().is_some_and(|extension| extension == "txt") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub fn rewrite_links_r<F>(text: &str, mut f: F) -> Result<(String, usize), String>
+    where F: FnMut(&mut WikiLink)
+{
+    // Call the function on each link in the text and put any changed links back in place,
+    // leaving everything else as it was. Links in code blocks and %%nowiki%% text are skipped.
+    // Returns the new text and the number of links that changed.
+    //
+    // Unlike parse_inline_r() this is lenient the way DokuWiki itself is: a "[[" with no "]]" later
+    // on the line, a stray "]]", or a "%%" with no closing "%%" later in the text is plain text.
+    // A %% section may span lines.
+    let mut result = String::with_capacity(text.len());
+    let mut change_count = 0;
+    let mut code_close: Option<String> = None;
+    let mut in_nowiki = false;
+    let mut line_start = 0;
+    for line in text.split_inclusive('\n') {
+        let line_end = line_start + line.len();
+        if !in_nowiki {
+            if let Some(close) = &code_close {
+                if line.contains(close.as_str()) {
+                    code_close = None;
+                }
+                result.push_str(line);
+                line_start = line_end;
+                continue;
+            }
+            if let Some((tag, _, rest)) = parse_code_start(line.trim()) {
+                let close = format!("</{}>", tag);
+                if !rest.contains(&close) {
+                    code_close = Some(close);
+                }
+                result.push_str(line);
+                line_start = line_end;
+                continue;
+            }
+        }
+        let mut pos = 0;
+        while pos < line.len() {
+            let rest = &line[pos..];
+            if in_nowiki {
+                match rest.find("%%") {
+                    Some(close_pos) => {
+                        result.push_str(&rest[..close_pos + 2]);
+                        pos += close_pos + 2;
+                        in_nowiki = false;
+                    },
+                    None => {
+                        result.push_str(rest);
+                        pos = line.len();
+                    },
+                }
+                continue;
+            }
+            let next_link = rest.find("[[");
+            let next_nowiki = rest.find("%%");
+            match (next_link, next_nowiki) {
+                (Some(link_pos), _) if next_nowiki.is_none_or(|nowiki_pos| link_pos < nowiki_pos) => {
+                    result.push_str(&rest[..link_pos]);
+                    let after_open = &rest[link_pos + 2..];
+                    match after_open.find("]]") {
+                        Some(close_pos) => {
+                            let inner = &after_open[..close_pos];
+                            let mut link = WikiLink::parse(inner);
+                            let original = link.clone();
+                            f(&mut link);
+                            if link != original {
+                                change_count += 1;
+                                result.push_str(&link.to_markup());
+                            } else {
+                                result.push_str(&rest[link_pos..link_pos + 2 + close_pos + 2]);
+                            }
+                            pos += link_pos + 2 + close_pos + 2;
+                        },
+                        None => {
+                            result.push_str("[[");
+                            pos += link_pos + 2;
+                        },
+                    }
+                },
+                (_, Some(nowiki_pos)) => {
+                    result.push_str(&rest[..nowiki_pos + 2]);
+                    pos += nowiki_pos + 2;
+                    // Only a %% that's closed somewhere later starts a nowiki section.
+                    in_nowiki = text[line_start + pos..].contains("%%");
+                },
+                _ => {
+                    result.push_str(rest);
+                    pos = line.len();
+                },
+            }
+        }
+        line_start = line_end;
+    }
+    Ok((result, change_count))
+}
+
+pub fn rewrite_links_in_folder_r<P, F>(pages_folder: P, mut f: F) -> Result<Vec<String>, String>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&str, &mut WikiLink),
+{
+    // Rewrite the links in every page file in the folder and its subfolders. The function gets the
+    // ID of the page the link is on as well as the link. Only files with changed links are
+    // written, and only once every page has been read and rewritten without an error, so a
+    // problem with one page leaves the whole folder untouched. Returns the IDs of the changed
+    // pages.
+    let mut changes = vec![];
+    for path in page_files_r(&pages_folder)? {
+        let id = path_to_page_id_r(&pages_folder, &path)?;
+        let text = read_file_to_string_r(&path)?;
+        let (new_text, change_count) = err_context(rewrite_links_r(&text, |link| f(&id, link)), &format!("rewrite_links_in_folder_r: \"{}\":", path_name(&path)))?;
+        if change_count > 0 {
+            changes.push((id, path, new_text));
+        }
+    }
+    let mut changed_ids = vec![];
+    for (id, path, new_text) in changes {
+        write_file_atomic_r(&path, &new_text)?;
+        changed_ids.push(id);
+    }
+    Ok(changed_ids)
+}
+
+pub fn retarget_links_in_folder_r<P>(pages_folder: P, old_id: &str, new_id: &str) -> Result<Vec<String>, String>
+    where P: AsRef<Path>
+{
+    // Point every link to the old page at the new page instead, as when a page has been moved or
+    // renamed. Links are matched by the page they resolve to, so relative links are found too.
+    // The anchor and label of each link are kept.
+    let old_id = normalize_page_id(old_id);
+    let new_id = normalize_page_id(new_id);
+    rewrite_links_in_folder_r(pages_folder, |page_id, link| {
+        let current_namespace = page_id.rsplit_once(':').map_or("", |(namespace, _)| namespace);
+        if !link.is_external() && link.resolved_id(current_namespace) == old_id {
+            // An ID without a colon would be taken as relative to the current namespace.
+            if new_id.contains(':') || current_namespace.is_empty() {
+                link.set_id(&new_id);
+            } else {
+                link.set_id(&format!(":{}", new_id));
+            }
+        }
+    })
+}
+
+fn is_external(target: &str) -> bool {
+    target.contains("://") || target.starts_with("mailto:") || target.starts_with(r"\\")
+}
+
+fn split_page_id(id: &str) -> (Vec<String>, String) {
+    let mut parts = id.split(':').map(|part| part.to_string()).collect::<Vec<_>>();
+    let page = parts.pop().unwrap_or_default();
+    (parts, page)
+}
+
+fn split_namespace(namespace: &str) -> Vec<String> {
+    namespace.split(':').filter(|part| !part.is_empty()).map(|part| part.to_string()).collect()
+}
+
+fn previous_line_blank(lines: &[&str], index: usize) -> bool {
+    index > 0 && lines[index - 1].trim().is_empty()
+}
+
+fn parse_heading(trimmed: &str) -> Option<(usize, String)> {
+    // "====== Title ======" is level 1 and "== Title ==" is level 5.
+    let open = trimmed.chars().take_while(|c| *c == '=').count();
+    if !(2..=6).contains(&open) || !trimmed.ends_with("==") || trimmed.len() <= open * 2 {
+        return None;
+    }
+    let title = trimmed.trim_start_matches('=').trim_end_matches('=').trim();
+    if title.is_empty() {
+        return None;
+    }
+    Some((7 - open, title.to_string()))
+}
+
+fn parse_list_item(line: &str) -> Option<(usize, bool, &str)> {
+    // A list item is indented by at least two spaces, two more for each level of nesting, and
+    // starts with "*" for a bulleted list or "-" for a numbered one.
+    let trimmed = line.trim_start_matches(' ');
+    let indent = line.len() - trimmed.len();
+    if indent < 2 {
+        return None;
+    }
+    let ordered = match trimmed.chars().next() {
+        Some('*') => false,
+        Some('-') => true,
+        _ => return None,
+    };
+    let rest = &trimmed[1..];
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    Some((indent / 2, ordered, rest.trim()))
+}
+
+fn parse_code_start(trimmed: &str) -> Option<(String, String, &str)> {
+    // Returns the tag name, its attributes, and the rest of the line after the opening tag.
+    for tag in ["code", "file"] {
+        if let Some(after) = trimmed.strip_prefix('<').and_then(|rest| rest.strip_prefix(tag)) {
+            if after.starts_with([' ', '>']) {
+                let close = after.find('>')?;
+                return Some((tag.to_string(), after[..close].trim().to_string(), &after[close + 1..]));
+            }
+        }
+    }
+    None
+}
+
+fn parse_table_row_r(trimmed: &str) -> Result<Vec<WikiTableCell>, String> {
+    // Cells are separated by "|", or by "^" for header cells, except inside links and other
+    // delimited text. Each separator says what kind of cell follows it.
+    let tokens = tokenize_delimited_rc(trimmed, &[("[[", "]]"), ("{{", "}}"), ("%%", "%%")], None, "parse_table_row_r")?;
+    let mut cells = vec![];
+    let mut header = false;
+    let mut cell_start = None;
+    for token in tokens {
+        if token.is_delimited() {
+            continue;
+        }
+        for (offset, c) in trimmed[token.start..token.end].char_indices() {
+            if c == '|' || c == '^' {
+                let pos = token.start + offset;
+                if let Some(start) = cell_start {
+                    cells.push(WikiTableCell { header, content: parse_inline_r(trimmed[start..pos].trim())? });
+                }
+                header = c == '^';
+                cell_start = Some(pos + 1);
+            }
+        }
+    }
+    // Text after the last separator is a cell too, though it's normally empty.
+    if let Some(start) = cell_start {
+        let rest = trimmed[start..].trim();
+        if !rest.is_empty() {
+            cells.push(WikiTableCell { header, content: parse_inline_r(rest)? });
+        }
+    }
+    Ok(cells)
+}
+
+pub fn parse_inline_r(text: &str) -> Result<Vec<WikiInline>, String> {
+    // A formatting marker like "**" without a matching closing marker is left as plain text, but
+    // an unclosed link, media item or %% is an error since it would swallow the rest of the text.
+    let mut elements = vec![];
+    let mut plain = String::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let rest = &text[pos..];
+        if let Some((open, close)) = [("[[", "]]"), ("{{", "}}"), ("%%", "%%")].iter().find(|(open, _)| rest.starts_with(open)) {
+            let end = rest[open.len()..].find(close).ok_or_else(|| format!("parse_inline_r: \"{}\" has no matching \"{}\" in \"{}\".", open, close, text))?;
+            let inner = &rest[open.len()..open.len() + end];
+            push_plain(&mut elements, &mut plain);
+            elements.push(match *open {
+                "[[" => WikiInline::Link(WikiLink::parse(inner)),
+                "{{" => WikiInline::Media(inner.to_string()),
+                _ => WikiInline::NoWiki(inner.to_string()),
+            });
+            pos += open.len() + end + close.len();
+            continue;
+        }
+        if let Some(marker) = ["**", "//", "__", "''"].iter().find(|marker| rest.starts_with(*marker)) {
+            // The slashes in a URL like "http://" aren't italics.
+            let is_url = *marker == "//" && text[..pos].ends_with(':');
+            if !is_url {
+                if let Some(end) = find_closing_marker(&rest[2..], marker) {
+                    let content = parse_inline_r(&rest[2..2 + end])?;
+                    push_plain(&mut elements, &mut plain);
+                    elements.push(match *marker {
+                        "**" => WikiInline::Bold(content),
+                        "//" => WikiInline::Italic(content),
+                        "__" => WikiInline::Underline(content),
+                        _ => WikiInline::Monospace(content),
+                    });
+                    pos += 2 + end + 2;
+                    continue;
+                }
+            }
+        }
+        let c = rest.chars().next().unwrap();
+        plain.push(c);
+        pos += c.len_utf8();
+    }
+    push_plain(&mut elements, &mut plain);
+    Ok(elements)
+}
+
+fn find_closing_marker(text: &str, marker: &str) -> Option<usize> {
+    // Skip over links and other delimited text so that a marker inside them doesn't count.
+    let mut pos = 0;
+    while pos < text.len() {
+        let rest = &text[pos..];
+        if rest.starts_with(marker) && !(marker == "//" && text[..pos].ends_with(':')) {
+            return Some(pos);
+        }
+        if let Some((open, close)) = [("[[", "]]"), ("{{", "}}"), ("%%", "%%")].iter().find(|(open, _)| rest.starts_with(open)) {
+            pos += open.len() + rest[open.len()..].find(close)? + close.len();
+            continue;
+        }
+        pos += rest.chars().next().unwrap().len_utf8();
+    }
+    None
+}
+
+fn push_plain(elements: &mut Vec<WikiInline>, plain: &mut String) {
+    if !plain.is_empty() {
+        elements.push(WikiInline::Text(std::mem::take(plain)));
+    }
+}
+
+fn inline_to_markup(elements: &[WikiInline]) -> String {
+    elements.iter().map(|inline| inline.to_markup()).collect()
+}
+
+fn for_each_inline_recursive<'a, F>(elements: &'a [WikiInline], f: &mut F)
+    where F: FnMut(&'a WikiInline)
+{
+    for inline in elements {
+        match inline {
+            WikiInline::Bold(content) | WikiInline::Italic(content) | WikiInline::Underline(content) | WikiInline::Monospace(content) =>
+                for_each_inline_recursive(content, f),
+            _ => f(inline),
+        }
+    }
+}
+
+fn for_each_inline_recursive_mut<'a, F>(elements: &'a mut [WikiInline], f: &mut F)
+    where F: FnMut(&'a mut WikiInline)
+{
+    for inline in elements {
+        match inline {
+            WikiInline::Bold(content) | WikiInline::Italic(content) | WikiInline::Underline(content) | WikiInline::Monospace(content) =>
+                for_each_inline_recursive_mut(content, f),
+            _ => f(inline),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::temp_workspace::{spec_file, spec_folder, TempWorkspace};
+
+    const SAMPLE: &str = "====== Narrators ======
+
+Some **bold //and italic//** text with a [[tools:nav:attributes#Narrator|Narrator]] link
+and a second line with ''code'' and https://example.com//path.
+
+^ Name ^ Link ^
+| Mark | [[tools:nav:attribute_values#Mark Steinberg|Mark | Steinberg]] |
+
+  * One
+    - Two
+  * Three
+
+<code rust>
+let s = \"[[not a link]]\";
+</code>
+
+  preformatted text
+
+----
+";
+
+    #[test]
+    fn test_parse_wiki_page() {
+        let page = WikiPage::parse_r(SAMPLE).unwrap();
+        assert_eq!(vec![(1, "Narrators")], page.headings());
+        assert_eq!(7, page.blocks.len());
+        match &page.blocks[1] {
+            WikiBlock::Paragraph(lines) => {
+                assert_eq!(2, lines.len());
+                assert_eq!(WikiInline::Text("Some ".to_string()), lines[0][0]);
+                assert_eq!(WikiInline::Bold(vec![WikiInline::Text("bold ".to_string()), WikiInline::Italic(vec![WikiInline::Text("and italic".to_string())])]), lines[0][1]);
+                assert_eq!("and a second line with code and https://example.com//path.", lines[1].iter().map(|inline| inline.plain_text()).collect::<String>());
+            },
+            block => panic!("Expected a paragraph, found {:?}", block),
+        }
+        match &page.blocks[2] {
+            WikiBlock::Table(rows) => {
+                assert!(rows[0].iter().all(|cell| cell.header));
+                assert_eq!(2, rows[1].len());
+                assert_eq!("Mark | Steinberg", rows[1][1].content[0].plain_text());
+            },
+            block => panic!("Expected a table, found {:?}", block),
+        }
+        match &page.blocks[3] {
+            WikiBlock::List(items) => assert_eq!(vec![(1, false), (2, true), (1, false)], items.iter().map(|item| (item.depth, item.ordered)).collect::<Vec<_>>()),
+            block => panic!("Expected a list, found {:?}", block),
+        }
+        assert_eq!(WikiBlock::Code { tag: "code".to_string(), attributes: "rust".to_string(), text: "let s = \"[[not a link]]\";".to_string() }, page.blocks[4]);
+        assert_eq!(WikiBlock::Preformatted(vec!["preformatted text".to_string()]), page.blocks[5]);
+
+        let links = page.links();
+        assert_eq!(2, links.len());
+        assert_eq!(vec!["tools", "nav"], links[0].namespace);
+        assert_eq!("attributes", links[0].page);
+        assert_eq!(Some("Narrator".to_string()), links[0].anchor);
+        assert_eq!("tools:nav:attribute_values#Mark Steinberg", links[1].target());
+
+        // Writing the page and parsing it again gives the same page.
+        let markup = page.to_markup();
+        assert!(markup.contains("| Mark | [[tools:nav:attribute_values#Mark Steinberg|Mark | Steinberg]] |"));
+        assert_eq!(page, WikiPage::parse_r(&markup).unwrap());
+
+        assert_eq!("Line 2: parse_inline_r: \"[[\" has no matching \"]]\" in \"a [[b\".", WikiPage::parse_r("x\na [[b\n").unwrap_err());
+        assert!(WikiPage::parse_r("<code>\nabc\n").unwrap_err().starts_with("Line 1: The <code> block"));
+    }
+
+    #[test]
+    fn test_wiki_links() {
+        let link = WikiLink::parse("https://example.com/a#b|Example");
+        assert!(link.is_external());
+        assert_eq!("[[https://example.com/a#b|Example]]", link.to_markup());
+        let link = WikiLink::parse("page");
+        assert_eq!("tools:nav:page", link.resolved_id("tools:nav"));
+        assert_eq!("tools:other", WikiLink::parse("..:other").resolved_id("tools:nav"));
+        assert_eq!("start", WikiLink::parse(":Start").resolved_id("tools:nav"));
+        assert_eq!("tools:my_page", WikiLink::parse("tools:My Page").resolved_id("x"));
+
+        let mut page = WikiPage::parse_r("See [[a:b]] and **[[c]]**.").unwrap();
+        for link in page.links_mut() {
+            link.label = Some("Label".to_string());
+        }
+        assert_eq!("See [[a:b|Label]] and **[[c|Label]]**.\n", page.to_markup());
+
+        let text = "| [[a:b#x|B]] | [[a:c]] |\n<file txt f.txt>\n[[a:b]]\n</file>\n%%[[a:b]]%% [[a:b]]";
+        let (new_text, count) = rewrite_links_r(text, |link| if link.id() == "a:b" { link.set_id("z:b") }).unwrap();
+        assert_eq!(2, count);
+        assert_eq!("| [[z:b#x|B]] | [[a:c]] |\n<file txt f.txt>\n[[a:b]]\n</file>\n%%[[a:b]]%% [[z:b]]", new_text);
+
+        // Stray and unclosed markers are plain text, and a %% section can span lines.
+        let text = "x]] [[a:b]] [[open\n%%start [[a:b]]\n[[a:b]] end%% [[a:b]]\n50%% off [[a:b]]\n";
+        let (new_text, count) = rewrite_links_r(text, |link| if link.id() == "a:b" { link.set_id("z:b") }).unwrap();
+        assert_eq!(3, count);
+        assert_eq!("x]] [[z:b]] [[open\n%%start [[a:b]]\n[[a:b]] end%% [[z:b]]\n50%% off [[z:b]]\n", new_text);
+    }
+
+    #[test]
+    fn test_retarget_links_in_folder_r() {
+        let workspace = TempWorkspace::with_spec("test_retarget_links_in_folder_r", &[
+            spec_file("start.txt", "[[tools:old_page|Old]] and [[other]]\n"),
+            spec_folder("tools", vec![
+                spec_file("old_page.txt", "===== Old =====\n"),
+                spec_file("index.txt", "  * [[old page#top]]\n  * [[:tools:old_page]]\n"),
+            ]),
+        ]);
+        let pages = workspace.path();
+        assert_eq!("tools:index", path_to_page_id_r(pages, pages.join("tools").join("index.txt")).unwrap());
+        assert_eq!(pages.join("tools").join("old_page.txt"), page_id_to_path(pages, "Tools:Old Page"));
+        assert_eq!(3, page_files_r(pages).unwrap().len());
+
+        let changed = retarget_links_in_folder_r(pages, "tools:old_page", "archive:new_page").unwrap();
+        assert_eq!(vec!["start", "tools:index"], changed);
+        assert_eq!("[[archive:new_page|Old]] and [[other]]\n", read_file_to_string_r(pages.join("start.txt")).unwrap());
+        assert_eq!("  * [[archive:new_page#top]]\n  * [[archive:new_page]]\n", read_file_to_string_r(pages.join("tools").join("index.txt")).unwrap());
+
+        // A page that can't be read means no page is changed, including those before it.
+        fs::write(pages.join("tools").join("unreadable.txt"), [0xff, 0xfe, 0x00, 0xd8]).unwrap();
+        assert!(retarget_links_in_folder_r(pages, "archive:new_page", "tools:old_page").is_err());
+        assert_eq!("[[archive:new_page|Old]] and [[other]]\n", read_file_to_string_r(pages.join("start.txt")).unwrap());
+    }
+}
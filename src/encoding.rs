@@ -0,0 +1,428 @@
+// Detect and decode the text encodings found in files from different sources: UTF-8 with or
+// without a byte order mark (BOM), UTF-16 and UTF-32 files written by Windows tools (which is where
+// the FF and FE bytes at the start of a file come from), and old single-byte Windows-1252 or
+// Latin-1 files that have no BOM at all.
+
+use std::fmt::{self, Display};
+use std::io::{self, Read};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Utf32Le,
+    Utf32Be,
+    Windows1252,
+    Latin1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+const BOM_UTF_8: &[u8] = &[0xEF, 0xBB, 0xBF];
+const BOM_UTF_16_LE: &[u8] = &[0xFF, 0xFE];
+const BOM_UTF_16_BE: &[u8] = &[0xFE, 0xFF];
+const BOM_UTF_32_LE: &[u8] = &[0xFF, 0xFE, 0x00, 0x00];
+const BOM_UTF_32_BE: &[u8] = &[0x00, 0x00, 0xFE, 0xFF];
+
+// Windows-1252 is the same as Latin-1 except for the bytes 0x80 through 0x9F, which Latin-1 uses
+// for control characters that never show up in real text. Windows-1252 uses them for curly quotes,
+// dashes, the euro sign and so on. The five bytes that aren't defined in Windows-1252 are passed
+// through as the matching control characters, the same as browsers do.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+impl TextEncoding {
+    pub fn bom(&self) -> &'static [u8] {
+        match self {
+            TextEncoding::Utf8Bom => BOM_UTF_8,
+            TextEncoding::Utf16Le => BOM_UTF_16_LE,
+            TextEncoding::Utf16Be => BOM_UTF_16_BE,
+            TextEncoding::Utf32Le => BOM_UTF_32_LE,
+            TextEncoding::Utf32Be => BOM_UTF_32_BE,
+            TextEncoding::Utf8 | TextEncoding::Windows1252 | TextEncoding::Latin1 => &[],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "UTF-8",
+            TextEncoding::Utf8Bom => "UTF-8 with BOM",
+            TextEncoding::Utf16Le => "UTF-16LE",
+            TextEncoding::Utf16Be => "UTF-16BE",
+            TextEncoding::Utf32Le => "UTF-32LE",
+            TextEncoding::Utf32Be => "UTF-32BE",
+            TextEncoding::Windows1252 => "Windows-1252",
+            TextEncoding::Latin1 => "Latin-1",
+        }
+    }
+}
+
+impl Display for TextEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+pub fn detect_bom(bytes: &[u8]) -> Option<TextEncoding> {
+    // The UTF-32LE BOM starts with the UTF-16LE BOM, so it has to be checked first.
+    [TextEncoding::Utf32Le, TextEncoding::Utf32Be, TextEncoding::Utf8Bom, TextEncoding::Utf16Le, TextEncoding::Utf16Be].iter()
+        .find(|encoding| bytes.starts_with(encoding.bom()))
+        .copied()
+}
+
+pub fn detect_encoding(bytes: &[u8]) -> TextEncoding {
+    // A BOM settles it. Next look for UTF-16 without a BOM, which has to come before the UTF-8
+    // check because mostly-ASCII UTF-16 is technically valid UTF-8 full of zero characters.
+    // Otherwise anything that's valid UTF-8 is taken to be UTF-8, since it's very unlikely that
+    // text in another encoding happens to be valid UTF-8 unless it's plain ASCII, in which case it
+    // doesn't matter.
+    if let Some(encoding) = detect_bom(bytes) {
+        return encoding;
+    }
+    if let Some(encoding) = detect_utf_16_without_bom(bytes) {
+        return encoding;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return TextEncoding::Utf8;
+    }
+    if bytes.iter().any(|byte| (0x80..=0x9F).contains(byte)) {
+        TextEncoding::Windows1252
+    } else {
+        TextEncoding::Latin1
+    }
+}
+
+fn detect_utf_16_without_bom(bytes: &[u8]) -> Option<TextEncoding> {
+    // Mostly-ASCII text in UTF-16 has a zero byte in every other position: the odd positions for
+    // little-endian and the even positions for big-endian. Single-byte encodings essentially never
+    // contain zero bytes, so if at least a third of the bytes on one side are zero, call it UTF-16.
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let pair_count = bytes.len() / 2;
+    let zero_count_even = bytes.iter().step_by(2).filter(|byte| **byte == 0).count();
+    let zero_count_odd = bytes.iter().skip(1).step_by(2).filter(|byte| **byte == 0).count();
+    let threshold = (pair_count / 3).max(1);
+    if zero_count_odd >= threshold && zero_count_even == 0 {
+        Some(TextEncoding::Utf16Le)
+    } else if zero_count_even >= threshold && zero_count_odd == 0 {
+        Some(TextEncoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+pub fn decode_bytes_r(bytes: &[u8], encoding: TextEncoding) -> Result<String, String> {
+    // Decode using the given encoding. If the bytes start with that encoding's BOM, the BOM is
+    // skipped.
+    let bytes = bytes.strip_prefix(encoding.bom()).unwrap_or(bytes);
+    match encoding {
+        TextEncoding::Utf8 | TextEncoding::Utf8Bom => {
+            // Files saved as UTF-8 with a BOM can also end up with a BOM that's been doubled by a
+            // second editor, so strip any that remain.
+            let bytes = bytes.strip_prefix(BOM_UTF_8).unwrap_or(bytes);
+            match std::str::from_utf8(bytes) {
+                Ok(text) => Ok(text.to_string()),
+                Err(err) => Err(format!("decode_bytes_r: The text is not valid {}: {}.", encoding, err)),
+            }
+        },
+        TextEncoding::Utf16Le => decode_utf_16_r(bytes, encoding, u16::from_le_bytes),
+        TextEncoding::Utf16Be => decode_utf_16_r(bytes, encoding, u16::from_be_bytes),
+        TextEncoding::Utf32Le => decode_utf_32_r(bytes, encoding, u32::from_le_bytes),
+        TextEncoding::Utf32Be => decode_utf_32_r(bytes, encoding, u32::from_be_bytes),
+        TextEncoding::Windows1252 => Ok(bytes.iter().map(|byte| windows_1252_char(*byte)).collect()),
+        TextEncoding::Latin1 => Ok(bytes.iter().map(|byte| *byte as char).collect()),
+    }
+}
+
+pub fn decode_bytes_detect_r(bytes: &[u8]) -> Result<(String, TextEncoding), String> {
+    let encoding = detect_encoding(bytes);
+    Ok((decode_bytes_r(bytes, encoding)?, encoding))
+}
+
+pub fn windows_1252_char(byte: u8) -> char {
+    match byte {
+        0x80..=0x9F => WINDOWS_1252_HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+fn decode_utf_16_r(bytes: &[u8], encoding: TextEncoding, from_bytes: fn([u8; 2]) -> u16) -> Result<String, String> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(format!("decode_bytes_r: The text has an odd number of bytes ({}) so it can't be {}.", bytes.len(), encoding));
+    }
+    let units = bytes.chunks_exact(2).map(|pair| from_bytes([pair[0], pair[1]]));
+    let mut text = String::with_capacity(bytes.len() / 2);
+    for (index, c) in char::decode_utf16(units).enumerate() {
+        match c {
+            Ok(c) => text.push(c),
+            Err(err) => return Err(format!("decode_bytes_r: The text is not valid {} at code unit {}: {}.", encoding, index, err)),
+        }
+    }
+    Ok(text)
+}
+
+fn decode_utf_32_r(bytes: &[u8], encoding: TextEncoding, from_bytes: fn([u8; 4]) -> u32) -> Result<String, String> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(format!("decode_bytes_r: The text has {} bytes, which isn't a multiple of four, so it can't be {}.", bytes.len(), encoding));
+    }
+    let mut text = String::with_capacity(bytes.len() / 4);
+    for (index, quad) in bytes.chunks_exact(4).enumerate() {
+        let value = from_bytes([quad[0], quad[1], quad[2], quad[3]]);
+        match char::from_u32(value) {
+            Some(c) => text.push(c),
+            None => return Err(format!("decode_bytes_r: The text is not valid {} at code unit {}: {:#X} is not a character.", encoding, index, value)),
+        }
+    }
+    Ok(text)
+}
+
+// Wraps a reader of UTF-16, UTF-32 or single-byte text and reads it back out as UTF-8, a chunk at
+// a time, so that large files don't have to be decoded all at once. A BOM at the start of the
+// stream is skipped.
+pub struct DecodeReader<R>
+    where R: Read
+{
+    inner: R,
+    encoding: TextEncoding,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    output_pos: usize,
+    bom_checked: bool,
+    at_end: bool,
+}
+
+impl <R> DecodeReader<R>
+    where R: Read
+{
+    pub fn new(inner: R, encoding: TextEncoding) -> Self {
+        Self {
+            inner,
+            encoding,
+            input: vec![],
+            output: vec![],
+            output_pos: 0,
+            bom_checked: false,
+            at_end: false,
+        }
+    }
+
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    fn fill_output(&mut self) -> io::Result<()> {
+        let mut chunk = [0; 8192];
+        let count = self.inner.read(&mut chunk)?;
+        self.input.extend_from_slice(&chunk[..count]);
+        if count == 0 {
+            self.at_end = true;
+        }
+        if !self.bom_checked {
+            let bom = self.encoding.bom();
+            if self.input.len() < bom.len() && !self.at_end {
+                // Not enough bytes yet to tell whether there's a BOM.
+                return Ok(());
+            }
+            if self.input.starts_with(bom) {
+                self.input.drain(..bom.len());
+            }
+            self.bom_checked = true;
+        }
+        let usable_len = self.usable_input_len();
+        let decoded = match self.encoding {
+            TextEncoding::Utf8 | TextEncoding::Utf8Bom => std::str::from_utf8(&self.input[..usable_len])
+                .map(|text| text.to_string())
+                .map_err(|err| format!("DecodeReader: The text is not valid {}: {}.", self.encoding, err)),
+            TextEncoding::Utf16Le => decode_utf_16_r(&self.input[..usable_len], self.encoding, u16::from_le_bytes),
+            TextEncoding::Utf16Be => decode_utf_16_r(&self.input[..usable_len], self.encoding, u16::from_be_bytes),
+            TextEncoding::Utf32Le => decode_utf_32_r(&self.input[..usable_len], self.encoding, u32::from_le_bytes),
+            TextEncoding::Utf32Be => decode_utf_32_r(&self.input[..usable_len], self.encoding, u32::from_be_bytes),
+            TextEncoding::Windows1252 | TextEncoding::Latin1 => decode_bytes_r(&self.input[..usable_len], self.encoding),
+        };
+        let decoded = decoded.map_err(|msg| io::Error::new(io::ErrorKind::InvalidData, msg))?;
+        self.input.drain(..usable_len);
+        if self.at_end && !self.input.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("DecodeReader: The text ends partway through a {} character.", self.encoding)));
+        }
+        self.output = decoded.into_bytes();
+        self.output_pos = 0;
+        Ok(())
+    }
+
+    fn usable_input_len(&self) -> usize {
+        // The number of bytes at the start of the input that can be decoded now. A chunk read from
+        // the inner reader can end in the middle of a code unit, or for UTF-16 between the two
+        // halves of a surrogate pair, and those bytes have to wait for the next chunk.
+        let unit_size = match self.encoding {
+            TextEncoding::Utf16Le | TextEncoding::Utf16Be => 2,
+            TextEncoding::Utf32Le | TextEncoding::Utf32Be => 4,
+            TextEncoding::Utf8 | TextEncoding::Utf8Bom => return self.utf_8_usable_input_len(),
+            TextEncoding::Windows1252 | TextEncoding::Latin1 => 1,
+        };
+        let mut usable_len = self.input.len() - (self.input.len() % unit_size);
+        if unit_size == 2 && usable_len >= 2 && !self.at_end {
+            let last_pair = [self.input[usable_len - 2], self.input[usable_len - 1]];
+            let last_unit = if self.encoding == TextEncoding::Utf16Le { u16::from_le_bytes(last_pair) } else { u16::from_be_bytes(last_pair) };
+            if (0xD800..=0xDBFF).contains(&last_unit) {
+                usable_len -= 2;
+            }
+        }
+        usable_len
+    }
+
+    fn utf_8_usable_input_len(&self) -> usize {
+        // Hold back a multi-byte character that's been cut off at the end of the chunk.
+        if self.at_end {
+            return self.input.len();
+        }
+        match std::str::from_utf8(&self.input) {
+            Ok(_) => self.input.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(_) => self.input.len(),
+        }
+    }
+}
+
+impl <R> Read for DecodeReader<R>
+    where R: Read
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.output_pos == self.output.len() && !self.at_end {
+            self.fill_output()?;
+        }
+        let count = buf.len().min(self.output.len() - self.output_pos);
+        buf[..count].copy_from_slice(&self.output[self.output_pos..self.output_pos + count]);
+        self.output_pos += count;
+        Ok(count)
+    }
+}
+
+pub fn detect_line_ending(text: &str) -> Option<LineEnding> {
+    // Return the most common line ending, or None if there are no line breaks at all. In a tie,
+    // prefer LF, then CRLF.
+    let crlf_count = text.matches("\r\n").count();
+    let cr_count = text.matches('\r').count() - crlf_count;
+    let lf_count = text.matches('\n').count() - crlf_count;
+    if lf_count + crlf_count + cr_count == 0 {
+        None
+    } else if lf_count >= crlf_count && lf_count >= cr_count {
+        Some(LineEnding::Lf)
+    } else if crlf_count >= cr_count {
+        Some(LineEnding::CrLf)
+    } else {
+        Some(LineEnding::Cr)
+    }
+}
+
+pub fn normalize_line_endings(text: &str, line_ending: LineEnding) -> String {
+    // Convert every line break, whether "\r\n", "\r" or "\n", to the given line ending.
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    match line_ending {
+        LineEnding::Lf => text,
+        _ => text.replace('\n', line_ending.as_str()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf_16_le_bytes(text: &str, with_bom: bool) -> Vec<u8> {
+        let mut bytes = if with_bom { BOM_UTF_16_LE.to_vec() } else { vec![] };
+        text.encode_utf16().for_each(|unit| bytes.extend_from_slice(&unit.to_le_bytes()));
+        bytes
+    }
+
+    #[test]
+    fn test_detect_encoding() {
+        assert_eq!(TextEncoding::Utf8, detect_encoding(b"Plain ASCII"));
+        assert_eq!(TextEncoding::Utf8, detect_encoding("Caf\u{e9}".as_bytes()));
+        assert_eq!(TextEncoding::Utf8Bom, detect_encoding(&[0xEF, 0xBB, 0xBF, b'a']));
+        assert_eq!(TextEncoding::Utf16Le, detect_encoding(&utf_16_le_bytes("Abc", true)));
+        assert_eq!(TextEncoding::Utf16Be, detect_encoding(&[0xFE, 0xFF, 0x00, b'A']));
+        assert_eq!(TextEncoding::Utf32Le, detect_encoding(&[0xFF, 0xFE, 0x00, 0x00, b'A', 0x00, 0x00, 0x00]));
+        assert_eq!(TextEncoding::Utf32Be, detect_encoding(&[0x00, 0x00, 0xFE, 0xFF, 0x00, 0x00, 0x00, b'A']));
+        // No BOM but obviously UTF-16.
+        assert_eq!(TextEncoding::Utf16Le, detect_encoding(&utf_16_le_bytes("Hello there", false)));
+        // Curly quotes from Windows-1252.
+        assert_eq!(TextEncoding::Windows1252, detect_encoding(&[0x93, b'H', b'i', 0x94]));
+        // An accented character that isn't valid UTF-8 and has no Windows-1252-only bytes.
+        assert_eq!(TextEncoding::Latin1, detect_encoding(&[b'C', b'a', b'f', 0xE9]));
+    }
+
+    #[test]
+    fn test_decode_bytes_detect_r() {
+        let text = "L\u{ed}ne one\r\nLine \u{1F600} two";
+        assert_eq!((text.to_string(), TextEncoding::Utf16Le), decode_bytes_detect_r(&utf_16_le_bytes(text, true)).unwrap());
+
+        let mut bytes = BOM_UTF_16_BE.to_vec();
+        text.encode_utf16().for_each(|unit| bytes.extend_from_slice(&unit.to_be_bytes()));
+        assert_eq!((text.to_string(), TextEncoding::Utf16Be), decode_bytes_detect_r(&bytes).unwrap());
+
+        let mut bytes = BOM_UTF_32_LE.to_vec();
+        text.chars().for_each(|c| bytes.extend_from_slice(&(c as u32).to_le_bytes()));
+        assert_eq!((text.to_string(), TextEncoding::Utf32Le), decode_bytes_detect_r(&bytes).unwrap());
+
+        let mut bytes = BOM_UTF_8.to_vec();
+        bytes.extend_from_slice(text.as_bytes());
+        assert_eq!((text.to_string(), TextEncoding::Utf8Bom), decode_bytes_detect_r(&bytes).unwrap());
+
+        assert_eq!(("\u{201C}Hi\u{201D} \u{2013} \u{20AC}5".to_string(), TextEncoding::Windows1252),
+            decode_bytes_detect_r(&[0x93, b'H', b'i', 0x94, b' ', 0x96, b' ', 0x80, b'5']).unwrap());
+        assert_eq!(("Caf\u{e9}".to_string(), TextEncoding::Latin1), decode_bytes_detect_r(&[b'C', b'a', b'f', 0xE9]).unwrap());
+
+        // A lone high surrogate isn't valid UTF-16.
+        assert!(decode_bytes_r(&[0xFF, 0xFE, 0x00, 0xD8, b'A', 0x00], TextEncoding::Utf16Le).is_err());
+        // An odd number of bytes can't be UTF-16.
+        assert!(decode_bytes_r(&[0xFF, 0xFE, b'A'], TextEncoding::Utf16Le).is_err());
+    }
+
+    #[test]
+    fn test_decode_reader() {
+        let mut text = String::new();
+        let bytes = [BOM_UTF_8, "Caf\u{e9}".as_bytes()].concat();
+        DecodeReader::new(&bytes[..], TextEncoding::Utf8Bom).read_to_string(&mut text).unwrap();
+        assert_eq!("Caf\u{e9}", text);
+
+        // Bad UTF-8 is an error as it is for the other encodings, not replacement characters.
+        let bytes = [BOM_UTF_8, b"Caf\xe9"].concat();
+        let err = DecodeReader::new(&bytes[..], TextEncoding::Utf8Bom).read_to_string(&mut String::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn test_line_endings() {
+        assert_eq!(None, detect_line_ending("One line"));
+        assert_eq!(Some(LineEnding::Lf), detect_line_ending("a\nb\r\nc\n"));
+        assert_eq!(Some(LineEnding::CrLf), detect_line_ending("a\r\nb\r\nc\n"));
+        assert_eq!(Some(LineEnding::Cr), detect_line_ending("a\rb\rc"));
+
+        let text = "a\r\nb\rc\nd\r\n";
+        assert_eq!("a\nb\nc\nd\n", normalize_line_endings(text, LineEnding::Lf));
+        assert_eq!("a\r\nb\r\nc\r\nd\r\n", normalize_line_endings(text, LineEnding::CrLf));
+        assert_eq!("a\rb\rc\rd\r", normalize_line_endings(text, LineEnding::Cr));
+    }
+}
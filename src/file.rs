@@ -1,4 +1,4 @@
-use std::fs::{DirEntry, FileType, File};
+use std::fs::{DirEntry, FileType, File, OpenOptions};
 use chrono::{NaiveDate, DateTime, Local, Datelike};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -7,9 +7,14 @@ use crate::format::format_zeros;
 
 use crate::*;
 use crate::date_time::date_for_file_name_now;
-use std::io::{BufReader, Read, BufRead};
-use itertools::Itertools;
+use crate::line_reader::LineReader;
+use crate::encoding::{TextEncoding, LineEnding, decode_bytes_detect_r, detect_line_ending, normalize_line_endings};
+use std::io::{BufReader, Read, BufRead, Write};
 use std::borrow::Borrow;
+use std::time::SystemTime;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_TEMP_FILE_NUMBER: AtomicUsize = AtomicUsize::new(1);
 
 pub fn dir_entry_to_file_name(dir_entry: &DirEntry) -> String {
     dir_entry.file_name().to_str().unwrap().to_string()
@@ -89,6 +94,19 @@ pub fn path_is_file_r<P>(path: P) -> Result<bool, String>
     Ok(metadata.is_file())
 }
 
+pub fn path_metadata_r<P>(path: P) -> Result<fs::Metadata, String>
+    where P: AsRef<Path>,
+{
+    path_exists_r(&path)?;
+    rse!(path.as_ref().metadata())
+}
+
+pub fn path_modified_r<P>(path: P) -> Result<SystemTime, String>
+    where P: AsRef<Path>,
+{
+    rse!(path_metadata_r(path)?.modified())
+}
+
 pub fn dir_entry_to_naive_date(dir_entry: &DirEntry) -> NaiveDate {
     let date = dir_entry.metadata().unwrap().modified().unwrap();
     let date: DateTime<Local> = chrono::DateTime::from(date);
@@ -119,6 +137,88 @@ pub fn write_if_changed_r<P>(path: P, contents: &str) -> Result<bool, String>
     }
 }
 
+pub fn write_file_atomic_r<P>(path: P, contents: &str) -> Result<(), String>
+    where P: AsRef<Path>
+{
+    // Write the contents to a temporary file in the same folder as the destination, flush it to
+    // disk, then rename it over the destination. A rename within a single folder replaces the file
+    // in one step, so if the process dies partway through we're left with either the old file or
+    // the new one, never a truncated file. The temporary file has to be in the same folder because
+    // a rename across file systems isn't atomic (and may not be allowed at all).
+    let path = path.as_ref();
+    let path_temp = path_temp_file_for(path)?;
+    let result = write_and_sync_r(&path_temp, contents)
+        .and_then(|()| rse!(fs::rename(&path_temp, path)))
+        .and_then(|()| sync_parent_folder_r(path));
+    if result.is_err() && path_exists(&path_temp) {
+        // Don't leave the partial temporary file behind. The original error is more useful than
+        // any error from this cleanup, so ignore the latter.
+        let _ = fs::remove_file(&path_temp);
+    }
+    match result {
+        Ok(()) => Ok(()),
+        Err(msg) => Err(format!("write_file_atomic_r: Couldn't write \"{}\". Message was \"{}\".", path_name(path), msg)),
+    }
+}
+
+pub fn write_file_atomic_back_up_r<P, B>(path: P, contents: &str, path_backup_folder: B, prefix: &str, extension: &str, digits: usize) -> Result<(), String>
+    where
+        P: AsRef<Path>,
+        B: AsRef<Path>,
+{
+    // Same as write_file_atomic_r() except that if the file already exists, the current version is
+    // first copied to the backup folder as the next numbered file, like "Config 004.txt".
+    if path_exists(&path) {
+        back_up_file_next_number_r(&path, path_backup_folder, prefix, extension, digits)?;
+    }
+    write_file_atomic_r(path, contents)
+}
+
+pub fn append_file_locked_r<P>(path: P, contents: &str) -> Result<(), String>
+    where P: AsRef<Path>
+{
+    // Append to the file, creating it if necessary, while holding an exclusive lock so that
+    // multiple processes or threads writing to the same file (such as a shared log) don't
+    // interleave their output. The call blocks until the lock is available, and the lock is
+    // released when the file is closed at the end of the function.
+    let mut file = rse!(OpenOptions::new().create(true).append(true).open(&path))?;
+    rse!(file.lock())?;
+    rse!(file.write_all(contents.as_bytes()))?;
+    rse!(file.sync_data())
+}
+
+fn path_temp_file_for(path: &Path) -> Result<PathBuf, String> {
+    // Something like "/Data/.Config.txt.1234.7.tmp" for "/Data/Config.txt". The process ID keeps
+    // two processes writing the same file from using the same temporary file, and the counter does
+    // the same for two threads in one process.
+    let file_name = path_file_name_r(path)?;
+    let number = NEXT_TEMP_FILE_NUMBER.fetch_add(1, Ordering::SeqCst);
+    let temp_file_name = format!(".{}.{}.{}.tmp", file_name, std::process::id(), number);
+    Ok(path.with_file_name(temp_file_name))
+}
+
+fn write_and_sync_r(path: &Path, contents: &str) -> Result<(), String> {
+    let mut file = rse!(File::create(path))?;
+    rse!(file.write_all(contents.as_bytes()))?;
+    // Make sure the contents are actually on disk before the rename makes them visible.
+    rse!(file.sync_all())
+}
+
+#[cfg(unix)]
+fn sync_parent_folder_r(path: &Path) -> Result<(), String> {
+    // On Unix the rename itself isn't durable until the folder entry is flushed. Windows doesn't
+    // allow opening a folder this way and doesn't need it.
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => rse!(File::open(parent).and_then(|folder| folder.sync_all())),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_folder_r(_path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
 pub fn read_file_to_string_r<P>(path: P) -> Result<String, String>
     where P: AsRef<Path>
 {
@@ -162,6 +262,19 @@ pub fn read_file_as_lines_r<P>(path: P) -> Result<Vec<String>, String>
     Ok(lines)
 }
 
+pub fn read_file_as_lines_lenient_r<P>(path: P) -> Result<Vec<String>, String>
+    where P: AsRef<Path>
+{
+    // Unlike read_file_as_lines_r(), lines may also end with a bare "\r", a BOM at the start of
+    // the file is skipped, UTF-16 and UTF-32 files are decoded, and text that isn't valid UTF-8 is taken to
+    // be Windows-1252 rather than being an error.
+    let mut lines = vec![];
+    for line in LineReader::open_r(path)? {
+        lines.push(line?.text);
+    }
+    Ok(lines)
+}
+
 pub fn read_file_as_lines_remove_bom_chars_r<P>(path: P) -> Result<Vec<String>, String>
     where P: AsRef<Path>
 {
@@ -495,31 +608,56 @@ pub fn remove_bom_characters<S, D>(path_source_file: S, path_dest_file: D)
     // These are the FF and FE (255 and 254) characters that can appear at the beginning of a file
     // and lead to:
     //   InvalidData, error: "stream did not contain valid UTF-8"
+    // They're the byte order mark of a UTF-16 file, so simply dropping them leaves every other
+    // byte a zero. Instead decode the file using whatever encoding it turns out to have and write
+    // it back out as plain UTF-8.
 
     // print_bytes(&path_source_file, 20);
 
-    let mut buffer = Vec::new();
-    {
-        let f = File::open(path_source_file).unwrap();
-        let mut reader = BufReader::new(f);
-        // Read file into vector.
-        reader.read_to_end(&mut buffer).unwrap();
-    }
-
-    let fixed_content = buffer.iter()
-        .filter(|val| **val < 254)
-        .map(|val| *val as char)
-        .join("");
+    let buffer = read_file_bytes_r(&path_source_file).unwrap();
+    let (fixed_content, _encoding) = decode_bytes_detect_r(&buffer).unwrap();
     write_file_r(&path_dest_file, &fixed_content).unwrap();
-    // {
-        // let w = File::create(&path_dest_file).unwrap();
-        // let mut writer = BufWriter::new(w);
-        // writer.write_all(&fixed_buffer[..]);
-    // }
 
     // print_bytes(&path_dest_file, 20);
 }
 
+pub fn read_file_bytes_r<P>(path: P) -> Result<Vec<u8>, String>
+    where P: AsRef<Path>
+{
+    path_exists_r(&path)?;
+    rse!(fs::read(path))
+}
+
+pub fn read_file_to_string_detect_encoding_r<P>(path: P) -> Result<(String, TextEncoding), String>
+    where P: AsRef<Path>
+{
+    // Read a file that may be UTF-8, UTF-16, UTF-32, Windows-1252 or Latin-1, with or without a
+    // BOM, and return the decoded text along with the encoding that was found. The BOM itself is
+    // not part of the returned text.
+    let bytes = read_file_bytes_r(&path)?;
+    match decode_bytes_detect_r(&bytes) {
+        Ok(decoded) => Ok(decoded),
+        Err(msg) => Err(format!("read_file_to_string_detect_encoding_r: \"{}\": {}", path_name(&path), msg)),
+    }
+}
+
+pub fn normalize_file_encoding_r<S, D>(path_source_file: S, path_dest_file: D, line_ending: Option<LineEnding>) -> Result<TextEncoding, String>
+    where
+        S: AsRef<Path>,
+        D: AsRef<Path>,
+{
+    // Rewrite a file as UTF-8 without a BOM. If a line ending is given, every line break is
+    // converted to it; otherwise the file's most common line ending is applied to all of its
+    // lines. The source and destination may be the same file. Returns the source encoding.
+    let (text, encoding) = read_file_to_string_detect_encoding_r(&path_source_file)?;
+    let text = match line_ending.or_else(|| detect_line_ending(&text)) {
+        Some(line_ending) => normalize_line_endings(&text, line_ending),
+        None => text,
+    };
+    write_file_atomic_r(path_dest_file, &text)?;
+    Ok(encoding)
+}
+
 pub fn print_bytes<F>(path_file: &F, max_byte_count: usize)
     where F: AsRef<Path>,
 {
@@ -539,27 +677,16 @@ pub fn print_bytes<F>(path_file: &F, max_byte_count: usize)
 
 #[cfg(test)]
 mod tests {
-    // Unit tests normally run in parallel, so if multiple tests are changing the same folder
-    // they'll conflict. Therefore each test works on a subfolder of PATH_TEST named after the name
-    // of the test function.
-    //
-    // To run single-threaded, call:
-    //   cargo test -- --test-threads=1
-    // or
-    //   cargo test -- --nocapture --test-threads=1
+    // Each test works in its own temp workspace named after the test function, so the tests can
+    // run in parallel and nothing is left behind.
 
     use super::*;
+    use crate::temp_workspace::TempWorkspace;
 
-    const PATH_TEST: &str = r"C:\Test_Rust_File_Functions";
     const FOLDER_WITH_FILES: &str = "Subfolder With Files";
 
-    fn setup(test_function_name: &str) -> PathBuf {
-        let path = path_buf(&[PATH_TEST, test_function_name]);
-        if path_exists(&path) {
-            fs::remove_dir_all(&path).unwrap();
-        }
-        fs::create_dir_all(&path).unwrap();
-        path
+    fn setup(test_function_name: &str) -> TempWorkspace {
+        TempWorkspace::new(test_function_name)
     }
 
     #[allow(dead_code)]
@@ -681,7 +808,8 @@ mod tests {
 
     #[test]
     fn test_path_file_name_r() {
-        let path_test_root = setup("test_path_file_name_r");
+        let workspace = setup("test_path_file_name_r");
+        let path_test_root = workspace.path();
         let file_name = "Abc.txt";
         let path_file = path_test_root.join(file_name);
         assert_eq!(file_name, path_file_name_r(&path_file).unwrap());
@@ -689,7 +817,8 @@ mod tests {
 
     #[test]
     fn test_path_exists() {
-        let path_test_root = setup("test_path_exists");
+        let workspace = setup("test_path_exists");
+        let path_test_root = workspace.path();
         let path = path_test_root.join("Subfolder A");
         assert_eq!(false, path_exists(&path));
         fs::create_dir_all(&path).unwrap();
@@ -698,7 +827,8 @@ mod tests {
 
     #[test]
     fn test_path_exists_r() {
-        let path_test_root = setup("fn test_path_exists_r");
+        let workspace = setup("test_path_exists_r");
+        let path_test_root = workspace.path();
         let path = path_test_root.join("Subfolder B");
         assert_err_path_not_found(path_exists_r(&path), &path);
         fs::create_dir_all(&path).unwrap();
@@ -707,7 +837,8 @@ mod tests {
 
     #[test]
     fn test_path_is_new_r() {
-        let path_test_root = setup("test_path_is_new_r");
+        let workspace = setup("test_path_is_new_r");
+        let path_test_root = workspace.path();
         let path = path_test_root.join("Subfolder C");
         assert!(path_is_new_r(&path).is_ok());
         fs::create_dir_all(&path).unwrap();
@@ -716,7 +847,8 @@ mod tests {
 
     #[test]
     fn test_path_is_directory() {
-        let path_test_root = setup("test_path_is_directory");
+        let workspace = setup("test_path_is_directory");
+        let path_test_root = workspace.path();
         let path_folder = path_test_root.join("Subfolder D");
         let path_file = path_folder.join("File D.txt");
         assert_err_path_not_found(path_is_directory_r(&path_folder), &path_folder);
@@ -733,7 +865,8 @@ mod tests {
 
     #[test]
     fn test_path_entries_r() {
-        let path_test_root = setup("test_path_entries_r");
+        let workspace = setup("test_path_entries_r");
+        let path_test_root = workspace.path();
         let (path_folder, exp_file_names) = create_test_folder_with_files(&path_test_root);
         let mut act_file_names = path_entries_r(&path_folder).unwrap().iter()
             .map(|path_buf| path_file_name_r(path_buf).unwrap())
@@ -744,7 +877,8 @@ mod tests {
 
     #[test]
     fn test_path_file_names_r() {
-        let path_test_root = setup("test_path_file_names_r");
+        let workspace = setup("test_path_file_names_r");
+        let path_test_root = workspace.path();
         let path_test_missing = path_test_root.join("Missing");
         assert_err_path_not_found(path_file_names_r(&path_test_missing), &path_test_missing);
 
@@ -755,7 +889,8 @@ mod tests {
 
     #[test]
     fn test_path_create_if_necessary_r() {
-        let path_test_root = setup("test_path_create_if_necessary_r");
+        let workspace = setup("test_path_create_if_necessary_r");
+        let path_test_root = workspace.path();
 
         // Place the subfolder a few levels down to confirm that we'll create all levels.
         let path_subfolder = path_test_root.join("Sub1").join("Sub2").join("Sub3");
@@ -784,7 +919,8 @@ mod tests {
 
     #[test]
     fn test_copy_folder_recursive_r() {
-        let path_test_root = setup("test_copy_folder_recursive_r");
+        let workspace = setup("test_copy_folder_recursive_r");
+        let path_test_root = workspace.path();
         let path_dest = path_test_root.join("Dest");
         let path_missing_folder = path_test_root.join("Missing");
         assert_err_path_not_found(copy_folder_recursive_r(&path_missing_folder, &path_dest),&path_missing_folder);
@@ -802,7 +938,8 @@ mod tests {
 
     #[test]
     fn test_copy_folder_to_new_folder_r() {
-        let path_test_root = setup("test_copy_folder_to_new_folder_r");
+        let workspace = setup("test_copy_folder_to_new_folder_r");
+        let path_test_root = workspace.path();
         let path_dest = path_test_root.join("Dest");
         let path_missing_folder = path_test_root.join("Missing");
         assert_err_path_not_found(copy_folder_to_new_folder_r(&path_missing_folder, &path_dest),&path_missing_folder);
@@ -830,7 +967,8 @@ mod tests {
 
     #[test]
     fn test_folder_highest_number_r() {
-        let path_test_root = setup("test_folder_highest_number_r");
+        let workspace = setup("test_folder_highest_number_r");
+        let path_test_root = workspace.path();
         let prefix = "Abc";
 
         let path_missing_folder = path_test_root.join("Missing");
@@ -853,7 +991,8 @@ mod tests {
 
     #[test]
     fn test_path_folder_highest_number_r() {
-        let path_test_root = setup("test_path_folder_highest_number_r");
+        let workspace = setup("test_path_folder_highest_number_r");
+        let path_test_root = workspace.path();
         // The path will be created.
         let path = path_test_root.join("Sub1").join("Sub2");
         let prefix = "Xyz";
@@ -869,7 +1008,8 @@ mod tests {
 
     #[test]
     fn test_path_folder_next_number_r() {
-        let path_test_root = setup("test_path_folder_next_number_r");
+        let workspace = setup("test_path_folder_next_number_r");
+        let path_test_root = workspace.path();
         // The path will be created.
         let path = path_test_root.join("Sub1");
         let prefix = "Def";
@@ -889,7 +1029,8 @@ mod tests {
     // pub fn path_folder_dated_next_number_r<P>(path_base: P, prefix: &str, digits: usize) -> Result<PathBuf, String>
     #[test]
     fn test_path_folder_dated_next_number_r() {
-        let path_test_root = setup("test_path_folder_dated_next_number_r");
+        let workspace = setup("test_path_folder_dated_next_number_r");
+        let path_test_root = workspace.path();
         // The path will be created.
         let path = path_test_root.join("Sub1").join("Sub2");
         let prefix = "Jkl";
@@ -912,7 +1053,8 @@ mod tests {
 
     #[test]
     fn test_back_up_folder_next_number_r() {
-        let path_test_root = setup("test_back_up_folder_next_number_r");
+        let workspace = setup("test_back_up_folder_next_number_r");
+        let path_test_root = workspace.path();
         let path_missing_folder = path_test_root.join("Missing");
         assert_err_path_not_found(back_up_folder_next_number_r(&path_missing_folder, &path_test_root, "Back Red", 3), &path_missing_folder);
 
@@ -941,7 +1083,8 @@ mod tests {
     #[test]
 
     fn test_back_up_folder_dated_next_number_r() {
-        let path_test_root = setup("test_back_up_folder_dated_next_number_r");
+        let workspace = setup("test_back_up_folder_dated_next_number_r");
+        let path_test_root = workspace.path();
         let path_missing_folder = path_test_root.join("Missing");
         assert_err_path_not_found(back_up_folder_dated_next_number_r(&path_missing_folder, &path_test_root, "Back Red", 3), &path_missing_folder);
 
@@ -970,4 +1113,125 @@ mod tests {
         assert_eq!(exp_dest_folder_name, act_dest_folder_name);
     }
 
+    #[test]
+    fn test_write_file_atomic_r() {
+        let workspace = setup("test_write_file_atomic_r");
+        let path_test_root = workspace.path();
+        let path_file = path_test_root.join("Atomic.txt");
+
+        // The file doesn't exist yet.
+        write_file_atomic_r(&path_file, "First version.").unwrap();
+        assert_eq!("First version.", read_file_to_string_r(&path_file).unwrap());
+
+        // Overwrite the existing file.
+        write_file_atomic_r(&path_file, "Second version.").unwrap();
+        assert_eq!("Second version.", read_file_to_string_r(&path_file).unwrap());
+
+        // The temporary file should have been renamed, leaving only the destination file.
+        assert_eq!(vec!["Atomic.txt".to_string()], path_file_names_r(&path_test_root).unwrap());
+
+        // The folder doesn't exist, so the write fails and there's nothing left behind.
+        let path_missing_file = path_test_root.join("Missing").join("Atomic.txt");
+        assert!(write_file_atomic_r(&path_missing_file, "Text.").is_err());
+        assert_eq!(false, path_exists(path_test_root.join("Missing")));
+
+        // Threads writing the same file at once each use their own temporary file, so the result
+        // is one complete version rather than a mix.
+        assert_ne!(path_temp_file_for(&path_file).unwrap(), path_temp_file_for(&path_file).unwrap());
+        let versions = (0..4).map(|index| format!("{}\n", index).repeat(10_000)).collect::<Vec<_>>();
+        let handles = versions.iter().cloned()
+            .map(|contents| {
+                let path_file = path_file.clone();
+                std::thread::spawn(move || write_file_atomic_r(&path_file, &contents).unwrap())
+            })
+            .collect::<Vec<_>>();
+        handles.into_iter().for_each(|handle| handle.join().unwrap());
+        assert!(versions.contains(&read_file_to_string_r(&path_file).unwrap()));
+        assert_eq!(vec!["Atomic.txt".to_string()], path_file_names_r(&path_test_root).unwrap());
+    }
+
+    #[test]
+    fn test_write_file_atomic_back_up_r() {
+        let workspace = setup("test_write_file_atomic_back_up_r");
+        let path_test_root = workspace.path();
+        let path_file = path_test_root.join("Config.txt");
+        let path_backup = path_test_root.join("Backup");
+
+        // Nothing to back up the first time, so the backup folder isn't even created.
+        write_file_atomic_back_up_r(&path_file, "Version 1.", &path_backup, "Config", "txt", 3).unwrap();
+        assert_eq!(false, path_exists(&path_backup));
+
+        write_file_atomic_back_up_r(&path_file, "Version 2.", &path_backup, "Config", "txt", 3).unwrap();
+        write_file_atomic_back_up_r(&path_file, "Version 3.", &path_backup, "Config", "txt", 3).unwrap();
+        assert_eq!("Version 3.", read_file_to_string_r(&path_file).unwrap());
+        assert_eq!(str_to_string_vector(&["Config 001.txt", "Config 002.txt"]), path_file_names_r(&path_backup).unwrap());
+        assert_eq!("Version 1.", read_file_to_string_r(path_backup.join("Config 001.txt")).unwrap());
+        assert_eq!("Version 2.", read_file_to_string_r(path_backup.join("Config 002.txt")).unwrap());
+    }
+
+    #[test]
+    fn test_append_file_locked_r() {
+        let workspace = setup("test_append_file_locked_r");
+        let path_test_root = workspace.path();
+        let path_file = path_test_root.join("Shared Log.txt");
+
+        // Several threads append to the same file at once. Each line should come through whole.
+        let thread_count = 4;
+        let line_count = 50;
+        let handles = (0..thread_count)
+            .map(|thread_index| {
+                let path_file = path_file.clone();
+                std::thread::spawn(move || {
+                    for line_index in 0..line_count {
+                        append_file_locked_r(&path_file, &format!("Thread {} line {}\n", thread_index, line_index)).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        handles.into_iter().for_each(|handle| handle.join().unwrap());
+
+        let lines = read_file_as_lines_r(&path_file).unwrap();
+        assert_eq!(thread_count * line_count, lines.len());
+        assert!(lines.iter().all(|line| line.starts_with("Thread ") && line.contains(" line ")));
+    }
+
+    #[test]
+    fn test_read_file_as_lines_lenient_r() {
+        let workspace = setup("test_read_file_as_lines_lenient_r");
+        let path_file = workspace.join("Mixed.txt");
+        fs::write(&path_file, b"one\rtwo\r\ncaf\xe9\n").unwrap();
+        // The strict version only splits on "\n" and "\r\n" and needs valid UTF-8.
+        assert!(read_file_as_lines_r(&path_file).is_err());
+        assert_eq!(str_to_string_vector(&["one", "two", "caf\u{e9}"]), read_file_as_lines_lenient_r(&path_file).unwrap());
+    }
+
+    #[test]
+    fn test_normalize_file_encoding_r() {
+        let workspace = setup("test_normalize_file_encoding_r");
+        let path_test_root = workspace.path();
+        let path_source = path_test_root.join("UTF-16.txt");
+        let path_dest = path_test_root.join("UTF-8.txt");
+
+        // A UTF-16LE file with a BOM and mixed line endings, as written by some Windows tools.
+        let text = "Caf\u{e9} one\r\nLine two\r\nLine three\n";
+        let mut bytes = vec![0xFF, 0xFE];
+        text.encode_utf16().for_each(|unit| bytes.extend_from_slice(&unit.to_le_bytes()));
+        fs::write(&path_source, &bytes).unwrap();
+
+        // Reading it as UTF-8 fails, but detecting the encoding works.
+        assert!(read_file_to_string_r(&path_source).is_err());
+        assert_eq!((text.to_string(), TextEncoding::Utf16Le), read_file_to_string_detect_encoding_r(&path_source).unwrap());
+
+        // With no line ending given, the most common one (CRLF) is used throughout.
+        assert_eq!(TextEncoding::Utf16Le, normalize_file_encoding_r(&path_source, &path_dest, None).unwrap());
+        assert_eq!("Caf\u{e9} one\r\nLine two\r\nLine three\r\n", read_file_to_string_r(&path_dest).unwrap());
+
+        // Normalize in place.
+        assert_eq!(TextEncoding::Utf8, normalize_file_encoding_r(&path_dest, &path_dest, Some(LineEnding::Lf)).unwrap());
+        assert_eq!("Caf\u{e9} one\nLine two\nLine three\n", read_file_to_string_r(&path_dest).unwrap());
+
+        // The old BOM-removal path now decodes the file rather than leaving zero bytes behind.
+        assert_eq!(text, read_file_to_string_remove_bom_chars_r(&path_source).unwrap());
+    }
+
 }
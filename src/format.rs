@@ -2,6 +2,7 @@ use num_format::{Locale, ToFormattedStr, ToFormattedString};
 use std::fmt::Display;
 use itertools::Itertools;
 use textwrap;
+use terminal_size::{terminal_size, Width};
 
 use super::parse;
 
@@ -93,6 +94,23 @@ pub fn format_float<T>(val: T, precision: usize) -> String
     format!("{:.*}", precision, val.into())
 }
 
+pub fn format_bytes(bytes: u64) -> String {
+    // A size in the largest unit that keeps the number at least one, like "1.5 GB". Units are
+    // powers of 1,024 as in Windows Explorer.
+    let units = ["bytes", "KB", "MB", "GB", "TB", "PB"];
+    let mut value = bytes as f64;
+    let mut unit_index = 0;
+    while value >= 1024.0 && unit_index < units.len() - 1 {
+        value /= 1024.0;
+        unit_index += 1;
+    }
+    if unit_index == 0 {
+        format!("{} {}", format_count(bytes), units[0])
+    } else {
+        format!("{} {}", format_float(value, 1), units[unit_index])
+    }
+}
+
 pub fn list_of_counts<T>(vals: &[T]) -> String
     where T: ToFormattedStr
 {
@@ -361,6 +379,297 @@ pub fn limit_length(value: &str, max_length: Option<usize>) -> String {
     }
 }
 
+pub fn limit_length_chars(value: &str, max_length: Option<usize>) -> String {
+    // Like limit_length() but the length is in characters, so that text with accents or symbols
+    // isn't cut in the middle of a character and lines up in columns.
+    if let Some(max_length) = max_length {
+        assert!(max_length >= 4);
+        if value.chars().count() <= max_length {
+            value.to_string()
+        } else {
+            format!("{}...", value.chars().take(max_length - 3).collect::<String>())
+        }
+    } else {
+        value.to_string()
+    }
+}
+
+// Text tables with auto-sized columns, for reports that would otherwise line things up by hand
+// with {:>width$}. Numbers are right-aligned and everything else is left-aligned.
+
+// limit_length_chars() can't go shorter than this.
+const TABLE_COLUMN_WIDTH_MIN: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableStyle {
+    // Columns separated by spaces with a line of dashes under the header.
+    Plain,
+    // Box-drawing characters around every cell.
+    Box,
+    Markdown,
+    Html,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableCell {
+    pub text: String,
+    pub is_number: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextTable {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<TableCell>>,
+    // Longer cells are cut short with "...".
+    pub max_column_width: Option<usize>,
+    // The widest the Plain and Box styles may be, after which the widest columns are narrowed.
+    pub max_width: Option<usize>,
+}
+
+impl TableCell {
+    pub fn text(text: &str) -> Self {
+        Self { text: text.to_string(), is_number: false }
+    }
+
+    pub fn count<T: ToFormattedStr>(val: T) -> Self {
+        Self { text: format_count(val), is_number: true }
+    }
+
+    pub fn float<T>(val: T, precision: usize) -> Self
+        where T: Into<f64>
+    {
+        Self { text: format_float(val, precision), is_number: true }
+    }
+
+    pub fn empty() -> Self {
+        // An empty cell doesn't keep a column of numbers from being right-aligned.
+        Self { text: "".to_string(), is_number: true }
+    }
+}
+
+impl From<&str> for TableCell {
+    fn from(text: &str) -> Self {
+        TableCell::text(text)
+    }
+}
+
+impl From<String> for TableCell {
+    fn from(text: String) -> Self {
+        Self { text, is_number: false }
+    }
+}
+
+impl TextTable {
+    pub fn new(headers: &[&str]) -> Self {
+        Self {
+            headers: headers.iter().map(|header| header.to_string()).collect(),
+            rows: vec![],
+            max_column_width: None,
+            max_width: None,
+        }
+    }
+
+    pub fn add_row(&mut self, row: Vec<TableCell>) {
+        self.rows.push(row);
+    }
+
+    pub fn fit_terminal(&mut self) {
+        // Keep the table within the width of the console, if there is one.
+        self.max_width = terminal_width();
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.rows.iter().map(|row| row.len()).chain(std::iter::once(self.headers.len())).max().unwrap_or(0)
+    }
+
+    pub fn format(&self, style: TableStyle) -> String {
+        let right_aligned = (0..self.column_count())
+            .map(|index| self.rows.iter().all(|row| row.get(index).is_none_or(|cell| cell.is_number)))
+            .collect::<Vec<_>>();
+        let widths = self.column_widths(style);
+        let fit = |text: &str, index: usize| limit_length_chars(text, Some(widths[index]).filter(|width| *width >= TABLE_COLUMN_WIDTH_MIN));
+        let headers = (0..widths.len()).map(|index| fit(self.headers.get(index).map_or("", |header| header.as_str()), index)).collect::<Vec<_>>();
+        let rows = self.rows.iter()
+            .map(|row| (0..widths.len()).map(|index| fit(row.get(index).map_or("", |cell| cell.text.as_str()), index)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        match style {
+            TableStyle::Plain => format_table_plain(&headers, &rows, &widths, &right_aligned),
+            TableStyle::Box => format_table_box(&headers, &rows, &widths, &right_aligned),
+            TableStyle::Markdown => format_table_markdown(&headers, &rows, &widths, &right_aligned),
+            TableStyle::Html => format_table_html(&headers, &rows, &right_aligned),
+        }
+    }
+
+    pub fn print(&self, style: TableStyle) {
+        print!("{}", self.format(style));
+    }
+
+    fn column_widths(&self, style: TableStyle) -> Vec<usize> {
+        // The width of the longest cell in each column, limited by max_column_width. For the text
+        // styles the widest column is then narrowed one character at a time until the table fits
+        // in max_width or every column is as narrow as it can go.
+        let mut widths = vec![0; self.column_count()];
+        for (index, header) in self.headers.iter().enumerate() {
+            widths[index] = widths[index].max(header.chars().count());
+        }
+        for row in self.rows.iter() {
+            for (index, cell) in row.iter().enumerate() {
+                widths[index] = widths[index].max(cell.text.chars().count());
+            }
+        }
+        if let Some(max_column_width) = self.max_column_width {
+            let max_column_width = max_column_width.max(TABLE_COLUMN_WIDTH_MIN);
+            widths.iter_mut().for_each(|width| *width = (*width).min(max_column_width));
+        }
+        let overhead = match style {
+            TableStyle::Plain => 2 * widths.len().saturating_sub(1),
+            TableStyle::Box => 3 * widths.len() + 1,
+            TableStyle::Markdown | TableStyle::Html => return widths,
+        };
+        if let Some(max_width) = self.max_width {
+            while widths.iter().sum::<usize>() + overhead > max_width {
+                match widths.iter_mut().filter(|width| **width > TABLE_COLUMN_WIDTH_MIN).max_by_key(|width| **width) {
+                    Some(width) => *width -= 1,
+                    None => break,
+                }
+            }
+        }
+        widths
+    }
+}
+
+pub fn terminal_width() -> Option<usize> {
+    terminal_size().map(|(Width(width), _)| width as usize)
+}
+
+fn pad_cell(text: &str, width: usize, right_aligned: bool) -> String {
+    if right_aligned {
+        format!("{:>width$}", text, width = width)
+    } else {
+        format!("{:<width$}", text, width = width)
+    }
+}
+
+fn format_table_plain(headers: &[String], rows: &[Vec<String>], widths: &[usize], right_aligned: &[bool]) -> String {
+    let format_line = |cells: &[String]| -> String {
+        let line = cells.iter().enumerate().map(|(index, cell)| pad_cell(cell, widths[index], right_aligned[index])).join("  ");
+        format!("{}\n", line.trim_end())
+    };
+    let mut table = format_line(headers);
+    table.push_str(&format_line(&widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<_>>()));
+    for row in rows.iter() {
+        table.push_str(&format_line(row));
+    }
+    table
+}
+
+fn format_table_box(headers: &[String], rows: &[Vec<String>], widths: &[usize], right_aligned: &[bool]) -> String {
+    let format_rule = |left: &str, middle: &str, right: &str| -> String {
+        format!("{}{}{}\n", left, widths.iter().map(|width| "─".repeat(width + 2)).join(middle), right)
+    };
+    let format_line = |cells: &[String]| -> String {
+        format!("│ {} │\n", cells.iter().enumerate().map(|(index, cell)| pad_cell(cell, widths[index], right_aligned[index])).join(" │ "))
+    };
+    let mut table = format_rule("┌", "┬", "┐");
+    table.push_str(&format_line(headers));
+    table.push_str(&format_rule("├", "┼", "┤"));
+    for row in rows.iter() {
+        table.push_str(&format_line(row));
+    }
+    table.push_str(&format_rule("└", "┴", "┘"));
+    table
+}
+
+fn format_table_markdown(headers: &[String], rows: &[Vec<String>], widths: &[usize], right_aligned: &[bool]) -> String {
+    // Padded so that the source lines up too. A "|" in a cell would end the cell so it's escaped,
+    // which makes that line a little longer.
+    let widths = widths.iter().map(|width| (*width).max(3)).collect::<Vec<_>>();
+    let format_line = |cells: &[String]| -> String {
+        format!("| {} |\n", cells.iter().enumerate().map(|(index, cell)| pad_cell(&cell.replace('|', "\\|"), widths[index], right_aligned[index])).join(" | "))
+    };
+    let mut table = format_line(headers);
+    let rule = widths.iter().zip(right_aligned.iter())
+        .map(|(width, right)| if *right { format!("{}:", "-".repeat(width + 1)) } else { "-".repeat(width + 2) })
+        .join("|");
+    table.push_str(&format!("|{}|\n", rule));
+    for row in rows.iter() {
+        table.push_str(&format_line(row));
+    }
+    table
+}
+
+fn format_table_html(headers: &[String], rows: &[Vec<String>], right_aligned: &[bool]) -> String {
+    let format_line = |cells: &[String], tag: &str| -> String {
+        let cells = cells.iter().enumerate().map(|(index, cell)| {
+            let style = if right_aligned[index] { " style=\"text-align: right\"" } else { "" };
+            format!("<{}{}>{}</{}>", tag, style, escape_html(cell), tag)
+        }).join("");
+        format!("<tr>{}</tr>\n", cells)
+    };
+    let mut table = "<table>\n<thead>\n".to_string();
+    table.push_str(&format_line(headers, "th"));
+    table.push_str("</thead>\n<tbody>\n");
+    for row in rows.iter() {
+        table.push_str(&format_line(row, "td"));
+    }
+    table.push_str("</tbody>\n</table>\n");
+    table
+}
+
+fn escape_html(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod table_tests {
+    use super::*;
+
+    fn sample_table() -> TextTable {
+        let mut table = TextTable::new(&["Name", "Count", "Mean"]);
+        table.add_row(vec!["Widgets".into(), TableCell::count(12_345usize), TableCell::float(1.5f32, 2)]);
+        table.add_row(vec!["A <b> | c".into(), TableCell::count(7usize), TableCell::empty()]);
+        table
+    }
+
+    #[test]
+    fn test_text_table_plain_and_box() {
+        let table = sample_table();
+        let expected = "Name        Count  Mean\n---------  ------  ----\nWidgets    12,345  1.50\nA <b> | c       7\n";
+        assert_eq!(expected, table.format(TableStyle::Plain));
+        let expected = "┌───────────┬────────┬──────┐\n│ Name      │  Count │ Mean │\n├───────────┼────────┼──────┤\n│ Widgets   │ 12,345 │ 1.50 │\n│ A <b> | c │      7 │      │\n└───────────┴────────┴──────┘\n";
+        assert_eq!(expected, table.format(TableStyle::Box));
+
+        // Narrowing the table cuts the widest column short.
+        let table = TextTable { max_width: Some(20), ..sample_table() };
+        assert_eq!("Wid...  12,345  1.50", table.format(TableStyle::Plain).lines().nth(2).unwrap());
+        assert!(table.format(TableStyle::Plain).lines().all(|line| line.chars().count() <= 20));
+        let table = TextTable { max_column_width: Some(6), ..sample_table() };
+        assert!(table.format(TableStyle::Plain).contains("Wid..."));
+    }
+
+    #[test]
+    fn test_text_table_markdown_and_html() {
+        let table = sample_table();
+        let markdown = table.format(TableStyle::Markdown);
+        assert_eq!("| Name      |  Count | Mean |", markdown.lines().next().unwrap());
+        assert_eq!("|-----------|-------:|-----:|", markdown.lines().nth(1).unwrap());
+        assert!(markdown.contains("A <b> \\| c"));
+        let html = table.format(TableStyle::Html);
+        assert!(html.contains("<th>Name</th><th style=\"text-align: right\">Count</th>"));
+        assert!(html.contains("<td>A &lt;b&gt; | c</td>"));
+    }
+
+    #[test]
+    fn test_limit_length() {
+        assert_eq!("abcdef", limit_length("abcdef", Some(6)));
+        assert_eq!("abc...", limit_length("abcdefg", Some(6)));
+        assert_eq!("abcdefg", limit_length("abcdefg", None));
+        assert_eq!("abc...", limit_length_chars("abcdefg", Some(6)));
+        assert_eq!("été...", limit_length_chars("étémoins", Some(6)));
+        assert_eq!("abcdefg", limit_length_chars("abcdefg", None));
+    }
+}
+
 /*
 #[cfg(test)]
 mod tests {
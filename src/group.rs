@@ -97,6 +97,15 @@ impl <T> Grouper<T>
         }
     }
 
+    pub fn counts(&self, sort_by_count: bool) -> Vec<(T, usize)> {
+        // The keys and their counts, for something other than printing like a spreadsheet.
+        let mut v = self.entries.values().map(|entry| (entry.key.clone(), entry.count)).collect::<Vec<_>>();
+        if sort_by_count {
+            v.sort_by(|a, b| { a.1.cmp(&b.1).reverse().then(a.0.cmp(&b.0)) } );
+        }
+        v
+    }
+
     pub fn label_line(&self) -> String {
         format!("\nname: {}: entries: {}, items: {}, counts: {}..={}",
                 self.name,
@@ -1,29 +1,53 @@
-use std::fs;
 use std::path;
 
-use crate::parse;
+use crate::*;
+use crate::file;
 use crate::format;
 
-#[derive(Debug)]
+pub mod chrome_json;
+pub mod manage;
+pub mod wiki_export;
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct BookmarkSet {
     pub name: String,
     pub links: Vec<BookmarkLink>,
     pub sets: Vec<Box<BookmarkSet>>,
+    // Seconds since the Unix epoch, as in the ADD_DATE and LAST_MODIFIED attributes.
+    pub add_date: Option<i64>,
+    pub last_modified: Option<i64>,
+    // Any other attributes of the folder, such as PERSONAL_TOOLBAR_FOLDER, in the order found.
+    pub attributes: Vec<(String, String)>,
+    // The order in which the links, subfolders and separators appeared in the file. The first
+    // BookmarkKind::Link is links[0], the second is links[1], and so on.
+    pub order: Vec<BookmarkKind>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct BookmarkLink {
     pub url: String,
     pub label: String,
+    pub add_date: Option<i64>,
+    pub last_modified: Option<i64>,
+    // Usually a data URL with the site's icon.
+    pub icon: Option<String>,
+    // The text of a <DD> element after the link, which Firefox uses for descriptions.
+    pub description: Option<String>,
+    pub attributes: Vec<(String, String)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BookmarkKind {
+    Link,
+    Set,
+    Separator,
 }
 
 impl BookmarkSet {
-    fn new(name: &str) -> Self {
-        assert!(name.len() > 0);
+    pub fn new(name: &str) -> Self {
         Self {
             name: name.to_string(),
-            links: vec![],
-            sets: vec![],
+            ..Default::default()
         }
     }
 
@@ -38,72 +62,611 @@ impl BookmarkSet {
         }
     }
 
+    pub fn add_link(&mut self, link: BookmarkLink) {
+        self.links.push(link);
+        self.order.push(BookmarkKind::Link);
+    }
+
+    pub fn add_set(&mut self, set: BookmarkSet) {
+        self.sets.push(Box::new(set));
+        self.order.push(BookmarkKind::Set);
+    }
+
+    pub fn add_separator(&mut self) {
+        self.order.push(BookmarkKind::Separator);
+    }
+
+    pub fn link_count_deep(&self) -> usize {
+        self.links.len() + self.sets.iter().map(|set| set.link_count_deep()).sum::<usize>()
+    }
+
+    pub fn set_count_deep(&self) -> usize {
+        self.sets.len() + self.sets.iter().map(|set| set.set_count_deep()).sum::<usize>()
+    }
+
+    pub fn remove_link(&mut self, index: usize) -> BookmarkLink {
+        remove_from_order(&mut self.order, BookmarkKind::Link, index);
+        self.links.remove(index)
+    }
+
+    pub fn remove_set(&mut self, index: usize) -> BookmarkSet {
+        remove_from_order(&mut self.order, BookmarkKind::Set, index);
+        *self.sets.remove(index)
+    }
+
+    pub fn is_toolbar_folder(&self) -> bool {
+        self.attributes.iter().any(|(name, value)| name == "PERSONAL_TOOLBAR_FOLDER" && value == "true")
+    }
+
+    pub fn ordered_items(&self) -> Vec<BookmarkItem<'_>> {
+        // The links, subfolders and separators in the order they should be shown. If links or
+        // subfolders were added without going through add_link() or add_set(), the ones that
+        // aren't in the order list come at the end.
+        let mut items = vec![];
+        let (mut link_index, mut set_index) = (0, 0);
+        for kind in self.order.iter() {
+            match kind {
+                BookmarkKind::Link if link_index < self.links.len() => {
+                    items.push(BookmarkItem::Link(&self.links[link_index]));
+                    link_index += 1;
+                },
+                BookmarkKind::Set if set_index < self.sets.len() => {
+                    items.push(BookmarkItem::Set(&self.sets[set_index]));
+                    set_index += 1;
+                },
+                BookmarkKind::Separator => items.push(BookmarkItem::Separator),
+                _ => {},
+            }
+        }
+        items.extend(self.links[link_index..].iter().map(BookmarkItem::Link));
+        items.extend(self.sets[set_index..].iter().map(|set| BookmarkItem::Set(set)));
+        items
+    }
+
+    pub fn to_bookmark_html(&self) -> String {
+        // The whole file in the format Chrome writes, which all of the major browsers can
+        // import. This set is the outer list and its name is the <H1> heading.
+        let mut lines = vec![
+            "<!DOCTYPE NETSCAPE-Bookmark-file-1>".to_string(),
+            "<!-- This is an automatically generated file.".to_string(),
+            "     It will be read and overwritten.".to_string(),
+            "     DO NOT EDIT! -->".to_string(),
+            "<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">".to_string(),
+            "<TITLE>Bookmarks</TITLE>".to_string(),
+            format!("<H1>{}</H1>", encode_html_entities(&self.name)),
+        ];
+        self.add_bookmark_html_lines(&mut lines, 0);
+        let mut text = lines.join("\n");
+        text.push('\n');
+        text
+    }
+
+    pub fn write_bookmark_html_r<P>(&self, path: P) -> Result<(), String>
+        where P: AsRef<path::Path>
+    {
+        file::write_file_atomic_r(path, &self.to_bookmark_html())
+    }
+
+    fn add_bookmark_html_lines(&self, lines: &mut Vec<String>, depth: usize) {
+        let indent = "    ".repeat(depth);
+        lines.push(format!("{}<DL><p>", indent));
+        for item in self.ordered_items() {
+            match item {
+                BookmarkItem::Link(link) => {
+                    let mut attributes = vec![("HREF".to_string(), link.url.clone())];
+                    attributes.extend(date_attributes(link.add_date, link.last_modified));
+                    if let Some(icon) = &link.icon {
+                        attributes.push(("ICON".to_string(), icon.clone()));
+                    }
+                    attributes.extend(link.attributes.iter().cloned());
+                    lines.push(format!("{}    <DT><A{}>{}</A>", indent, format_attributes(&attributes), encode_html_entities(&link.label)));
+                    if let Some(description) = &link.description {
+                        lines.push(format!("{}    <DD>{}", indent, encode_html_entities(description)));
+                    }
+                },
+                BookmarkItem::Set(set) => {
+                    let mut attributes = date_attributes(set.add_date, set.last_modified);
+                    attributes.extend(set.attributes.iter().cloned());
+                    lines.push(format!("{}    <DT><H3{}>{}</H3>", indent, format_attributes(&attributes), encode_html_entities(&set.name)));
+                    set.add_bookmark_html_lines(lines, depth + 1);
+                },
+                BookmarkItem::Separator => lines.push(format!("{}    <HR>", indent)),
+            }
+        }
+        lines.push(format!("{}</DL><p>", indent));
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BookmarkItem<'a> {
+    Link(&'a BookmarkLink),
+    Set(&'a BookmarkSet),
+    Separator,
+}
+
+fn remove_from_order(order: &mut Vec<BookmarkKind>, kind: BookmarkKind, index: usize) {
+    if let Some(position) = order.iter().enumerate().filter(|(_, k)| **k == kind).nth(index).map(|(position, _)| position) {
+        order.remove(position);
+    }
+}
+
+fn date_attributes(add_date: Option<i64>, last_modified: Option<i64>) -> Vec<(String, String)> {
+    let mut attributes = vec![];
+    if let Some(date) = add_date {
+        attributes.push(("ADD_DATE".to_string(), date.to_string()));
+    }
+    if let Some(date) = last_modified {
+        attributes.push(("LAST_MODIFIED".to_string(), date.to_string()));
+    }
+    attributes
+}
+
+fn format_attributes(attributes: &[(String, String)]) -> String {
+    attributes.iter().map(|(name, value)| format!(" {}=\"{}\"", name, encode_html_entities(value))).collect()
 }
 
 impl BookmarkLink {
-    fn new(url: &str, label: &str) -> Self {
-        assert!(url.len() > 0);
-        assert!(label.len() > 0);
+    pub fn new(url: &str, label: &str) -> Self {
         Self {
             label: label.to_string(),
             url: url.to_string(),
+            ..Default::default()
         }
     }
 }
 
 pub fn parse_chrome_bookmarks(path_file: &path::Path) -> BookmarkSet {
     assert!(path_file.is_file());
-    let lines: Vec<String> = fs::read_to_string(path_file)
-        .unwrap()
-        .split("\r\n")
-        .map(|line| line.to_string())
-        .collect();
-    parse_chrome_bookmarks_internal(&lines[..])
+    parse_bookmark_file_r(path_file).unwrap()
 }
 
 pub fn parse_chrome_bookmarks_internal(lines: &[String]) -> BookmarkSet {
-    let dt_h3 = "<DT><H3 ";
-    let dl_p = "</DL><p>";
-    let first_line = &lines[0];
-    let name = parse::before(first_line, "</H3>");
-    let name = parse::rafter(name, ">");
-    let indent = first_line.find(dt_h3).unwrap();
-    //bg!(&first_line, &name, &indent);
-
-    let mut b = BookmarkSet::new(name);
-
-    let last_line = lines.last().unwrap();
-    let expected_prefix = format!("{}{}", " ".repeat(indent), dl_p);
-    //bg!(&last_line, &expected_prefix);
-    assert!(last_line.starts_with(&expected_prefix));
-
-    let mut line_index = 2;
-    let spaces = " ".repeat(indent + 4);
-    let prefix_link = format!("{}<DT><A HREF=", spaces);
-    let prefix_nested_set_first = format!("{}{}", spaces, dt_h3);
-    let prefix_nested_set_last = format!("{}{}", spaces, dl_p);
-    while line_index < lines.len() - 1 {
-        let line = &lines[line_index];
-        //bg!(&line_index, &line);
-        if line.starts_with(&prefix_link) {
-            let url = parse::after(&line, "A HREF=\"");
-            let url = parse::before(url, "\"");
-            let label = parse::rbefore(&line, "</A>");
-            let label = parse::rafter(&label, ">");
-            //dbg!(&url, &label);
-            b.links.push(BookmarkLink::new(url, label));
-        } else if line.starts_with(&prefix_nested_set_first) {
-            let line_index_nested_start = line_index;
-            line_index += 2;
-            while !lines[line_index].starts_with(&prefix_nested_set_last) {
-                line_index += 1;
+    parse_bookmark_html_r(&lines.join("\n")).unwrap()
+}
+
+pub fn parse_bookmark_file_r<P>(path: P) -> Result<BookmarkSet, String>
+    where P: AsRef<path::Path>
+{
+    let text = file::read_file_to_string_r(&path)?;
+    err_context(parse_bookmark_html_r(&text), &format!("parse_bookmark_file_r: \"{}\":", file::path_name(&path)))
+}
+
+// A tag or a run of text between tags, with the line it starts on.
+#[derive(Debug)]
+enum HtmlToken {
+    Tag { name: String, closing: bool, attributes: Vec<(String, String)>, line: usize },
+    Text { text: String, line: usize },
+}
+
+pub fn parse_bookmark_html_r(text: &str) -> Result<BookmarkSet, String> {
+    // Parse the Netscape Bookmark File format written by Chrome, Firefox and Edge:
+    //
+    //     <H1>Bookmarks</H1>
+    //     <DL><p>
+    //         <DT><H3 ADD_DATE="1580000000" PERSONAL_TOOLBAR_FOLDER="true">Bookmarks bar</H3>
+    //         <DL><p>
+    //             <DT><A HREF="https://example.com/" ADD_DATE="1580000001">Example</A>
+    //             <HR>
+    //         </DL><p>
+    //     </DL><p>
+    //
+    // Only the tags matter, not the layout, so any indentation and line endings work. The
+    // returned set is the outer list, named after the <H1> heading. If the text starts with a
+    // folder heading instead, as with part of a file, the returned set is that folder.
+    let tokens = tokenize_html_r(text)?;
+    // Folders that have been opened with <DL> and not yet closed.
+    let mut stack: Vec<BookmarkSet> = vec![];
+    // A folder heading that hasn't been followed by its <DL> yet. If something else comes first
+    // the folder is empty.
+    let mut pending_set: Option<BookmarkSet> = None;
+    let mut root_name = "Bookmarks".to_string();
+    // The element whose text is being collected, such as "A" for a link's label.
+    let mut capture: Option<String> = None;
+    let mut captured = String::new();
+    let mut pending_link: Option<BookmarkLink> = None;
+    // A <DD> description belongs to the link just before it. Firefox also writes them for
+    // folders, but those are skipped.
+    let mut after_link = false;
+    let mut in_description = false;
+    let mut result = None;
+    let mut last_line = 1;
+    for token in tokens {
+        match token {
+            HtmlToken::Text { text, line } => {
+                last_line = line;
+                if capture.is_some() {
+                    captured.push_str(&text);
+                } else if in_description && !text.trim().is_empty() {
+                    let description = decode_html_entities(text.trim());
+                    let target = stack.last_mut().and_then(|set| set.links.last_mut());
+                    if let Some(link) = target {
+                        link.description = Some(description);
+                    }
+                }
+            },
+            HtmlToken::Tag { name, closing, attributes, line } => {
+                last_line = line;
+                let err_line = |msg: String| Err(format!("Line {}: {}", line, msg));
+                if closing {
+                    if capture.as_deref() == Some(name.as_str()) {
+                        let text = decode_html_entities(&collapse_whitespace(&captured));
+                        captured.clear();
+                        capture = None;
+                        match name.as_str() {
+                            "A" => {
+                                let mut link = match pending_link.take() {
+                                    Some(link) => link,
+                                    None => return err_line("</A> without a matching <A>.".to_string()),
+                                };
+                                link.label = text;
+                                after_link = true;
+                                match stack.last_mut() {
+                                    Some(set) => set.add_link(link),
+                                    None => return err_line(format!("The link \"{}\" isn't inside a <DL> list.", link.label)),
+                                }
+                            },
+                            "H3" => match pending_set.as_mut() {
+                                Some(set) => set.name = text,
+                                None => return err_line("</H3> without a matching <H3>.".to_string()),
+                            },
+                            "H1" => root_name = text,
+                            _ => {},
+                        }
+                        continue;
+                    }
+                    if let Some(element) = &capture {
+                        // Closing tags inside a label, like </b>, are ignored, but the end of a
+                        // list means the label was never closed.
+                        if name == "DL" {
+                            return err_line(format!("<{}> isn't closed before </DL>.", element));
+                        }
+                        continue;
+                    }
+                    if name == "DL" {
+                        close_pending_set(&mut stack, &mut pending_set);
+                        let set = match stack.pop() {
+                            Some(set) => set,
+                            None => return err_line("</DL> without a matching <DL>.".to_string()),
+                        };
+                        match stack.last_mut() {
+                            Some(parent) => parent.add_set(set),
+                            None => {
+                                result = Some(set);
+                                break;
+                            },
+                        }
+                    }
+                    continue;
+                }
+                if capture.is_some() {
+                    // Tags inside a label, like <b>, are ignored along with their attributes.
+                    continue;
+                }
+                if name != "P" {
+                    in_description = name == "DD" && after_link;
+                    after_link = false;
+                }
+                match name.as_str() {
+                    "DL" => {
+                        let set = match pending_set.take() {
+                            Some(set) => set,
+                            None if stack.is_empty() => BookmarkSet { name: root_name.clone(), ..Default::default() },
+                            None => return err_line("<DL> without a folder heading before it.".to_string()),
+                        };
+                        stack.push(set);
+                    },
+                    "H3" => {
+                        close_pending_set(&mut stack, &mut pending_set);
+                        let mut set = BookmarkSet::default();
+                        for (name, value) in attributes {
+                            match (name.as_str(), value.parse::<i64>()) {
+                                ("ADD_DATE", Ok(date)) => set.add_date = Some(date),
+                                ("LAST_MODIFIED", Ok(date)) => set.last_modified = Some(date),
+                                _ => set.attributes.push((name, value)),
+                            }
+                        }
+                        pending_set = Some(set);
+                        capture = Some(name);
+                    },
+                    "A" => {
+                        close_pending_set(&mut stack, &mut pending_set);
+                        let mut link = BookmarkLink::default();
+                        let mut has_url = false;
+                        for (name, value) in attributes {
+                            match (name.as_str(), value.parse::<i64>()) {
+                                ("HREF", _) => {
+                                    link.url = value;
+                                    has_url = true;
+                                },
+                                ("ADD_DATE", Ok(date)) => link.add_date = Some(date),
+                                ("LAST_MODIFIED", Ok(date)) => link.last_modified = Some(date),
+                                ("ICON", _) => link.icon = Some(value),
+                                _ => link.attributes.push((name, value)),
+                            }
+                        }
+                        if !has_url {
+                            return err_line("A link has no HREF attribute.".to_string());
+                        }
+                        pending_link = Some(link);
+                        capture = Some(name);
+                    },
+                    "H1" | "TITLE" => capture = Some(name),
+                    "HR" => {
+                        close_pending_set(&mut stack, &mut pending_set);
+                        match stack.last_mut() {
+                            Some(set) => set.add_separator(),
+                            None => return err_line("A separator isn't inside a <DL> list.".to_string()),
+                        }
+                    },
+                    _ => {},
+                }
+            },
+        }
+    }
+    if let Some(element) = capture {
+        return Err(format!("Line {}: The <{}> element is never closed.", last_line, element));
+    }
+    match (result, stack.last()) {
+        (Some(set), _) => Ok(set),
+        (None, Some(set)) => Err(format!("Line {}: Missing </DL> at the end of the folder \"{}\".", last_line, set.name)),
+        (None, None) => Err("No bookmark list (<DL>) was found.".to_string()),
+    }
+}
+
+fn close_pending_set(stack: &mut [BookmarkSet], pending_set: &mut Option<BookmarkSet>) {
+    // A folder heading with no <DL> after it is an empty folder.
+    if let Some(set) = pending_set.take() {
+        if let Some(parent) = stack.last_mut() {
+            parent.add_set(set);
+        }
+    }
+}
+
+fn tokenize_html_r(text: &str) -> Result<Vec<HtmlToken>, String> {
+    // Tag and attribute names are returned in uppercase and attribute values are decoded.
+    // Comments and declarations like <!DOCTYPE> are skipped.
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut pos = 0;
+    let count_lines = |text: &str| text.matches('\n').count() + text.matches('\r').count() - text.matches("\r\n").count();
+    while pos < text.len() {
+        let rest = &text[pos..];
+        if !rest.starts_with('<') {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = &rest[..end];
+            tokens.push(HtmlToken::Text { text: text.to_string(), line });
+            line += count_lines(text);
+            pos += end;
+            continue;
+        }
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").ok_or_else(|| format!("Line {}: The comment is never closed.", line))?;
+            line += count_lines(&rest[..end]);
+            pos += end + 3;
+            continue;
+        }
+        let end = find_tag_end(rest).ok_or_else(|| format!("Line {}: The tag is never closed with \">\".", line))?;
+        let tag = &rest[1..end];
+        if !tag.starts_with('!') && !tag.starts_with('?') {
+            let closing = tag.starts_with('/');
+            let tag = tag.trim_start_matches('/').trim_end_matches('/');
+            let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+            let name = tag[..name_end].to_uppercase();
+            if name.is_empty() {
+                return Err(format!("Line {}: A tag has no name.", line));
+            }
+            let attributes = parse_attributes_r(&tag[name_end..]).map_err(|msg| format!("Line {}: {}", line, msg))?;
+            tokens.push(HtmlToken::Tag { name, closing, attributes, line });
+        }
+        line += count_lines(&rest[..end]);
+        pos += end + 1;
+    }
+    Ok(tokens)
+}
+
+fn find_tag_end(text: &str) -> Option<usize> {
+    // The ">" that ends the tag starting the text, skipping any inside quoted attribute values.
+    let mut quote = None;
+    for (index, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>' => return Some(index),
+            None if c == '<' && index > 0 => return None,
+            None => {},
+        }
+    }
+    None
+}
+
+fn parse_attributes_r(text: &str) -> Result<Vec<(String, String)>, String> {
+    // Attributes like NAME="value", NAME='value', NAME=value or just NAME.
+    let mut attributes = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let name_end = rest.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(rest.len());
+        let name = rest[..name_end].to_uppercase();
+        rest = rest[name_end..].trim_start();
+        let value = if let Some(after_equals) = rest.strip_prefix('=') {
+            let after_equals = after_equals.trim_start();
+            match after_equals.chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => {
+                    let close = after_equals[1..].find(quote).ok_or_else(|| format!("The value of {} has no closing quote.", name))?;
+                    rest = &after_equals[close + 2..];
+                    &after_equals[1..close + 1]
+                },
+                _ => {
+                    let end = after_equals.find(char::is_whitespace).unwrap_or(after_equals.len());
+                    rest = &after_equals[end..];
+                    &after_equals[..end]
+                },
             }
-            b.sets.push(Box::new(parse_chrome_bookmarks_internal(&lines[line_index_nested_start..line_index + 1])));
         } else {
-            panic!("Unexpected line: {}", line);
+            ""
+        };
+        attributes.push((name, decode_html_entities(value)));
+        rest = rest.trim_start();
+    }
+    Ok(attributes)
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn encode_html_entities(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub fn decode_html_entities(text: &str) -> String {
+    // Named entities that browsers use in bookmark files plus numeric ones like "&#39;" and
+    // "&#x27;". Anything that isn't a recognized entity is left as it is.
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let entity = rest.find(';').filter(|end| *end <= 10).map(|end| &rest[1..end]);
+        let c = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                    u32::from_str_radix(hex, 16).ok()
+                } else {
+                    entity.strip_prefix('#').and_then(|decimal| decimal.parse::<u32>().ok())
+                };
+                code.and_then(char::from_u32)
+            },
+        });
+        match (c, entity) {
+            (Some(c), Some(entity)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            },
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            },
         }
-        line_index += 1;
     }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bookmark_html_r() {
+        let text = "<!DOCTYPE NETSCAPE-Bookmark-file-1>\r\n<!-- This is an automatically generated file.\r\n     It will be read and overwritten. -->\r\n\
+            <META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">\r\n<TITLE>Bookmarks</TITLE>\r\n<H1>Bookmarks Menu</H1>\r\n\
+            <DL><p>\r\n\
+            \t<DT><H3 ADD_DATE=\"1580000000\" LAST_MODIFIED=\"1580000100\" PERSONAL_TOOLBAR_FOLDER=\"true\">Bookmarks bar</H3>\r\n\
+            \t<DL><p>\r\n\
+            \t\t<DT><A HREF=\"https://example.com/?a=1&amp;b=2\" ADD_DATE=\"1580000001\" ICON=\"data:image/png;base64,AAA\">Tom &amp; Jerry&#39;s\r\n  page</A>\r\n\
+            \t\t<DD>A description &lt;here&gt;\r\n\
+            \t\t<HR>\r\n\
+            \t\t<DT><H3>Empty</H3>\r\n\
+            \t\t<DT><H3>Also empty</H3>\r\n\
+            \t\t<DL><p>\r\n\
+            \t\t</DL><p>\r\n\
+            \t\t<DT><a href='https://rust-lang.org' tags=rust>Rust</a>\r\n\
+            \t</DL><p>\r\n\
+            \t<DT><A HREF=\"https://b.com\"></A>\r\n\
+            </DL><p>\r\n";
+        let root = parse_bookmark_html_r(text).unwrap();
+        assert_eq!("Bookmarks Menu", root.name);
+        assert_eq!(vec![BookmarkKind::Set, BookmarkKind::Link], root.order);
+        assert_eq!("", root.links[0].label);
+        let bar = &root.sets[0];
+        assert_eq!("Bookmarks bar", bar.name);
+        assert_eq!((Some(1580000000), Some(1580000100)), (bar.add_date, bar.last_modified));
+        assert_eq!(vec![("PERSONAL_TOOLBAR_FOLDER".to_string(), "true".to_string())], bar.attributes);
+        assert_eq!(vec![BookmarkKind::Link, BookmarkKind::Separator, BookmarkKind::Set, BookmarkKind::Set, BookmarkKind::Link], bar.order);
+        let link = &bar.links[0];
+        assert_eq!("https://example.com/?a=1&b=2", link.url);
+        assert_eq!("Tom & Jerry's page", link.label);
+        assert_eq!(Some(1580000001), link.add_date);
+        assert_eq!(Some("data:image/png;base64,AAA".to_string()), link.icon);
+        assert_eq!(Some("A description <here>".to_string()), link.description);
+        assert_eq!(vec!["Empty", "Also empty"], bar.sets.iter().map(|set| set.name.as_str()).collect::<Vec<_>>());
+        assert!(bar.sets.iter().all(|set| set.links.is_empty() && set.sets.is_empty()));
+        assert_eq!(vec![("TAGS".to_string(), "rust".to_string())], bar.links[1].attributes);
+        assert_eq!(3, root.link_count_deep());
+        assert_eq!(3, root.set_count_deep());
 
-    b
+        // The same file with Unix line endings and no indentation, and a fragment starting with
+        // a folder as read by parse_chrome_bookmarks_internal().
+        let unix = text.replace("\r\n", "\n").replace('\t', "");
+        assert_eq!(root, parse_bookmark_html_r(&unix).unwrap());
+        let lines = ["<DT><H3>Folder</H3>", "<DL><p>", "<DT><A HREF=\"https://a.com\">A</A>", "</DL><p>"].iter().map(|line| line.to_string()).collect::<Vec<_>>();
+        let folder = parse_chrome_bookmarks_internal(&lines);
+        assert_eq!("Folder", folder.name);
+        assert_eq!("https://a.com", folder.links[0].url);
+    }
+
+    #[test]
+    fn test_parse_bookmark_html_r_errors() {
+        assert_eq!("Line 3: Missing </DL> at the end of the folder \"F\".", parse_bookmark_html_r("<DL><p>\n<DT><H3>F</H3><DL><p>\n<DT><A HREF=\"x\">x</A>\n").unwrap_err());
+        assert_eq!("Line 2: A link has no HREF attribute.", parse_bookmark_html_r("<DL><p>\n<DT><A>x</A>\n</DL>").unwrap_err());
+        assert_eq!("Line 2: The tag is never closed with \">\".", parse_bookmark_html_r("<DL><p>\n<DT><A HREF=\"x\"\n</DL>").unwrap_err());
+        assert_eq!("Line 1: </DL> without a matching <DL>.", parse_bookmark_html_r("</DL>").unwrap_err());
+        assert_eq!("No bookmark list (<DL>) was found.", parse_bookmark_html_r("<H1>Bookmarks</H1>").unwrap_err());
+        assert_eq!("Line 1: <H3> isn't closed before </DL>.", parse_bookmark_html_r("<DL><p><DT><H3>A</H3><DL><p><DT><H3>F</DL></H3></DL>").unwrap_err());
+        assert_eq!("Line 1: <A> isn't closed before </DL>.", parse_bookmark_html_r("<DL><p><DT><A HREF=\"x\">x</DL></A>").unwrap_err());
+    }
+
+    #[test]
+    fn test_to_bookmark_html() {
+        let mut bar = BookmarkSet { name: "Bookmarks bar".to_string(), add_date: Some(100), attributes: vec![("PERSONAL_TOOLBAR_FOLDER".to_string(), "true".to_string())], ..Default::default() };
+        bar.add_link(BookmarkLink { icon: Some("data:x".to_string()), description: Some("<d>".to_string()), ..BookmarkLink::new("https://a.com/?x=1&y=2", "A & \"B\"") });
+        bar.add_separator();
+        bar.add_set(BookmarkSet::new("Empty"));
+        bar.add_link(BookmarkLink::new("https://c.com", "C"));
+        let mut root = BookmarkSet::new("Bookmarks");
+        root.add_set(bar);
+        // A link added directly goes after everything in the order list.
+        root.links.push(BookmarkLink::new("https://d.com", "D"));
+        let html = root.to_bookmark_html();
+        let expected = "<H1>Bookmarks</H1>
+<DL><p>
+    <DT><H3 ADD_DATE=\"100\" PERSONAL_TOOLBAR_FOLDER=\"true\">Bookmarks bar</H3>
+    <DL><p>
+        <DT><A HREF=\"https://a.com/?x=1&amp;y=2\" ICON=\"data:x\">A &amp; &quot;B&quot;</A>
+        <DD>&lt;d&gt;
+        <HR>
+        <DT><H3>Empty</H3>
+        <DL><p>
+        </DL><p>
+        <DT><A HREF=\"https://c.com\">C</A>
+    </DL><p>
+    <DT><A HREF=\"https://d.com\">D</A>
+</DL><p>
+";
+        assert!(html.starts_with("<!DOCTYPE NETSCAPE-Bookmark-file-1>\n"));
+        assert!(html.ends_with(expected));
+
+        // Reading the file back gives the same bookmarks, except that the link added directly is
+        // now in the order list.
+        root.order.push(BookmarkKind::Link);
+        assert_eq!(root, parse_bookmark_html_r(&html).unwrap());
+        let removed = root.sets[0].remove_set(0);
+        assert_eq!("Empty", removed.name);
+        assert_eq!(vec![BookmarkKind::Link, BookmarkKind::Separator, BookmarkKind::Link], root.sets[0].order);
+        root.sets[0].remove_link(0);
+        assert_eq!(vec![BookmarkKind::Separator, BookmarkKind::Link], root.sets[0].order);
+    }
+
+    #[test]
+    fn test_decode_html_entities() {
+        assert_eq!("a & b < c > \"d\" 'e' \u{a0}", decode_html_entities("a &amp; b &lt; c &gt; &quot;d&quot; &#39;e&#x27; &nbsp;"));
+        assert_eq!("AT&T &unknown; & ;", decode_html_entities("AT&T &unknown; &amp; ;"));
+    }
 }
@@ -0,0 +1,336 @@
+// Conversion between a BookmarkSet and Chrome's "Bookmarks" file, the JSON file in the profile
+// folder where Chrome (and Edge, which uses the same format) keeps its bookmarks:
+//
+//     {
+//        "roots": {
+//           "bookmark_bar": { "children": [ ... ], "name": "Bookmarks bar", "type": "folder", ... },
+//           "other": { "children": [ ... ], "name": "Other bookmarks", "type": "folder", ... },
+//           "synced": { "children": [ ... ], "name": "Mobile bookmarks", "type": "folder", ... }
+//        },
+//        "version": 1
+//     }
+//
+// The set is laid out the way Chrome's own HTML export does it: an outer set holding the
+// bookmarks bar, marked with PERSONAL_TOOLBAR_FOLDER, followed by the other bookmarks folder and
+// the mobile bookmarks folder if they have anything in them. Chrome stores times as microseconds
+// since 1601, which are converted to and from the Unix seconds used in the HTML format.
+//
+// The written file has no checksum, which Chrome accepts, and new IDs. Separators are dropped
+// since Chrome has no such thing. Edit the file only while the browser is closed, since it writes
+// over the file when it exits.
+
+use std::path::Path;
+
+use serde_json::{json, Map, Value};
+
+use crate::*;
+use crate::file::{path_name, read_file_to_string_r, write_file_atomic_r};
+use crate::html::{BookmarkItem, BookmarkLink, BookmarkSet};
+
+const CHROME_EPOCH_OFFSET_SECONDS: i64 = 11_644_473_600;
+const TOOLBAR_ATTRIBUTE: &str = "PERSONAL_TOOLBAR_FOLDER";
+const OTHER_BOOKMARKS: &str = "Other bookmarks";
+const MOBILE_BOOKMARKS: &str = "Mobile bookmarks";
+
+pub fn parse_chrome_json_r(text: &str) -> Result<BookmarkSet, String> {
+    let value: Value = rse!(serde_json::from_str(text.trim_start_matches('\u{feff}')))?;
+    let roots = value.get("roots").and_then(Value::as_object).ok_or("parse_chrome_json_r: No \"roots\" object found.")?;
+    let mut set = BookmarkSet::new("Bookmarks");
+    for (key, always_include) in [("bookmark_bar", true), ("other", false), ("synced", false)] {
+        if let Some(node) = roots.get(key) {
+            let mut folder = err_context(folder_from_json_r(node), &format!("parse_chrome_json_r: \"{}\":", key))?;
+            if key == "bookmark_bar" {
+                folder.attributes.push((TOOLBAR_ATTRIBUTE.to_string(), "true".to_string()));
+            }
+            if always_include || !folder.order.is_empty() {
+                set.add_set(folder);
+            }
+        }
+    }
+    Ok(set)
+}
+
+pub fn read_chrome_json_r<P>(path: P) -> Result<BookmarkSet, String>
+    where P: AsRef<Path>
+{
+    let text = read_file_to_string_r(&path)?;
+    err_context(parse_chrome_json_r(&text), &format!("read_chrome_json_r: \"{}\":", path_name(&path)))
+}
+
+pub fn chrome_json(set: &BookmarkSet) -> String {
+    // The bookmarks bar is the folder marked as the toolbar, or else the first folder, and the
+    // folders named "Other bookmarks" and "Mobile bookmarks" go to their own roots. Everything
+    // else at the top level goes into the other bookmarks.
+    let mut next_id = 1;
+    let toolbar_index = set.sets.iter().position(|folder| folder.is_toolbar_folder()).or(if set.sets.is_empty() { None } else { Some(0) });
+    let other_index = set.sets.iter().position(|folder| folder.name == OTHER_BOOKMARKS).filter(|index| Some(*index) != toolbar_index);
+    let mobile_index = set.sets.iter().position(|folder| folder.name == MOBILE_BOOKMARKS).filter(|index| Some(*index) != toolbar_index);
+
+    let empty_toolbar = BookmarkSet::new("Bookmarks bar");
+    let toolbar = toolbar_index.map_or(&empty_toolbar, |index| set.sets[index].as_ref());
+    let bookmark_bar = folder_to_json(toolbar, &toolbar.ordered_items(), &mut next_id);
+
+    let mut other_items = match other_index {
+        Some(index) => set.sets[index].ordered_items(),
+        None => vec![],
+    };
+    let mut set_index = 0;
+    for item in set.ordered_items() {
+        if let BookmarkItem::Set(_) = item {
+            set_index += 1;
+            let index = Some(set_index - 1);
+            if index == toolbar_index || index == other_index || index == mobile_index {
+                continue;
+            }
+        }
+        other_items.push(item);
+    }
+    let other_folder = match other_index {
+        Some(index) => set.sets[index].as_ref().clone(),
+        None => BookmarkSet::new(OTHER_BOOKMARKS),
+    };
+    let other = folder_to_json(&other_folder, &other_items, &mut next_id);
+
+    let mobile_folder = match mobile_index {
+        Some(index) => set.sets[index].as_ref().clone(),
+        None => BookmarkSet::new(MOBILE_BOOKMARKS),
+    };
+    let synced = folder_to_json(&mobile_folder, &mobile_folder.ordered_items(), &mut next_id);
+
+    let value = json!({
+        "roots": {
+            "bookmark_bar": bookmark_bar,
+            "other": other,
+            "synced": synced,
+        },
+        "version": 1,
+    });
+    // Chrome indents its file by three spaces where serde_json uses two. Newlines inside strings
+    // are escaped so every leading space is indentation.
+    serde_json::to_string_pretty(&value).unwrap().lines()
+        .map(|line| {
+            let indent = line.len() - line.trim_start_matches(' ').len();
+            format!("{}{}\n", " ".repeat(indent / 2 * 3), &line[indent..])
+        })
+        .collect()
+}
+
+pub fn write_chrome_json_r<P>(set: &BookmarkSet, path: P) -> Result<(), String>
+    where P: AsRef<Path>
+{
+    write_file_atomic_r(path, &chrome_json(set))
+}
+
+pub fn chrome_time_to_unix_seconds(value: &str) -> Option<i64> {
+    // Zero or a missing time means none was recorded.
+    let microseconds = value.trim().parse::<i64>().ok().filter(|microseconds| *microseconds > 0)?;
+    Some(microseconds / 1_000_000 - CHROME_EPOCH_OFFSET_SECONDS)
+}
+
+pub fn unix_seconds_to_chrome_time(seconds: i64) -> Option<String> {
+    // None if the time is too far out to represent, as with an HTML file whose ADD_DATE values
+    // are in microseconds rather than seconds.
+    seconds.checked_add(CHROME_EPOCH_OFFSET_SECONDS)
+        .and_then(|seconds| seconds.checked_mul(1_000_000))
+        .map(|microseconds| microseconds.to_string())
+}
+
+fn folder_from_json_r(node: &Value) -> Result<BookmarkSet, String> {
+    let mut folder = BookmarkSet {
+        name: json_str(node, "name"),
+        add_date: node.get("date_added").and_then(Value::as_str).and_then(chrome_time_to_unix_seconds),
+        last_modified: node.get("date_modified").and_then(Value::as_str).and_then(chrome_time_to_unix_seconds),
+        ..Default::default()
+    };
+    let children = match node.get("children") {
+        Some(children) => children.as_array().ok_or_else(|| format!("The \"children\" of \"{}\" isn't an array.", folder.name))?.clone(),
+        None => vec![],
+    };
+    for child in children.iter() {
+        match child.get("type").and_then(Value::as_str) {
+            Some("url") => folder.add_link(BookmarkLink {
+                url: json_str(child, "url"),
+                label: json_str(child, "name"),
+                add_date: child.get("date_added").and_then(Value::as_str).and_then(chrome_time_to_unix_seconds),
+                ..Default::default()
+            }),
+            Some("folder") => folder.add_set(folder_from_json_r(child)?),
+            other => return Err(format!("Unknown bookmark type {:?} in \"{}\".", other, folder.name)),
+        }
+    }
+    Ok(folder)
+}
+
+fn folder_to_json(folder: &BookmarkSet, items: &[BookmarkItem], next_id: &mut usize) -> Value {
+    let id = take_id(next_id);
+    let mut children = vec![];
+    for item in items {
+        match item {
+            BookmarkItem::Link(link) => {
+                let mut node = Map::new();
+                node.insert("date_added".to_string(), json!(chrome_time(link.add_date)));
+                node.insert("id".to_string(), json!(take_id(next_id)));
+                node.insert("name".to_string(), json!(link.label));
+                node.insert("type".to_string(), json!("url"));
+                node.insert("url".to_string(), json!(link.url));
+                children.push(Value::Object(node));
+            },
+            BookmarkItem::Set(set) => children.push(folder_to_json(set, &set.ordered_items(), next_id)),
+            BookmarkItem::Separator => {},
+        }
+    }
+    json!({
+        "children": children,
+        "date_added": chrome_time(folder.add_date),
+        "date_modified": chrome_time(folder.last_modified),
+        "id": id,
+        "name": folder.name,
+        "type": "folder",
+    })
+}
+
+fn take_id(next_id: &mut usize) -> String {
+    let id = next_id.to_string();
+    *next_id += 1;
+    id
+}
+
+fn chrome_time(seconds: Option<i64>) -> String {
+    // Chrome writes "0" for a time it doesn't have.
+    seconds.and_then(unix_seconds_to_chrome_time).unwrap_or_else(|| "0".to_string())
+}
+
+fn json_str(node: &Value, key: &str) -> String {
+    node.get(key).and_then(Value::as_str).unwrap_or("").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::parse_bookmark_html_r;
+
+    const CHROME_JSON: &str = r#"{
+   "checksum": "0123456789abcdef",
+   "roots": {
+      "bookmark_bar": {
+         "children": [ {
+            "date_added": "13244000000000000",
+            "guid": "a",
+            "id": "5",
+            "name": "Example & Co",
+            "type": "url",
+            "url": "https://example.com/"
+         }, {
+            "children": [ {
+               "date_added": "0",
+               "id": "7",
+               "name": "Rust",
+               "type": "url",
+               "url": "https://rust-lang.org/"
+            } ],
+            "date_added": "13244000001000000",
+            "date_modified": "13244000002000000",
+            "id": "6",
+            "name": "Dev",
+            "type": "folder"
+         } ],
+         "date_added": "13244000000000000",
+         "date_modified": "0",
+         "id": "1",
+         "name": "Bookmarks bar",
+         "type": "folder"
+      },
+      "other": {
+         "children": [ {
+            "date_added": "13244000003000000",
+            "id": "8",
+            "name": "News",
+            "type": "url",
+            "url": "https://news.example.com/"
+         } ],
+         "date_added": "0",
+         "date_modified": "0",
+         "id": "2",
+         "name": "Other bookmarks",
+         "type": "folder"
+      },
+      "synced": {
+         "children": [  ],
+         "date_added": "0",
+         "date_modified": "0",
+         "id": "3",
+         "name": "Mobile bookmarks",
+         "type": "folder"
+      }
+   },
+   "version": 1
+}
+"#;
+
+    #[test]
+    fn test_parse_chrome_json_r() {
+        let set = parse_chrome_json_r(CHROME_JSON).unwrap();
+        assert_eq!(vec!["Bookmarks bar", "Other bookmarks"], set.sets.iter().map(|folder| folder.name.as_str()).collect::<Vec<_>>());
+        let bar = &set.sets[0];
+        assert!(bar.is_toolbar_folder());
+        assert_eq!(Some(1_599_526_400), bar.add_date);
+        assert_eq!(None, bar.last_modified);
+        assert_eq!("Example & Co", bar.links[0].label);
+        assert_eq!(Some(1_599_526_400), bar.links[0].add_date);
+        assert_eq!(Some(1_599_526_402), bar.sets[0].last_modified);
+        assert_eq!(None, bar.sets[0].links[0].add_date);
+        assert_eq!(3, set.link_count_deep());
+
+        assert!(parse_chrome_json_r("{}").unwrap_err().contains("roots"));
+        assert!(parse_chrome_json_r("{").is_err());
+        let bad_type = CHROME_JSON.replace("\"type\": \"url\",\n            \"url\": \"https://example.com/\"", "\"type\": \"widget\"");
+        assert!(parse_chrome_json_r(&bad_type).unwrap_err().starts_with("parse_chrome_json_r: \"bookmark_bar\": Unknown bookmark type Some(\"widget\")"));
+    }
+
+    #[test]
+    fn test_chrome_json_round_trip() {
+        // JSON to a set and back gives the same bookmarks, and so does going through the HTML
+        // format in between.
+        let set = parse_chrome_json_r(CHROME_JSON).unwrap();
+        let json = chrome_json(&set);
+        assert!(json.starts_with("{\n   \"roots\": {\n      \"bookmark_bar\": {\n         \"children\": ["));
+        assert_eq!(set, parse_chrome_json_r(&json).unwrap());
+        let from_html = parse_bookmark_html_r(&set.to_bookmark_html()).unwrap();
+        assert_eq!(set, from_html);
+        assert_eq!(json, chrome_json(&from_html));
+
+        // Top-level links and folders other than the bookmarks bar go into the other bookmarks,
+        // and separators are dropped.
+        let mut loose = BookmarkSet::new("Bookmarks");
+        let mut bar = BookmarkSet::new("Toolbar");
+        bar.add_link(BookmarkLink::new("https://a.com", "A"));
+        bar.add_separator();
+        loose.add_set(bar);
+        loose.add_link(BookmarkLink::new("https://b.com", "B"));
+        loose.add_set(BookmarkSet::new("Loose folder"));
+        let converted = parse_chrome_json_r(&chrome_json(&loose)).unwrap();
+        assert_eq!("Toolbar", converted.sets[0].name);
+        assert_eq!(1, converted.sets[0].order.len());
+        let other = &converted.sets[1];
+        assert_eq!("Other bookmarks", other.name);
+        assert_eq!("B", other.links[0].label);
+        assert_eq!("Loose folder", other.sets[0].name);
+    }
+
+    #[test]
+    fn test_chrome_time() {
+        assert_eq!(Some(0), chrome_time_to_unix_seconds("11644473600000000"));
+        assert_eq!(None, chrome_time_to_unix_seconds("0"));
+        assert_eq!(Some("13244000000000000".to_string()), unix_seconds_to_chrome_time(1_599_526_400));
+        assert_eq!(None, unix_seconds_to_chrome_time(1_599_526_400_000_000));
+        assert_eq!(None, unix_seconds_to_chrome_time(i64::MAX));
+
+        // A date in microseconds from an HTML file is left out rather than overflowing.
+        let mut set = BookmarkSet::new("Bookmarks");
+        let mut bar = BookmarkSet::new("Bookmarks bar");
+        bar.add_link(BookmarkLink { add_date: Some(1_599_526_400_000_000), ..BookmarkLink::new("https://a.com", "A") });
+        set.add_set(bar);
+        let converted = parse_chrome_json_r(&chrome_json(&set)).unwrap();
+        assert_eq!(None, converted.sets[0].links[0].add_date);
+    }
+}
@@ -0,0 +1,425 @@
+// Managing a BookmarkSet after it's been parsed: finding and removing duplicate links, merging
+// one set of bookmarks into another, removing empty folders, and comparing two exports of the
+// same bookmarks taken at different times. Links are compared by their URLs after normalizing
+// them with normalize_bookmark_url(), so "http://example.com/" and
+// "https://example.com?utm_source=feed" count as the same bookmark. The results can be written
+// out with BookmarkSet::write_bookmark_html_r() and imported into a browser.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::html::{BookmarkItem, BookmarkLink, BookmarkSet};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BookmarkLocation {
+    // The path from the outer set to the folder holding the link, both as indexes into each
+    // folder's sets and as folder names. The outer set itself isn't included.
+    pub set_indexes: Vec<usize>,
+    pub folder_names: Vec<String>,
+    // The index of the link in its folder's links.
+    pub index: usize,
+    pub link: BookmarkLink,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DuplicateLinks {
+    pub normalized_url: String,
+    // Every place the link appears, in the order they appear in the file.
+    pub locations: Vec<BookmarkLocation>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MergeSummary {
+    pub links_added: usize,
+    pub sets_added: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BookmarkDiff {
+    pub added: Vec<BookmarkLocation>,
+    pub removed: Vec<BookmarkLocation>,
+    // Links found in both sets but in different folders, as (old, new).
+    pub moved: Vec<(BookmarkLocation, BookmarkLocation)>,
+    // Links found in both sets whose labels changed, as (old, new).
+    pub relabeled: Vec<(BookmarkLocation, BookmarkLocation)>,
+}
+
+impl BookmarkLocation {
+    pub fn folder_path(&self) -> String {
+        if self.folder_names.is_empty() {
+            "(top level)".to_string()
+        } else {
+            self.folder_names.join(" > ")
+        }
+    }
+
+    pub fn description(&self) -> String {
+        format!("{}: \"{}\" <{}>", self.folder_path(), self.link.label, self.link.url)
+    }
+}
+
+impl BookmarkDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.moved.is_empty() && self.relabeled.is_empty()
+    }
+
+    pub fn report_lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        lines.extend(self.added.iter().map(|location| format!("Added: {}", location.description())));
+        lines.extend(self.removed.iter().map(|location| format!("Removed: {}", location.description())));
+        lines.extend(self.moved.iter().map(|(old, new)| format!("Moved: \"{}\" <{}>: {} -> {}", new.link.label, new.link.url, old.folder_path(), new.folder_path())));
+        lines.extend(self.relabeled.iter().map(|(old, new)| format!("Relabeled: <{}>: \"{}\" -> \"{}\"", new.link.url, old.link.label, new.link.label)));
+        lines
+    }
+
+    pub fn to_bookmark_set(&self, name: &str) -> BookmarkSet {
+        // The changes as bookmarks, with a folder for each kind of change, so that they can be
+        // looked over in a browser. Moved and relabeled links are shown as they are in the newer
+        // set.
+        let mut set = BookmarkSet::new(name);
+        let categories = [
+            ("Added", self.added.iter().collect::<Vec<_>>()),
+            ("Removed", self.removed.iter().collect()),
+            ("Moved", self.moved.iter().map(|(_, new)| new).collect()),
+            ("Relabeled", self.relabeled.iter().map(|(_, new)| new).collect()),
+        ];
+        for (category, locations) in categories {
+            if !locations.is_empty() {
+                let mut folder = BookmarkSet::new(category);
+                for location in locations {
+                    folder.add_link(location.link.clone());
+                }
+                set.add_set(folder);
+            }
+        }
+        set
+    }
+}
+
+pub fn normalize_bookmark_url(url: &str) -> String {
+    // Lowercase the scheme and host, treat http the same as https, drop default ports, tracking
+    // parameters like utm_source, and trailing slashes on the path. The fragment is kept since
+    // some sites use it to tell pages apart.
+    let url = url.trim();
+    let (scheme, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (scheme.to_lowercase(), rest),
+        None => return url.to_string(),
+    };
+    let scheme = if scheme == "http" { "https".to_string() } else { scheme };
+    let (rest, fragment) = match rest.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (rest, None),
+    };
+    let (rest, query) = match rest.split_once('?') {
+        Some((rest, query)) => (rest, Some(query)),
+        None => (rest, None),
+    };
+    let (host, path) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, ""),
+    };
+    let host = host.to_lowercase();
+    let host = host.strip_suffix(":80").or_else(|| host.strip_suffix(":443")).unwrap_or(&host);
+    let mut normalized = format!("{}://{}{}", scheme, host, path.trim_end_matches('/'));
+    if let Some(query) = query {
+        let params = query.split('&')
+            .filter(|param| !param.is_empty() && !param.to_lowercase().starts_with("utm_"))
+            .collect::<Vec<_>>();
+        if !params.is_empty() {
+            normalized.push('?');
+            normalized.push_str(&params.join("&"));
+        }
+    }
+    if let Some(fragment) = fragment {
+        normalized.push('#');
+        normalized.push_str(fragment);
+    }
+    normalized
+}
+
+pub fn bookmark_locations(set: &BookmarkSet) -> Vec<BookmarkLocation> {
+    // Every link in the set and its folders, in the order they appear in the file.
+    let mut locations = vec![];
+    add_locations(&mut locations, set, &mut vec![], &mut vec![]);
+    locations
+}
+
+fn add_locations(locations: &mut Vec<BookmarkLocation>, set: &BookmarkSet, set_indexes: &mut Vec<usize>, folder_names: &mut Vec<String>) {
+    let (mut link_index, mut set_index) = (0, 0);
+    for item in set.ordered_items() {
+        match item {
+            BookmarkItem::Link(link) => {
+                locations.push(BookmarkLocation { set_indexes: set_indexes.clone(), folder_names: folder_names.clone(), index: link_index, link: link.clone() });
+                link_index += 1;
+            },
+            BookmarkItem::Set(child) => {
+                set_indexes.push(set_index);
+                folder_names.push(child.name.clone());
+                add_locations(locations, child, set_indexes, folder_names);
+                set_indexes.pop();
+                folder_names.pop();
+                set_index += 1;
+            },
+            BookmarkItem::Separator => {},
+        }
+    }
+}
+
+pub fn find_duplicate_links(set: &BookmarkSet) -> Vec<DuplicateLinks> {
+    // Links whose normalized URLs appear more than once anywhere in the set, in the order of
+    // their first appearance.
+    let mut groups: Vec<DuplicateLinks> = vec![];
+    let mut group_indexes: BTreeMap<String, usize> = BTreeMap::new();
+    for location in bookmark_locations(set) {
+        let normalized_url = normalize_bookmark_url(&location.link.url);
+        match group_indexes.get(&normalized_url) {
+            Some(index) => groups[*index].locations.push(location),
+            None => {
+                group_indexes.insert(normalized_url.clone(), groups.len());
+                groups.push(DuplicateLinks { normalized_url, locations: vec![location] });
+            },
+        }
+    }
+    groups.retain(|group| group.locations.len() > 1);
+    groups
+}
+
+pub fn remove_duplicate_links(set: &mut BookmarkSet) -> usize {
+    // Keep the first appearance of each link and remove the rest. Returns the number removed.
+    let mut removals: BTreeMap<Vec<usize>, Vec<usize>> = BTreeMap::new();
+    for group in find_duplicate_links(set) {
+        for location in group.locations.into_iter().skip(1) {
+            removals.entry(location.set_indexes).or_default().push(location.index);
+        }
+    }
+    let mut count = 0;
+    for (set_indexes, mut indexes) in removals {
+        let folder = folder_mut(set, &set_indexes);
+        // Remove from the end so that the remaining indexes stay valid.
+        indexes.sort_unstable_by(|a, b| b.cmp(a));
+        for index in indexes {
+            folder.remove_link(index);
+            count += 1;
+        }
+    }
+    count
+}
+
+pub fn remove_empty_folders(set: &mut BookmarkSet) -> usize {
+    // Remove folders with no links in them or in any of their subfolders. The bookmarks bar is
+    // kept even if it's empty since browsers expect it to be there. Returns the number of folders
+    // removed, including subfolders of removed folders.
+    let mut count = 0;
+    for index in (0..set.sets.len()).rev() {
+        count += remove_empty_folders(&mut set.sets[index]);
+        let child = &set.sets[index];
+        if child.links.is_empty() && child.sets.is_empty() && !child.is_toolbar_folder() {
+            set.remove_set(index);
+            count += 1;
+        }
+    }
+    count
+}
+
+pub fn merge_bookmark_sets(base: &mut BookmarkSet, other: &BookmarkSet) -> MergeSummary {
+    // Add the bookmarks from the other set to the base set. Folders are matched by name at each
+    // level, and folders that aren't in the base set are added at the end of their parent. A link
+    // is only added if its URL isn't already anywhere in the base set, so merging never creates
+    // duplicates.
+    let mut known_urls = bookmark_locations(base).iter()
+        .map(|location| normalize_bookmark_url(&location.link.url))
+        .collect::<BTreeSet<_>>();
+    let mut summary = MergeSummary::default();
+    merge_into(base, other, &mut known_urls, &mut summary);
+    summary
+}
+
+fn merge_into(target: &mut BookmarkSet, source: &BookmarkSet, known_urls: &mut BTreeSet<String>, summary: &mut MergeSummary) {
+    for item in source.ordered_items() {
+        match item {
+            BookmarkItem::Link(link) => {
+                if known_urls.insert(normalize_bookmark_url(&link.url)) {
+                    target.add_link(link.clone());
+                    summary.links_added += 1;
+                }
+            },
+            BookmarkItem::Set(source_child) => {
+                let index = match target.sets.iter().position(|set| set.name == source_child.name) {
+                    Some(index) => index,
+                    None => {
+                        let folder = BookmarkSet {
+                            name: source_child.name.clone(),
+                            add_date: source_child.add_date,
+                            last_modified: source_child.last_modified,
+                            attributes: source_child.attributes.clone(),
+                            ..Default::default()
+                        };
+                        target.add_set(folder);
+                        summary.sets_added += 1;
+                        target.sets.len() - 1
+                    },
+                };
+                merge_into(&mut target.sets[index], source_child, known_urls, summary);
+            },
+            BookmarkItem::Separator => {},
+        }
+    }
+}
+
+pub fn diff_bookmark_sets(old: &BookmarkSet, new: &BookmarkSet) -> BookmarkDiff {
+    // Compare two versions of a set of bookmarks by normalized URL. If a URL appears more than
+    // once in a set, only its first appearance is compared.
+    let old_locations = first_locations(old);
+    let new_locations = first_locations(new);
+    let old_map = old_locations.iter().map(|(url, location)| (url.as_str(), location)).collect::<BTreeMap<_, _>>();
+    let new_map = new_locations.iter().map(|(url, location)| (url.as_str(), location)).collect::<BTreeMap<_, _>>();
+    let mut diff = BookmarkDiff::default();
+    for (url, new_location) in new_locations.iter() {
+        match old_map.get(url.as_str()) {
+            None => diff.added.push(new_location.clone()),
+            Some(old_location) => {
+                if old_location.folder_names != new_location.folder_names {
+                    diff.moved.push(((*old_location).clone(), new_location.clone()));
+                }
+                if old_location.link.label != new_location.link.label {
+                    diff.relabeled.push(((*old_location).clone(), new_location.clone()));
+                }
+            },
+        }
+    }
+    diff.removed = old_locations.iter()
+        .filter(|(url, _)| !new_map.contains_key(url.as_str()))
+        .map(|(_, location)| location.clone())
+        .collect();
+    diff
+}
+
+fn first_locations(set: &BookmarkSet) -> Vec<(String, BookmarkLocation)> {
+    let mut seen = BTreeSet::new();
+    bookmark_locations(set).into_iter()
+        .map(|location| (normalize_bookmark_url(&location.link.url), location))
+        .filter(|(url, _)| seen.insert(url.clone()))
+        .collect()
+}
+
+fn folder_mut<'a>(set: &'a mut BookmarkSet, set_indexes: &[usize]) -> &'a mut BookmarkSet {
+    match set_indexes.split_first() {
+        Some((first, rest)) => folder_mut(&mut set.sets[*first], rest),
+        None => set,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::parse_bookmark_html_r;
+
+    fn sample_set() -> BookmarkSet {
+        let html = "<H1>Bookmarks</H1>
+<DL><p>
+    <DT><H3 PERSONAL_TOOLBAR_FOLDER=\"true\">Bookmarks bar</H3>
+    <DL><p>
+        <DT><A HREF=\"https://example.com/\">Example</A>
+        <DT><H3>Rust</H3>
+        <DL><p>
+            <DT><A HREF=\"https://doc.rust-lang.org/std/\">std</A>
+            <DT><A HREF=\"http://EXAMPLE.com?utm_source=feed\">Example again</A>
+            <DT><H3>Empty</H3>
+            <DL><p>
+                <DT><H3>Also empty</H3>
+            </DL><p>
+        </DL><p>
+    </DL><p>
+    <DT><A HREF=\"https://example.com\">Example once more</A>
+</DL><p>
+";
+        parse_bookmark_html_r(html).unwrap()
+    }
+
+    #[test]
+    fn test_normalize_bookmark_url() {
+        assert_eq!("https://example.com", normalize_bookmark_url("HTTP://Example.COM:80/"));
+        assert_eq!("https://example.com/a/b?x=1&y=2#top", normalize_bookmark_url("https://example.com/a/b/?utm_source=x&x=1&UTM_medium=y&y=2#top"));
+        assert_eq!("ftp://files.example.com/pub", normalize_bookmark_url("ftp://files.example.com/pub/"));
+        assert_eq!("javascript:void(0)", normalize_bookmark_url(" javascript:void(0) "));
+    }
+
+    #[test]
+    fn test_duplicates_and_empty_folders() {
+        let mut set = sample_set();
+        let duplicates = find_duplicate_links(&set);
+        assert_eq!(1, duplicates.len());
+        assert_eq!("https://example.com", duplicates[0].normalized_url);
+        let paths = duplicates[0].locations.iter().map(|location| location.folder_path()).collect::<Vec<_>>();
+        assert_eq!(vec!["Bookmarks bar", "Bookmarks bar > Rust", "(top level)"], paths);
+        assert_eq!(vec![0, 0], duplicates[0].locations[1].set_indexes);
+
+        assert_eq!(2, remove_duplicate_links(&mut set));
+        assert!(find_duplicate_links(&set).is_empty());
+        assert_eq!(2, set.link_count_deep());
+        assert!(set.links.is_empty() && set.order.len() == 1);
+
+        assert_eq!(2, remove_empty_folders(&mut set));
+        assert_eq!(2, set.set_count_deep());
+        // The bookmarks bar is kept even when it's empty.
+        let mut empty_bar = BookmarkSet::new("Bookmarks");
+        empty_bar.add_set(set.sets[0].as_ref().clone());
+        empty_bar.sets[0].links.clear();
+        empty_bar.sets[0].sets.clear();
+        assert_eq!(0, remove_empty_folders(&mut empty_bar));
+
+        // The cleaned-up set can be written out and read back.
+        assert_eq!(set, parse_bookmark_html_r(&set.to_bookmark_html()).unwrap());
+    }
+
+    #[test]
+    fn test_merge_bookmark_sets() {
+        let mut base = sample_set();
+        let mut other = BookmarkSet::new("Bookmarks");
+        let mut bar = BookmarkSet::new("Bookmarks bar");
+        let mut rust = BookmarkSet::new("Rust");
+        rust.add_link(BookmarkLink::new("https://doc.rust-lang.org/std", "std again"));
+        rust.add_link(BookmarkLink::new("https://crates.io", "Crates"));
+        bar.add_set(rust);
+        let mut news = BookmarkSet::new("News");
+        news.add_link(BookmarkLink::new("https://news.example.com", "News"));
+        bar.add_set(news);
+        other.add_set(bar);
+
+        let summary = merge_bookmark_sets(&mut base, &other);
+        assert_eq!(MergeSummary { links_added: 2, sets_added: 1 }, summary);
+        let rust = &base.sets[0].sets[0];
+        assert_eq!(vec!["std", "Example again", "Crates"], rust.links.iter().map(|link| link.label.as_str()).collect::<Vec<_>>());
+        assert_eq!("News", base.sets[0].sets[1].name);
+        // Merging the same bookmarks again changes nothing.
+        assert_eq!(MergeSummary::default(), merge_bookmark_sets(&mut base, &other));
+    }
+
+    #[test]
+    fn test_diff_bookmark_sets() {
+        let old = sample_set();
+        let mut new = sample_set();
+        new.sets[0].links[0].label = "Example site".to_string();
+        let moved = new.sets[0].sets[0].remove_link(0);
+        new.add_link(moved);
+        new.sets[0].sets[0].add_link(BookmarkLink::new("https://crates.io", "Crates"));
+        new.remove_link(0);
+
+        let diff = diff_bookmark_sets(&old, &new);
+        assert!(diff.removed.is_empty());
+        let expected = vec![
+            "Added: Bookmarks bar > Rust: \"Crates\" <https://crates.io>",
+            "Moved: \"std\" <https://doc.rust-lang.org/std/>: Bookmarks bar > Rust -> (top level)",
+            "Relabeled: <https://example.com/>: \"Example\" -> \"Example site\"",
+        ];
+        assert_eq!(expected, diff.report_lines());
+        let changes = diff.to_bookmark_set("Changes");
+        assert_eq!(vec!["Added", "Moved", "Relabeled"], changes.sets.iter().map(|set| set.name.as_str()).collect::<Vec<_>>());
+        assert!(diff_bookmark_sets(&old, &old).is_empty());
+        // Empty names and labels, which bookmark files can have, are fine.
+        assert_eq!("", diff.to_bookmark_set("").name);
+        assert_eq!("", BookmarkLink::new("https://a.com", "").label);
+
+        let diff = diff_bookmark_sets(&new, &old);
+        assert_eq!(vec!["Removed: Bookmarks bar > Rust: \"Crates\" <https://crates.io>"], diff.report_lines()[..1].to_vec());
+    }
+}
@@ -0,0 +1,353 @@
+// Export a BookmarkSet to wiki pages, one page per bookmark folder plus an index page with an
+// outline of all of the folders. Each folder's page has a link back up to its parent folder, a
+// list of links to its subfolders, and a list of its bookmarks. The pages can be written as
+// ConnectedText topics, DokuWiki pages or Markdown files.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::dokuwiki::{WikiBlock, WikiLink};
+use crate::file::{path_create_if_necessary_r, write_file_atomic_r};
+use crate::html::{BookmarkLink, BookmarkSet};
+use crate::path_util::{sanitize_file_name, FileNamePlatform};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WikiFormat {
+    ConnectedText,
+    DokuWiki,
+    Markdown,
+}
+
+#[derive(Clone, Debug)]
+pub struct WikiExportOptions {
+    pub format: WikiFormat,
+    pub index_page_name: String,
+    // Added to the start of every page name other than the index, so that the folder pages don't
+    // collide with other pages in the wiki.
+    pub page_prefix: String,
+    // The DokuWiki namespace the pages will live in, such as "bookmarks". The files themselves
+    // are written directly to the export folder, which should be the namespace's folder.
+    pub namespace: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WikiExportPage {
+    pub name: String,
+    pub file_name: String,
+    pub text: String,
+}
+
+// A bookmark folder along with what's needed to name and link to its page.
+struct FolderPage<'a> {
+    set: &'a BookmarkSet,
+    depth: usize,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    name: String,
+    page_id: String,
+    file_name: String,
+}
+
+impl Default for WikiExportOptions {
+    fn default() -> Self {
+        Self {
+            format: WikiFormat::ConnectedText,
+            index_page_name: "Bookmarks".to_string(),
+            page_prefix: "".to_string(),
+            namespace: "".to_string(),
+        }
+    }
+}
+
+impl WikiFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            WikiFormat::ConnectedText | WikiFormat::DokuWiki => "txt",
+            WikiFormat::Markdown => "md",
+        }
+    }
+
+    fn heading(&self, level: usize, title: &str) -> String {
+        match self {
+            WikiFormat::ConnectedText => format!("{} {} {}", "=".repeat(level), title, "=".repeat(level)),
+            WikiFormat::DokuWiki => WikiBlock::Heading { level, title: title.to_string() }.to_markup(),
+            WikiFormat::Markdown => format!("{} {}", "#".repeat(level), title),
+        }
+    }
+
+    fn list_item(&self, depth: usize, text: &str) -> String {
+        // Depth 1 is a top-level item.
+        match self {
+            WikiFormat::ConnectedText => format!("{} {}", "*".repeat(depth), text),
+            WikiFormat::DokuWiki => format!("{}* {}", "  ".repeat(depth), text),
+            WikiFormat::Markdown => format!("{}- {}", "  ".repeat(depth - 1), text),
+        }
+    }
+
+    fn page_link(&self, name: &str, page_id: &str, file_name: &str) -> String {
+        match self {
+            WikiFormat::ConnectedText => format!("[[{}]]", page_id),
+            WikiFormat::DokuWiki => WikiLink::new(page_id, None, Some(&clean_label(name))).to_markup(),
+            WikiFormat::Markdown => format!("[{}]({})", escape_markdown_label(name), escape_markdown_url(file_name)),
+        }
+    }
+
+    fn external_link(&self, link: &BookmarkLink) -> String {
+        // A bookmark with no label shows its URL instead.
+        let label = if link.label.trim().is_empty() { link.url.as_str() } else { link.label.trim() };
+        match self {
+            WikiFormat::ConnectedText => format!("[[$URL:{}|{}]]", clean_url(&link.url), clean_label(label)),
+            WikiFormat::DokuWiki => WikiLink { namespace: vec![], page: clean_url(&link.url), anchor: None, label: Some(clean_label(label)) }.to_markup(),
+            WikiFormat::Markdown => format!("[{}]({})", escape_markdown_label(label), escape_markdown_url(&link.url)),
+        }
+    }
+}
+
+pub fn bookmark_wiki_pages(set: &BookmarkSet, options: &WikiExportOptions) -> Vec<WikiExportPage> {
+    let format = options.format;
+    let mut folders = vec![];
+    add_folder_pages(&mut folders, set, 0, None);
+    let mut used_file_names = BTreeSet::new();
+    let (index_id, index_file_name) = page_id_and_file_name(&options.index_page_name, options);
+    used_file_names.insert(index_file_name.to_lowercase());
+    for index in 0..folders.len() {
+        let path = folder_path(&folders, index);
+        let base_name = format!("{}{}", options.page_prefix, path.join(" - "));
+        // Folders with the same path, or names that only differ in characters that can't be in a
+        // file name, get a number to keep their pages apart.
+        let mut name = base_name.clone();
+        let mut number = 2;
+        loop {
+            let (page_id, file_name) = page_id_and_file_name(&name, options);
+            if used_file_names.insert(file_name.to_lowercase()) {
+                let folder = &mut folders[index];
+                folder.name = name;
+                folder.page_id = page_id;
+                folder.file_name = file_name;
+                break;
+            }
+            name = format!("{} ({})", base_name, number);
+            number += 1;
+        }
+    }
+
+    let mut pages = vec![];
+    let mut index_lines = vec![format.heading(1, &options.index_page_name), "".to_string()];
+    for folder in folders.iter() {
+        let link = format.page_link(&folder.name, &folder.page_id, &folder.file_name);
+        index_lines.push(format.list_item(folder.depth + 1, &format!("{} ({})", link, link_count_label(folder.set.links.len()))));
+    }
+    pages.push(WikiExportPage { name: options.index_page_name.clone(), file_name: index_file_name.clone(), text: join_lines(&index_lines) });
+
+    for folder in folders.iter() {
+        let mut lines = vec![format.heading(1, &folder.set.name), "".to_string()];
+        // A top-level folder's page leads back to the index.
+        let up_link = match folder.parent.map(|parent| &folders[parent]) {
+            Some(up) => format.page_link(&up.name, &up.page_id, &up.file_name),
+            None => format.page_link(&options.index_page_name, &index_id, &index_file_name),
+        };
+        lines.push(format!("Up: {}", up_link));
+        if !folder.children.is_empty() {
+            lines.extend(["".to_string(), format.heading(2, "Folders"), "".to_string()]);
+            for child in folder.children.iter().map(|index| &folders[*index]) {
+                let link = format.page_link(&child.name, &child.page_id, &child.file_name);
+                lines.push(format.list_item(1, &format!("{} ({})", link, link_count_label(child.set.links.len()))));
+            }
+        }
+        if !folder.set.links.is_empty() {
+            lines.extend(["".to_string(), format.heading(2, "Links"), "".to_string()]);
+            for link in folder.set.links.iter() {
+                lines.push(format.list_item(1, &format.external_link(link)));
+            }
+        }
+        pages.push(WikiExportPage { name: folder.name.clone(), file_name: folder.file_name.clone(), text: join_lines(&lines) });
+    }
+    pages
+}
+
+pub fn export_bookmarks_to_wiki_r<P>(set: &BookmarkSet, path_folder: P, options: &WikiExportOptions) -> Result<Vec<PathBuf>, String>
+    where P: AsRef<Path>
+{
+    // Write the pages to the folder, creating it if necessary, and return the paths of the files.
+    path_create_if_necessary_r(&path_folder)?;
+    let mut paths = vec![];
+    for page in bookmark_wiki_pages(set, options) {
+        let path = path_folder.as_ref().join(&page.file_name);
+        write_file_atomic_r(&path, &page.text)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+fn add_folder_pages<'a>(folders: &mut Vec<FolderPage<'a>>, set: &'a BookmarkSet, depth: usize, parent: Option<usize>) {
+    // Folders are listed depth-first, the same order as in the index page's outline.
+    let index = folders.len();
+    folders.push(FolderPage { set, depth, parent, children: vec![], name: "".to_string(), page_id: "".to_string(), file_name: "".to_string() });
+    if let Some(parent) = parent {
+        folders[parent].children.push(index);
+    }
+    for child in set.sets.iter() {
+        add_folder_pages(folders, child, depth + 1, Some(index));
+    }
+}
+
+fn folder_path(folders: &[FolderPage], index: usize) -> Vec<String> {
+    let mut path = vec![folders[index].set.name.trim().to_string()];
+    let mut current = index;
+    while let Some(parent) = folders[current].parent {
+        path.insert(0, folders[parent].set.name.trim().to_string());
+        current = parent;
+    }
+    path
+}
+
+fn page_id_and_file_name(name: &str, options: &WikiExportOptions) -> (String, String) {
+    // DokuWiki page IDs are limited to lowercase letters, digits and a few separators, and the
+    // file name has to match the ID. The other formats use the page name as the file name. A
+    // ConnectedText topic is named after its file, so links use the file name without the
+    // extension, with anything that would break a link replaced as well.
+    let format = options.format;
+    match format {
+        WikiFormat::DokuWiki => {
+            let mut id = String::new();
+            for c in name.to_lowercase().chars() {
+                if c.is_alphanumeric() {
+                    id.push(c);
+                } else if !id.ends_with('_') {
+                    id.push('_');
+                }
+            }
+            let id = id.trim_matches('_').to_string();
+            let id = if id.is_empty() { "_".to_string() } else { id };
+            let file_name = format!("{}.{}", id, format.extension());
+            let page_id = if options.namespace.is_empty() { id } else { format!("{}:{}", options.namespace, id) };
+            (page_id, file_name)
+        },
+        WikiFormat::ConnectedText => {
+            let file_name = sanitize_file_name(&format!("{}.{}", clean_label(name), format.extension()), FileNamePlatform::Portable, "_");
+            let topic = file_name[..file_name.len() - format.extension().len() - 1].to_string();
+            (topic, file_name)
+        },
+        WikiFormat::Markdown => {
+            let file_name = sanitize_file_name(&format!("{}.{}", name, format.extension()), FileNamePlatform::Portable, "_");
+            (name.to_string(), file_name)
+        },
+    }
+}
+
+fn link_count_label(count: usize) -> String {
+    if count == 1 { "1 link".to_string() } else { format!("{} links", count) }
+}
+
+fn clean_label(label: &str) -> String {
+    // A bracket could end a wiki link early and a pipe would start its label.
+    label.replace('[', "(").replace(']', ")").replace('|', "/")
+}
+
+fn clean_url(url: &str) -> String {
+    url.replace('|', "%7C").replace('[', "%5B").replace(']', "%5D").replace(' ', "%20")
+}
+
+fn escape_markdown_label(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('[', "\\[").replace(']', "\\]")
+}
+
+fn escape_markdown_url(url: &str) -> String {
+    url.replace(' ', "%20").replace('(', "%28").replace(')', "%29")
+}
+
+fn join_lines(lines: &[String]) -> String {
+    let mut text = lines.join("\n");
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_workspace::{spec_file, TempWorkspace};
+
+    fn sample_set() -> BookmarkSet {
+        let link = |url: &str, label: &str| BookmarkLink { url: url.to_string(), label: label.to_string(), ..Default::default() };
+        let folder = |name: &str, links: Vec<BookmarkLink>, sets: Vec<BookmarkSet>| BookmarkSet {
+            name: name.to_string(),
+            links,
+            sets: sets.into_iter().map(Box::new).collect(),
+            ..Default::default()
+        };
+        folder("Bookmarks Bar", vec![link("https://example.com/", "Example")], vec![
+            folder("Rust", vec![link("https://doc.rust-lang.org/std/", "std [docs]"), link("https://crates.io/search?q=a|b", "")], vec![
+                folder("Tools", vec![], vec![]),
+            ]),
+            folder("Rust", vec![], vec![]),
+        ])
+    }
+
+    #[test]
+    fn test_connectedtext_pages() {
+        let pages = bookmark_wiki_pages(&sample_set(), &WikiExportOptions::default());
+        let names = pages.iter().map(|page| page.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["Bookmarks", "Bookmarks Bar", "Bookmarks Bar - Rust", "Bookmarks Bar - Rust - Tools", "Bookmarks Bar - Rust (2)"], names);
+        assert_eq!("= Bookmarks =
+
+* [[Bookmarks Bar]] (1 link)
+** [[Bookmarks Bar - Rust]] (2 links)
+*** [[Bookmarks Bar - Rust - Tools]] (0 links)
+** [[Bookmarks Bar - Rust (2)]] (0 links)
+", pages[0].text);
+        assert_eq!("= Rust =
+
+Up: [[Bookmarks Bar]]
+
+== Folders ==
+
+* [[Bookmarks Bar - Rust - Tools]] (0 links)
+
+== Links ==
+
+* [[$URL:https://doc.rust-lang.org/std/|std (docs)]]
+* [[$URL:https://crates.io/search?q=a%7Cb|https://crates.io/search?q=a/b]]
+", pages[2].text);
+        assert!(pages[1].text.contains("Up: [[Bookmarks]]"));
+
+        // Topics are named after their files, so characters that can't be in a file name or that
+        // would break a link are replaced in both.
+        let mut set = BookmarkSet::new("Dev");
+        set.add_set(BookmarkSet::new("C++: Tools"));
+        set.add_set(BookmarkSet::new("A|B [old]"));
+        let pages = bookmark_wiki_pages(&set, &WikiExportOptions::default());
+        assert_eq!(vec!["Bookmarks.txt", "Dev.txt", "Dev - C++_ Tools.txt", "Dev - A_B (old).txt"], pages.iter().map(|page| page.file_name.as_str()).collect::<Vec<_>>());
+        assert!(pages[0].text.contains("** [[Dev - C++_ Tools]] (0 links)\n** [[Dev - A_B (old)]] (0 links)\n"));
+        assert!(pages[2].text.contains("Up: [[Dev]]"));
+    }
+
+    #[test]
+    fn test_dokuwiki_and_markdown_pages() {
+        let options = WikiExportOptions { format: WikiFormat::DokuWiki, namespace: "bookmarks".to_string(), ..Default::default() };
+        let pages = bookmark_wiki_pages(&sample_set(), &options);
+        assert_eq!("bookmarks_bar_rust_tools.txt", pages[3].file_name);
+        assert!(pages[0].text.starts_with("====== Bookmarks ======\n\n  * [[bookmarks:bookmarks_bar|Bookmarks Bar]] (1 link)\n    * [[bookmarks:bookmarks_bar_rust|Bookmarks Bar - Rust]]"));
+        assert!(pages[2].text.contains("  * [[https://doc.rust-lang.org/std/|std (docs)]]\n"));
+
+        let options = WikiExportOptions { format: WikiFormat::Markdown, page_prefix: "BM ".to_string(), ..Default::default() };
+        let pages = bookmark_wiki_pages(&sample_set(), &options);
+        assert_eq!("BM Bookmarks Bar - Rust.md", pages[2].file_name);
+        assert!(pages[0].text.contains("\n  - [BM Bookmarks Bar - Rust](BM%20Bookmarks%20Bar%20-%20Rust.md) (2 links)\n"));
+        assert!(pages[2].text.contains("- [std \\[docs\\]](https://doc.rust-lang.org/std/)\n"));
+        assert!(pages[2].text.starts_with("# Rust\n\nUp: [BM Bookmarks Bar](BM%20Bookmarks%20Bar.md)\n"));
+    }
+
+    #[test]
+    fn test_export_bookmarks_to_wiki_r() {
+        let workspace = TempWorkspace::new("test_export_bookmarks_to_wiki_r");
+        let path_folder = workspace.join("wiki");
+        let mut set = BookmarkSet::new("Bar");
+        set.add_link(BookmarkLink::new("https://a.com", "A"));
+        let paths = export_bookmarks_to_wiki_r(&set, &path_folder, &WikiExportOptions::default()).unwrap();
+        assert_eq!(vec![path_folder.join("Bookmarks.txt"), path_folder.join("Bar.txt")], paths);
+        workspace.assert_tree("wiki", &[
+            spec_file("Bar.txt", "= Bar =\n\nUp: [[Bookmarks]]\n\n== Links ==\n\n* [[$URL:https://a.com|A]]\n"),
+            spec_file("Bookmarks.txt", "= Bookmarks =\n\n* [[Bar]] (1 link)\n"),
+        ]);
+    }
+}
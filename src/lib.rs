@@ -6,24 +6,39 @@ extern crate titlecase;
 pub use std::rc::Rc;
 pub use std::cell::RefCell;
 
+pub mod archive;
 pub mod bool;
+pub mod config;
 pub mod convert;
+pub mod data_frame;
 pub mod date_time;
+pub mod disk_usage;
+pub mod dokuwiki;
 pub mod elapsed;
+pub mod encoding;
 pub mod extract;
 pub mod format;
 pub mod file;
 pub mod group;
 pub mod html;
 pub mod info_theory;
+pub mod line_reader;
 pub mod log;
+pub mod markdown;
 pub mod math;
 pub mod number;
 pub mod parse;
+pub mod path_util;
+pub mod rename;
+pub mod schema;
+pub mod search;
 pub mod spreadsheet;
 pub mod stats_usize;
 pub mod tab;
+#[cfg(test)]
+pub mod temp_workspace;
 pub mod tree;
+pub mod watch;
 
 pub use format::fc;
 
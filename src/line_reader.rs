@@ -0,0 +1,269 @@
+// A streaming line reader shared by the parsers in this crate. Unlike BufRead::lines() it treats
+// "\n", "\r\n" and a bare "\r" all as line breaks, skips a BOM at the start of the file, decodes
+// UTF-16 and UTF-32 files on the fly, and numbers the lines so that errors can point to the place
+// in the file where they happened. Files can optionally be memory-mapped rather than read through
+// a buffer, which is faster for very large files that are read from start to finish.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::*;
+use crate::encoding::{DecodeReader, LineEnding, TextEncoding, detect_bom, windows_1252_char};
+use crate::file::{path_exists_r, path_name};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    // One-based, as in an editor.
+    pub number: usize,
+    pub text: String,
+    // None for a last line that doesn't end with a line break.
+    pub line_ending: Option<LineEnding>,
+}
+
+pub struct LineReader<'a> {
+    reader: Box<dyn BufRead + 'a>,
+    encoding: TextEncoding,
+    context: String,
+    line_number: usize,
+    done: bool,
+}
+
+impl <'a> LineReader<'a> {
+    pub fn from_reader<R>(reader: R, context: &str) -> Result<Self, String>
+        where R: Read + 'a
+    {
+        Self::from_buf_reader(BufReader::new(reader), context)
+    }
+
+    pub fn from_text(text: &'a str) -> Self {
+        // A string is already UTF-8, so there's nothing to detect and nothing can fail.
+        Self::from_buf_reader(text.as_bytes(), "string").unwrap()
+    }
+
+    fn from_buf_reader<R>(mut reader: R, context: &str) -> Result<Self, String>
+        where R: BufRead + 'a
+    {
+        // Look at the first few bytes for a BOM. This assumes the first fill of the buffer holds at
+        // least four bytes, which is true of files and anything else that isn't trickling in.
+        let bom_encoding = detect_bom(rse!(reader.fill_buf())?);
+        let (reader, encoding): (Box<dyn BufRead + 'a>, TextEncoding) = match bom_encoding {
+            None => (Box::new(reader), TextEncoding::Utf8),
+            Some(TextEncoding::Utf8Bom) => {
+                reader.consume(TextEncoding::Utf8Bom.bom().len());
+                (Box::new(reader), TextEncoding::Utf8Bom)
+            },
+            Some(encoding) => (Box::new(BufReader::new(DecodeReader::new(reader, encoding))), encoding),
+        };
+        Ok(Self {
+            reader,
+            encoding,
+            context: context.to_string(),
+            line_number: 0,
+            done: false,
+        })
+    }
+
+    pub fn encoding(&self) -> TextEncoding {
+        // The encoding found from the BOM, or UTF-8 if there was no BOM. In the latter case any
+        // line that isn't valid UTF-8 is decoded as Windows-1252.
+        self.encoding
+    }
+
+    pub fn line_number(&self) -> usize {
+        // The number of the last line returned.
+        self.line_number
+    }
+
+    pub fn read_line_r(&mut self) -> Result<Option<Line>, String> {
+        let mut bytes = vec![];
+        let mut line_ending = None;
+        while line_ending.is_none() {
+            let (pos, found_char) = {
+                let buf = self.fill_buf_r()?;
+                if buf.is_empty() {
+                    break;
+                }
+                match buf.iter().position(|byte| *byte == b'\n' || *byte == b'\r') {
+                    Some(pos) => {
+                        bytes.extend_from_slice(&buf[..pos]);
+                        (pos + 1, Some(buf[pos]))
+                    },
+                    None => {
+                        bytes.extend_from_slice(buf);
+                        (buf.len(), None)
+                    }
+                }
+            };
+            self.reader.consume(pos);
+            line_ending = match found_char {
+                Some(b'\n') => Some(LineEnding::Lf),
+                Some(_) => {
+                    // A "\r" is either a line ending by itself or the first half of "\r\n", which
+                    // may have been split across two reads.
+                    if self.fill_buf_r()?.first() == Some(&b'\n') {
+                        self.reader.consume(1);
+                        Some(LineEnding::CrLf)
+                    } else {
+                        Some(LineEnding::Cr)
+                    }
+                },
+                None => None,
+            };
+        }
+        if bytes.is_empty() && line_ending.is_none() {
+            return Ok(None);
+        }
+        self.line_number += 1;
+        let mut text = match String::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(err) => err.into_bytes().iter().map(|byte| windows_1252_char(*byte)).collect(),
+        };
+        if self.line_number == 1 && text.starts_with('\u{feff}') {
+            // A BOM that was written into an already-decoded string.
+            text.remove(0);
+        }
+        Ok(Some(Line {
+            number: self.line_number,
+            text,
+            line_ending,
+        }))
+    }
+
+    fn fill_buf_r(&mut self) -> Result<&[u8], String> {
+        let context = &self.context;
+        let line_number = self.line_number + 1;
+        match self.reader.fill_buf() {
+            Ok(buf) => Ok(buf),
+            Err(err) => Err(format!("LineReader: {}: error reading line {}: {}", context, line_number, err)),
+        }
+    }
+}
+
+impl LineReader<'static> {
+    pub fn open_r<P>(path: P) -> Result<Self, String>
+        where P: AsRef<Path>
+    {
+        path_exists_r(&path)?;
+        let file = rse!(File::open(&path))?;
+        Self::from_reader(file, &path_name(&path))
+    }
+
+    pub fn open_mmap_r<P>(path: P) -> Result<Self, String>
+        where P: AsRef<Path>
+    {
+        // Memory-map the file instead of reading it through a buffer. This is only safe as long as
+        // no other process truncates the file while it's being read, which is the usual situation
+        // for the large exported files this is meant for.
+        path_exists_r(&path)?;
+        let file = rse!(File::open(&path))?;
+        let mmap = rse!(unsafe { Mmap::map(&file) })?;
+        Self::from_buf_reader(Cursor::new(mmap), &path_name(&path))
+    }
+}
+
+impl <'a> Iterator for LineReader<'a> {
+    type Item = Result<Line, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_line_r() {
+            Ok(Some(line)) => Some(Ok(line)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(msg) => {
+                self.done = true;
+                Some(Err(msg))
+            },
+        }
+    }
+}
+
+pub fn read_lines_r<P>(path: P) -> Result<Vec<Line>, String>
+    where P: AsRef<Path>
+{
+    LineReader::open_r(path)?.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_workspace::TempWorkspace;
+
+    fn texts(reader: LineReader) -> Vec<String> {
+        reader.map(|line| line.unwrap().text).collect()
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let reader = LineReader::from_text("one\ntwo\r\nthree\rfour");
+        let lines = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(vec!["one", "two", "three", "four"], lines.iter().map(|line| line.text.as_str()).collect::<Vec<_>>());
+        assert_eq!(vec![1, 2, 3, 4], lines.iter().map(|line| line.number).collect::<Vec<_>>());
+        assert_eq!(vec![Some(LineEnding::Lf), Some(LineEnding::CrLf), Some(LineEnding::Cr), None],
+            lines.iter().map(|line| line.line_ending).collect::<Vec<_>>());
+
+        // Blank lines are kept, but a line break at the very end doesn't start another line.
+        assert_eq!(vec!["a", "", "", "b"], texts(LineReader::from_text("a\n\r\n\rb\r\n")));
+        assert!(texts(LineReader::from_text("")).is_empty());
+        assert_eq!(vec![""], texts(LineReader::from_text("\n")));
+    }
+
+    #[test]
+    fn test_crlf_split_across_reads() {
+        // A reader that returns one byte at a time, so "\r" and "\n" arrive in separate reads.
+        struct OneByteReader<'a>(&'a [u8]);
+        impl <'a> Read for OneByteReader<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.0.is_empty() || buf.is_empty() {
+                    return Ok(0);
+                }
+                buf[0] = self.0[0];
+                self.0 = &self.0[1..];
+                Ok(1)
+            }
+        }
+        let reader = LineReader::from_buf_reader(BufReader::with_capacity(1, OneByteReader(b"ab\r\ncd\r\n")), "test").unwrap();
+        assert_eq!(vec!["ab", "cd"], texts(reader));
+    }
+
+    #[test]
+    fn test_encodings() {
+        let text = "Caf\u{e9}\r\nLine \u{1F600}\r\n";
+
+        let mut bytes = vec![0xEF, 0xBB, 0xBF];
+        bytes.extend_from_slice(text.as_bytes());
+        let reader = LineReader::from_reader(&bytes[..], "test").unwrap();
+        assert_eq!(TextEncoding::Utf8Bom, reader.encoding());
+        assert_eq!(vec!["Caf\u{e9}", "Line \u{1F600}"], texts(reader));
+
+        let mut bytes = vec![0xFF, 0xFE];
+        text.encode_utf16().for_each(|unit| bytes.extend_from_slice(&unit.to_le_bytes()));
+        let reader = LineReader::from_reader(&bytes[..], "test").unwrap();
+        assert_eq!(TextEncoding::Utf16Le, reader.encoding());
+        assert_eq!(vec!["Caf\u{e9}", "Line \u{1F600}"], texts(reader));
+
+        // No BOM and not valid UTF-8, so the line is taken to be Windows-1252.
+        let bytes = [b'A', b'\n', 0x93, b'B', 0x94, b'\n'];
+        assert_eq!(vec!["A", "\u{201C}B\u{201D}"], texts(LineReader::from_reader(&bytes[..], "test").unwrap()));
+    }
+
+    #[test]
+    fn test_open_mmap_r() {
+        let workspace = TempWorkspace::new("test_open_mmap_r");
+        let path_file = workspace.join("Lines.txt");
+        std::fs::write(&path_file, "one\r\ntwo\r\n").unwrap();
+        assert_eq!(vec!["one", "two"], texts(LineReader::open_mmap_r(&path_file).unwrap()));
+        assert_eq!(texts(LineReader::open_r(&path_file).unwrap()), texts(LineReader::open_mmap_r(&path_file).unwrap()));
+
+        let path_empty_file = workspace.join("Empty.txt");
+        std::fs::write(&path_empty_file, "").unwrap();
+        assert!(texts(LineReader::open_mmap_r(&path_empty_file).unwrap()).is_empty());
+    }
+}
@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
 const PATH_CHROME_BOOKMARKS: &str = r"E:\Temp\bookmarks_1_29_20.html";
+const PATH_CONNECTEDTEXT_BOOKMARKS: &str = r"E:\Temp\Bookmark Topics";
 
 use std::path;
 use num_format::Locale;
@@ -27,6 +28,9 @@ fn main() {
 fn gen_bookmarks_for_connectedtext() {
     let bookmark_set = html::parse_chrome_bookmarks(&path::Path::new(PATH_CHROME_BOOKMARKS));
     bookmark_set.display_deep(0);
+    let options = html::wiki_export::WikiExportOptions::default();
+    let paths = html::wiki_export::export_bookmarks_to_wiki_r(&bookmark_set, PATH_CONNECTEDTEXT_BOOKMARKS, &options).unwrap();
+    println!("Wrote {} ConnectedText topics.", paths.len());
 }
 
 fn try_format_locale() {
@@ -0,0 +1,462 @@
+// An outline of a Markdown document, as a replacement for parse::break_into_sections() when the
+// order of the sections, their nesting, or repeated headings matter. The document is split into
+// sections at each ATX heading ("# Title" through "###### Title") or setext heading (a line of
+// text underlined with "===" or "---"), ignoring anything that looks like a heading inside a
+// fenced code block. Each section keeps its heading and body lines exactly as written so that the
+// document can be edited one section at a time and written back with everything else unchanged.
+
+use std::fmt::{self, Display};
+use std::path::Path;
+
+use crate::*;
+use crate::file::{path_name, read_file_to_string_r, write_file_atomic_r};
+use crate::tree::Tree;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MarkdownDocument {
+    // Lines before the first heading.
+    pub preamble: Vec<String>,
+    // Sections in document order. The nesting comes from the heading levels, so a section's
+    // subsections are the sections right after it with a higher level.
+    pub sections: Vec<MarkdownSection>,
+    line_ending: String,
+    trailing_newline: bool,
+}
+
+// The fields are in this order so that sorting sections, as the tree does, keeps them in document
+// order.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MarkdownSection {
+    pub index: usize,
+    pub level: usize,
+    pub title: String,
+    // One line for an ATX heading or two for a setext heading.
+    pub heading_lines: Vec<String>,
+    pub body: Vec<String>,
+}
+
+impl MarkdownDocument {
+    pub fn new() -> Self {
+        Self { preamble: vec![], sections: vec![], line_ending: "\n".to_string(), trailing_newline: true }
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim_start_matches('\u{feff}');
+        let line_ending = if text.contains("\r\n") { "\r\n" } else { "\n" };
+        let mut document = Self { preamble: vec![], sections: vec![], line_ending: line_ending.to_string(), trailing_newline: text.ends_with('\n') };
+        let lines = text.lines().collect::<Vec<_>>();
+        let mut fence: Option<String> = None;
+        let mut line_index = 0;
+        while line_index < lines.len() {
+            let line = lines[line_index];
+            if let Some(fence_text) = &fence {
+                if is_closing_fence(line, fence_text) {
+                    fence = None;
+                }
+                document.push_body_line(line);
+                line_index += 1;
+                continue;
+            }
+            if let Some(fence_text) = opening_fence(line) {
+                fence = Some(fence_text);
+                document.push_body_line(line);
+                line_index += 1;
+                continue;
+            }
+            if let Some((level, title)) = parse_atx_heading(line) {
+                document.push_section(level, title, vec![line.to_string()]);
+                line_index += 1;
+                continue;
+            }
+            // A setext heading is a single line of text with a blank line or another heading
+            // before it. Markdown also allows several lines of text to be underlined as one
+            // heading, but that's rare enough that those lines are left as body text.
+            let previous_is_break = match document.sections.last() {
+                Some(section) => section.body.last().is_none_or(|previous| previous.trim().is_empty()),
+                None => document.preamble.last().is_none_or(|previous| previous.trim().is_empty()),
+            };
+            if previous_is_break && is_setext_text(line) {
+                if let Some(level) = lines.get(line_index + 1).and_then(|next| setext_underline_level(next)) {
+                    document.push_section(level, line.trim().to_string(), vec![line.to_string(), lines[line_index + 1].to_string()]);
+                    line_index += 2;
+                    continue;
+                }
+            }
+            document.push_body_line(line);
+            line_index += 1;
+        }
+        document
+    }
+
+    pub fn read_r<P>(path: P) -> Result<Self, String>
+        where P: AsRef<Path>
+    {
+        let text = err_context(read_file_to_string_r(&path), &format!("MarkdownDocument::read_r: \"{}\":", path_name(&path)))?;
+        Ok(Self::parse(&text))
+    }
+
+    pub fn write_r<P>(&self, path: P) -> Result<(), String>
+        where P: AsRef<Path>
+    {
+        write_file_atomic_r(path, &self.to_text())
+    }
+
+    pub fn to_text(&self) -> String {
+        let lines = self.preamble.iter()
+            .chain(self.sections.iter().flat_map(|section| section.heading_lines.iter().chain(section.body.iter())))
+            .map(|line| line.as_str())
+            .collect::<Vec<_>>();
+        let mut text = lines.join(&self.line_ending);
+        if self.trailing_newline && !lines.is_empty() {
+            text.push_str(&self.line_ending);
+        }
+        text
+    }
+
+    pub fn parent_index(&self, index: usize) -> Option<usize> {
+        let level = self.sections[index].level;
+        (0..index).rev().find(|i| self.sections[*i].level < level)
+    }
+
+    pub fn child_indexes(&self, index: Option<usize>) -> Vec<usize> {
+        // The direct subsections of a section, or the top-level sections for None.
+        let (start, end) = match index {
+            Some(index) => (index + 1, self.subtree_end(index)),
+            None => (0, self.sections.len()),
+        };
+        (start..end).filter(|i| self.parent_index(*i) == index).collect()
+    }
+
+    pub fn subtree_end(&self, index: usize) -> usize {
+        // One past the last subsection of the section, which is where a new last subsection goes.
+        let level = self.sections[index].level;
+        (index + 1..self.sections.len()).find(|i| self.sections[*i].level <= level).unwrap_or(self.sections.len())
+    }
+
+    pub fn title_path(&self, index: usize) -> Vec<String> {
+        let mut path = vec![self.sections[index].title.clone()];
+        let mut current = index;
+        while let Some(parent) = self.parent_index(current) {
+            path.insert(0, self.sections[parent].title.clone());
+            current = parent;
+        }
+        path
+    }
+
+    pub fn find_section(&self, title_path: &[&str]) -> Option<usize> {
+        // Find a section by its title and the titles of the sections it's nested in, such as
+        // ["Installation", "Linux"]. The first match wins if there are repeated headings.
+        let mut parent = None;
+        for title in title_path {
+            parent = Some(self.child_indexes(parent).into_iter().find(|i| self.sections[*i].title == *title)?);
+        }
+        parent
+    }
+
+    pub fn find_section_r(&self, title_path: &[&str]) -> Result<usize, String> {
+        self.find_section(title_path).ok_or_else(|| format!("MarkdownDocument: no section \"{}\".", title_path.join(" > ")))
+    }
+
+    pub fn heading_tree(&self) -> Tree<MarkdownSection> {
+        // The tree's calculations such as height need at least one node, so they're only done if
+        // there are sections.
+        let pairs = (0..self.sections.len())
+            .filter_map(|i| self.parent_index(i).map(|parent| (self.sections[parent].clone(), self.sections[i].clone())))
+            .collect::<Vec<_>>();
+        Tree::create_with_items(self.sections.clone(), pairs, !self.sections.is_empty())
+    }
+
+    pub fn add_section(&mut self, parent: Option<usize>, title: &str, body: &str) -> usize {
+        // Add a section as the last subsection of the parent, or at the end of the document for
+        // None, and return its index.
+        let (index, level) = match parent {
+            Some(parent) => (self.subtree_end(parent), (self.sections[parent].level + 1).min(6)),
+            None => (self.sections.len(), self.sections.iter().map(|section| section.level).min().unwrap_or(1)),
+        };
+        let section = MarkdownSection::new(level, title, body);
+        self.insert_section(index, section);
+        index
+    }
+
+    pub fn insert_section(&mut self, index: usize, mut section: MarkdownSection) {
+        // Keep a blank line between the end of the previous section and the new heading.
+        let previous_lines = if index == 0 { &mut self.preamble } else { &mut self.sections[index - 1].body };
+        if previous_lines.last().is_some_and(|line| !line.trim().is_empty()) {
+            previous_lines.push("".to_string());
+        }
+        section.index = index;
+        self.sections.insert(index, section);
+        self.renumber();
+    }
+
+    pub fn remove_section(&mut self, index: usize) -> Vec<MarkdownSection> {
+        // Remove the section along with its subsections and return them.
+        let end = self.subtree_end(index);
+        let removed = self.sections.drain(index..end).collect();
+        self.renumber();
+        removed
+    }
+
+    pub fn set_title(&mut self, index: usize, title: &str) {
+        let section = &mut self.sections[index];
+        section.title = title.to_string();
+        section.heading_lines = heading_lines(section.level, title, section.heading_lines.len() == 2);
+    }
+
+    pub fn set_level(&mut self, index: usize, level: usize) {
+        // Setext headings only come in levels 1 and 2, so other levels switch to ATX style.
+        let section = &mut self.sections[index];
+        section.level = level.clamp(1, 6);
+        section.heading_lines = heading_lines(section.level, &section.title, section.heading_lines.len() == 2);
+    }
+
+    pub fn set_body(&mut self, index: usize, body: &str) {
+        self.sections[index].body = body_lines(body);
+    }
+
+    fn push_section(&mut self, level: usize, title: String, heading_lines: Vec<String>) {
+        let index = self.sections.len();
+        self.sections.push(MarkdownSection { index, level, title, heading_lines, body: vec![] });
+    }
+
+    fn push_body_line(&mut self, line: &str) {
+        match self.sections.last_mut() {
+            Some(section) => section.body.push(line.to_string()),
+            None => self.preamble.push(line.to_string()),
+        }
+    }
+
+    fn renumber(&mut self) {
+        for (index, section) in self.sections.iter_mut().enumerate() {
+            section.index = index;
+        }
+    }
+}
+
+impl Default for MarkdownDocument {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MarkdownSection {
+    pub fn new(level: usize, title: &str, body: &str) -> Self {
+        let level = level.clamp(1, 6);
+        Self { index: 0, level, title: title.to_string(), heading_lines: heading_lines(level, title, false), body: body_lines(body) }
+    }
+
+    pub fn body_text(&self) -> String {
+        // The body without the blank lines around it.
+        let start = self.body.iter().position(|line| !line.trim().is_empty()).unwrap_or(self.body.len());
+        let end = self.body.iter().rposition(|line| !line.trim().is_empty()).map_or(start, |index| index + 1);
+        self.body[start..end].join("\n")
+    }
+}
+
+impl Display for MarkdownSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", "#".repeat(self.level), self.title)
+    }
+}
+
+fn heading_lines(level: usize, title: &str, setext: bool) -> Vec<String> {
+    if setext && level <= 2 {
+        let underline = if level == 1 { "=" } else { "-" };
+        vec![title.to_string(), underline.repeat(title.chars().count().max(3))]
+    } else {
+        vec![format!("{} {}", "#".repeat(level), title)]
+    }
+}
+
+fn body_lines(body: &str) -> Vec<String> {
+    // A body that isn't empty ends with a blank line to separate it from the next heading.
+    let mut lines = body.trim_end().lines().map(|line| line.to_string()).collect::<Vec<_>>();
+    if !lines.is_empty() {
+        lines.push("".to_string());
+    }
+    lines
+}
+
+fn block_indent(line: &str) -> Option<&str> {
+    // Markdown allows up to three spaces before a heading or fence. Four or more make the line an
+    // indented code block.
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() <= 3 && !trimmed.starts_with('\t') {
+        Some(trimmed)
+    } else {
+        None
+    }
+}
+
+fn parse_atx_heading(line: &str) -> Option<(usize, String)> {
+    let trimmed = block_indent(line)?;
+    let level = trimmed.chars().take_while(|c| *c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let rest = &trimmed[level..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+    // An optional closing sequence of #'s is not part of the title, as in "## Title ##".
+    let title = rest.trim();
+    let without_closing = title.trim_end_matches('#');
+    let title = if without_closing.is_empty() || without_closing.ends_with([' ', '\t']) { without_closing.trim_end() } else { title };
+    Some((level, title.to_string()))
+}
+
+fn is_setext_text(line: &str) -> bool {
+    // Lines that would be list items, quotes, tables or other block markup can't be setext
+    // heading text.
+    match block_indent(line) {
+        Some(trimmed) => !trimmed.trim().is_empty() && !trimmed.starts_with(['-', '*', '+', '>', '|', '<', '=']) && opening_fence(line).is_none(),
+        None => false,
+    }
+}
+
+fn setext_underline_level(line: &str) -> Option<usize> {
+    let trimmed = block_indent(line)?.trim_end();
+    if !trimmed.is_empty() && trimmed.chars().all(|c| c == '=') {
+        Some(1)
+    } else if !trimmed.is_empty() && trimmed.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+fn opening_fence(line: &str) -> Option<String> {
+    // A fence is at least three backticks or tildes. The returned text is the fence itself, which
+    // the closing fence has to match or exceed.
+    let trimmed = block_indent(line)?;
+    let fence_char = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let fence_len = trimmed.chars().take_while(|c| *c == fence_char).count();
+    if fence_len < 3 || (fence_char == '`' && trimmed[fence_len..].contains('`')) {
+        return None;
+    }
+    Some(trimmed[..fence_len].to_string())
+}
+
+fn is_closing_fence(line: &str, fence: &str) -> bool {
+    let fence_char = fence.chars().next().unwrap();
+    match block_indent(line) {
+        Some(trimmed) => {
+            let fence_len = trimmed.chars().take_while(|c| *c == fence_char).count();
+            fence_len >= fence.len() && trimmed[fence_len..].trim().is_empty()
+        },
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "Intro text.
+
+# Installation
+
+Get the code.
+
+## Linux ##
+
+```sh
+# Not a heading.
+make install
+```
+
+Windows
+-------
+
+Run setup.exe.
+
+### Notes
+#hashtag is not a heading.
+
+Usage
+=====
+
+- item
+---
+
+## Linux
+";
+
+    #[test]
+    fn test_parse_markdown() {
+        let document = MarkdownDocument::parse(SAMPLE);
+        assert_eq!(vec!["Intro text.", ""], document.preamble);
+        let outline = document.sections.iter().map(|section| (section.level, section.title.as_str())).collect::<Vec<_>>();
+        assert_eq!(vec![(1, "Installation"), (2, "Linux"), (2, "Windows"), (3, "Notes"), (1, "Usage"), (2, "Linux")], outline);
+        assert_eq!("```sh\n# Not a heading.\nmake install\n```", document.sections[1].body_text());
+        assert_eq!("#hashtag is not a heading.", document.sections[3].body_text());
+        assert_eq!("- item\n---", document.sections[4].body_text());
+        assert_eq!(Some(2), document.parent_index(3));
+        assert_eq!(vec![1, 2], document.child_indexes(Some(0)));
+        assert_eq!(vec![0, 4], document.child_indexes(None));
+        assert_eq!(vec!["Installation", "Windows", "Notes"], document.title_path(3));
+        assert_eq!(Some(5), document.find_section(&["Usage", "Linux"]));
+        assert!(document.find_section_r(&["Usage", "Mac"]).is_err());
+
+        let tree = document.heading_tree();
+        assert_eq!(2, tree.top_nodes.len());
+        assert_eq!(3, tree.height());
+        assert_eq!(6, tree.node_count());
+        assert_eq!("Installation", b!(&tree.top_nodes[0]).item.title);
+
+        // Nothing was changed, so the text comes back exactly as it was.
+        assert_eq!(SAMPLE, document.to_text());
+        let crlf = SAMPLE.replace('\n', "\r\n");
+        assert_eq!(crlf, MarkdownDocument::parse(&crlf).to_text());
+    }
+
+    #[test]
+    fn test_edit_markdown() {
+        let mut document = MarkdownDocument::parse(SAMPLE);
+        let windows = document.find_section(&["Installation", "Windows"]).unwrap();
+        document.set_title(windows, "Windows 11");
+        document.set_body(windows, "Run setup.exe as administrator.");
+        let mac = document.add_section(Some(0), "Mac", "Use Homebrew.");
+        assert_eq!(4, mac);
+        assert_eq!(vec!["Installation", "Mac"], document.title_path(mac));
+        let removed = document.remove_section(document.find_section(&["Usage"]).unwrap());
+        assert_eq!(2, removed.len());
+        document.add_section(None, "License", "MIT");
+
+        let expected = "Intro text.
+
+# Installation
+
+Get the code.
+
+## Linux ##
+
+```sh
+# Not a heading.
+make install
+```
+
+Windows 11
+----------
+Run setup.exe as administrator.
+
+### Notes
+#hashtag is not a heading.
+
+## Mac
+Use Homebrew.
+
+# License
+MIT
+
+";
+        assert_eq!(expected, document.to_text());
+        let reparsed = MarkdownDocument::parse(&document.to_text());
+        assert_eq!(document.sections, reparsed.sections);
+
+        let mut empty = MarkdownDocument::new();
+        assert_eq!(0, empty.heading_tree().top_nodes.len());
+        empty.add_section(None, "Only", "");
+        assert_eq!("# Only\n", empty.to_text());
+    }
+}
@@ -1,20 +1,18 @@
 use std::{fs, path};
-use std::io::{self, BufRead, BufReader};
+use std::io;
 use std::collections::btree_map::BTreeMap;
 use glob::{glob_with, MatchOptions};
-use std::fs::File;
 use crate::err_context;
 use itertools::Itertools;
 
+pub mod grammar;
+
 pub fn main() {
     try_split_delimited_and_normal_rc();
 }
 
 pub fn read_file_as_lines(file_name: &str) -> Vec<String> {
-    let file = File::open(file_name).unwrap();
-    let reader = BufReader::new(file);
-    // Read the file line by line using the lines() iterator from std::io::BufRead.
-    reader.lines().map(|line| line.unwrap()).collect::<Vec<_>>()
+    crate::file::read_file_as_lines_r(file_name).unwrap()
 }
 
 pub fn read_file_into_sections(file_name: &str, header_prefix: &str) -> BTreeMap<String, String> {
@@ -270,81 +268,109 @@ pub fn delimited_entries_trim(text: &str, left_delimiter: &str, right_delimiter:
     trim_string_vector(&delimited_entries(text, left_delimiter, right_delimiter))
 }
 
-pub fn split_delimited_and_normal_rc(text: &str, left_delimiter: &str, right_delimiter: &str, trim: bool, context: &str) -> Result<Vec<(bool, String)>, String> {
-    // if text.eq("on_data_structures_and_algorithms_with_rust|Hands-On Data Structures and Algorithms with Rust]]") { panic!() }
-    // let debug = text.contains("on_data_structures_and_algorithms_with_rust|Hands");
-    let debug = false;
-    if debug { dbg!(text, left_delimiter, right_delimiter, context, trim); }
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DelimitedToken {
+    // The index of the delimiter pair that encloses this token, or None for text that's outside
+    // of any delimiters.
+    pub pair_index: Option<usize>,
+    // The text without the outer delimiters. Anything nested inside is left as it is, so it can be
+    // tokenized again to get at the inner levels.
+    pub text: String,
+    // Byte offsets of the whole token in the original text, including its delimiters.
+    pub start: usize,
+    pub end: usize,
+}
+
+impl DelimitedToken {
+    pub fn is_delimited(&self) -> bool {
+        self.pair_index.is_some()
+    }
+}
+
+pub fn tokenize_delimited_rc(text: &str, pairs: &[(&str, &str)], escape: Option<char>, context: &str) -> Result<Vec<DelimitedToken>, String> {
+    // Break the text into a flat list of tokens, each either plain text or one top-level delimited
+    // section. Delimiters may be nested to any depth and may be of different kinds, as in
+    // "[[a|[[b]] (c)]]", but they have to be closed in the right order. A character after the
+    // escape character is never treated as part of a delimiter. A pair whose left and right
+    // delimiters are the same, like quotes, can't be nested, so nothing inside it is treated as a
+    // delimiter except the closing one.
     let err_func = |pos: usize, msg: &str| Err(
-        format!("{} split_delimited_and_normal_rc: pos = {}: {} left = \"{}\"; right = \"{}\"; trim = {}; text = \"{}\".",
-        context, pos, msg, left_delimiter, right_delimiter, trim, text));
-    let text = if trim { text.trim().to_string() } else { text.to_string() };
-    let mut v = vec![];
+        format!("{} tokenize_delimited_rc: pos = {}: {} text = \"{}\".", context, pos, msg, text));
+    if pairs.iter().any(|(left, right)| left.is_empty() || right.is_empty()) {
+        return err_func(0, "Delimiters can't be empty.");
+    }
+    let mut tokens = vec![];
+    // The pair index and starting position of each delimiter that hasn't been closed yet.
+    let mut open: Vec<(usize, usize)> = vec![];
+    let mut text_start = 0;
     let mut pos = 0;
     while pos < text.len() {
-        let next_left = text[pos..].find(left_delimiter).map(|x| x + pos);
-        let next_right = if left_delimiter == right_delimiter {
-            // The left and right delimiters are the same, so we have to assume that the first
-            // delimiter found is the left one, and the right delimiter can only be sought after
-            // that left delimiter.
-            if text.len() - 1 > pos {
-                text[pos + 1..].find(right_delimiter).map(|x| x + pos + 1)
-            } else {
-                // There are no characters after pos.
-                None
-            }
-        } else {
-            // The left and right delimiters are different, so we don't have to be as precise about
-            // where to look.
-            text[pos..].find(right_delimiter).map(|x| x + pos)
-        };
-        if debug { println!("\nTop of loop:"); dbg!(pos, next_left, next_right); }
-        match next_left {
-            Some(next_left) => {
-                // We expect that we're seeing a non-delimited substring (which may be zero length)
-                // followed by a delimited substring, then the remainder of the text.
-                // Don't bother adding a non-delimited substring if the text starts with a
-                // delimited substring, that is a left delimiter in position 0, or if there's a new
-                // delimited substring immediately following the last one.
-                if next_left > pos {
-                    let text_one = &text[pos..next_left];
-                    let text_one = if trim { text_one.trim().to_string() } else { text_one.to_string() };
-                    v.push((false, text_one));
-                }
-                // Presumably there's a right delimiter to match the left one.
-                match next_right {
-                    Some(next_right) => {
-                        if next_right < next_left {
-                            if debug { panic!("Extra right delimiter."); }
-                            return err_func(pos, "Extra right delimiter.");
-                        }
-                        let pos_substring_start = next_left + left_delimiter.len();
-                        if debug { dbg!(pos_substring_start); }
-                        let text_one = &text[pos_substring_start..next_right];
-                        let text_one = if trim { text_one.trim().to_string() } else { text_one.to_string() };
-                        v.push((true, text_one));
-                        pos = next_right + right_delimiter.len();
-                    },
-                    None => {
-                        if debug { panic!("Missing right delimiter."); }
-                        return err_func(pos, "Missing right delimiter.");
-                    },
-                };
-            },
-            None => {
-                // The remainder of the text is assumed to be a non-delimited substring.
-                if next_right.is_some() {
-                    // There's an unexpected delimiter.
-                    return err_func(pos, "Extra right delimiter.");
+        let rest = &text[pos..];
+        if let Some(escape) = escape.filter(|escape| rest.starts_with(*escape)) {
+            // Skip the escape character and whatever follows it.
+            pos += escape.len_utf8();
+            pos += text[pos..].chars().next().map_or(0, |c| c.len_utf8());
+            continue;
+        }
+        if let Some((pair_index, pos_open)) = open.last().copied() {
+            let (left, right) = pairs[pair_index];
+            if rest.starts_with(right) {
+                pos += right.len();
+                open.pop();
+                if open.is_empty() {
+                    tokens.push(DelimitedToken {
+                        pair_index: Some(pair_index),
+                        text: text[pos_open + left.len()..pos - right.len()].to_string(),
+                        start: pos_open,
+                        end: pos,
+                    });
+                    text_start = pos;
                 }
-                let text_one = &text[pos..];
-                let text_one = if trim { text_one.trim().to_string() } else { text_one.to_string() };
-                v.push((false, text_one));
-                break;
+                continue;
             }
+            if left == right {
+                pos += rest.chars().next().unwrap().len_utf8();
+                continue;
+            }
+        }
+        // If more than one left delimiter matches, such as "[" and "[[", take the longest.
+        let next_left = pairs.iter().enumerate()
+            .filter(|(_, (left, _))| rest.starts_with(left))
+            .max_by_key(|(_, (left, _))| left.len());
+        if let Some((pair_index, (left, _))) = next_left {
+            if open.is_empty() && pos > text_start {
+                tokens.push(DelimitedToken { pair_index: None, text: text[text_start..pos].to_string(), start: text_start, end: pos });
+            }
+            open.push((pair_index, pos));
+            pos += left.len();
+            continue;
         }
+        if let Some((_, right)) = pairs.iter().find(|(_, right)| rest.starts_with(right)) {
+            return match open.last() {
+                Some((pair_index, pos_open)) => err_func(pos, &format!("Right delimiter \"{}\" doesn't match left delimiter \"{}\" at {}.", right, pairs[*pair_index].0, pos_open)),
+                None => err_func(pos, &format!("Extra right delimiter \"{}\".", right)),
+            };
+        }
+        pos += rest.chars().next().unwrap().len_utf8();
     }
-    Ok(v)
+    if let Some((pair_index, pos_open)) = open.last() {
+        return err_func(*pos_open, &format!("Missing right delimiter for \"{}\".", pairs[*pair_index].0));
+    }
+    if text.len() > text_start {
+        tokens.push(DelimitedToken { pair_index: None, text: text[text_start..].to_string(), start: text_start, end: text.len() });
+    }
+    Ok(tokens)
+}
+
+pub fn split_delimited_and_normal_rc(text: &str, left_delimiter: &str, right_delimiter: &str, trim: bool, context: &str) -> Result<Vec<(bool, String)>, String> {
+    // The same as tokenize_delimited_rc() with a single pair of delimiters and no escape character,
+    // giving each piece as a flag for whether it was delimited and the text.
+    let context = format!("{} split_delimited_and_normal_rc:", context);
+    let text = if trim { text.trim() } else { text };
+    let tokens = tokenize_delimited_rc(text, &[(left_delimiter, right_delimiter)], None, &context)?;
+    Ok(tokens.into_iter()
+        .map(|token| (token.is_delimited(), if trim { token.text.trim().to_string() } else { token.text }))
+        .collect())
 }
 
 pub fn replace_within_delimiters_rc(text: &str, left_delimiter: &str, right_delimiter: &str, from: &str, to: &str, trim: bool, context: &str) -> Result<String, String> {
@@ -379,16 +405,63 @@ pub fn split_outside_of_delimiters_rc(text: &str, split_delimiter: &str, left_de
     //   | [[tools:nav:attributes#Narrator|Narrator]] | [[tools:nav:attribute_values#Mark Steinberg|Mark Steinberg]] |
     // We want to split the cells on the pipe characters, ignoring those pipe characters that
     // appear inside the bracketed parts.
-    let placeholder = "{{{split_outside_of_delimiters}}}";
-    // Replace the split delimiters we don't want to split on with a placeholder.
-    let temp_with_replacements = replace_within_delimiters_rc(text, left_delimiter, right_delimiter, split_delimiter, placeholder, trim, context)?;
-    // Split on the remaining split delimiters, then put back the ones we took out earlier.
-    let splits = temp_with_replacements.split(split_delimiter).into_iter()
-        .map(|split| split.replace(placeholder, split_delimiter))
-        .collect::<Vec<_>>();
+    split_outside_of_delimiter_pairs_rc(text, split_delimiter, &[(left_delimiter, right_delimiter)], None, trim, context)
+}
+
+pub fn split_outside_of_delimiter_pairs_rc(text: &str, split_delimiter: &str, pairs: &[(&str, &str)], escape: Option<char>, trim: bool, context: &str) -> Result<Vec<String>, String> {
+    // Like split_outside_of_delimiters_rc() but with any number of kinds of delimiters, nested or
+    // not, and an optional escape character. A split delimiter right after the escape character
+    // doesn't count. The pieces keep their delimiters and escape characters.
+    if split_delimiter.is_empty() {
+        return Err(format!("{} split_outside_of_delimiter_pairs_rc: the split delimiter is empty.", context));
+    }
+    let mut splits = vec![];
+    let mut current = String::new();
+    for token in tokenize_delimited_rc(text, pairs, escape, context)? {
+        if token.is_delimited() {
+            current.push_str(&text[token.start..token.end]);
+            continue;
+        }
+        let mut rest = token.text.as_str();
+        loop {
+            let next_split = find_unescaped(rest, split_delimiter, escape);
+            match next_split {
+                Some(pos) => {
+                    current.push_str(&rest[..pos]);
+                    splits.push(std::mem::take(&mut current));
+                    rest = &rest[pos + split_delimiter.len()..];
+                },
+                None => {
+                    current.push_str(rest);
+                    break;
+                },
+            }
+        }
+    }
+    splits.push(current);
+    if trim {
+        splits = trim_string_vector(&splits);
+    }
     Ok(splits)
 }
 
+fn find_unescaped(text: &str, pat: &str, escape: Option<char>) -> Option<usize> {
+    let mut pos = 0;
+    while pos < text.len() {
+        let rest = &text[pos..];
+        if rest.starts_with(pat) {
+            return Some(pos);
+        }
+        let mut chars = rest.chars();
+        let c = chars.next().unwrap();
+        pos += c.len_utf8();
+        if Some(c) == escape {
+            pos += chars.next().map_or(0, |c| c.len_utf8());
+        }
+    }
+    None
+}
+
 pub fn split_once_with_option(value: &str, delimiter: &str) -> (String, Option<String>) {
     if value.contains(delimiter) {
         let (a, b) = value.split_once(delimiter).unwrap();
@@ -734,6 +807,41 @@ mod tests {
         assert_eq!(exp, act);
     }
 
+    #[test]
+    fn test_tokenize_delimited_rc() {
+        let context = "test_tokenize_delimited_rc";
+        let pairs = [("[[", "]]"), ("(", ")")];
+        let tokens = tokenize_delimited_rc("ab [[a|[[b]] (c)]] (d) e", &pairs, None, context).unwrap();
+        let act = tokens.iter().map(|token| (token.pair_index, token.text.as_str(), token.start, token.end)).collect::<Vec<_>>();
+        assert_eq!(vec![(None, "ab ", 0, 3), (Some(0), "a|[[b]] (c)", 3, 18), (None, " ", 18, 19), (Some(1), "d", 19, 22), (None, " e", 22, 24)], act);
+
+        // An escaped delimiter is just text.
+        let tokens = tokenize_delimited_rc(r"a\[[b [[c\]]]", &pairs, Some('\\'), context).unwrap();
+        assert_eq!(vec![None, Some(0)], tokens.iter().map(|token| token.pair_index).collect::<Vec<_>>());
+        assert_eq!(r"c\]", tokens[1].text);
+
+        // Quotes don't nest, so a bracket inside them doesn't need to be closed.
+        let tokens = tokenize_delimited_rc("\"a [b\" [c]", &[("\"", "\""), ("[", "]")], None, context).unwrap();
+        assert_eq!(vec!["a [b", " ", "c"], tokens.iter().map(|token| token.text.as_str()).collect::<Vec<_>>());
+
+        assert!(tokenize_delimited_rc("a ]] b", &pairs, None, context).unwrap_err().contains("Extra right delimiter"));
+        assert!(tokenize_delimited_rc("[[a (b]] c)", &pairs, None, context).unwrap_err().contains("doesn't match"));
+        assert!(tokenize_delimited_rc("[[a [[b]]", &pairs, None, context).unwrap_err().contains("Missing right delimiter"));
+    }
+
+    #[test]
+    fn test_split_outside_of_delimiter_pairs_rc() {
+        let context = "test_split_outside_of_delimiter_pairs_rc";
+        let pairs = [("[[", "]]"), ("(", ")")];
+        let splits = split_outside_of_delimiter_pairs_rc("a | [[b|[[c|d]]]] | (e | f) | g\\|h", "|", &pairs, Some('\\'), true, context).unwrap();
+        assert_eq!(vec!["a", "[[b|[[c|d]]]]", "(e | f)", "g\\|h"], splits);
+        // The placeholder text that was used before is ordinary content now.
+        let splits = split_outside_of_delimiters_rc("{{{split_outside_of_delimiters}}}|[[a|b]]", "|", "[[", "]]", false, context).unwrap();
+        assert_eq!(vec!["{{{split_outside_of_delimiters}}}", "[[a|b]]"], splits);
+        let exp = vec![(false, "a ".to_string()), (true, "b [[c]]".to_string())];
+        assert_eq!(exp, split_delimited_and_normal_rc("a [[b [[c]]]]", "[[", "]]", false, context).unwrap());
+    }
+
     #[test]
     fn test_trim_linefeeds() {
         assert_eq!("abc", trim_linefeeds("abc"));
@@ -0,0 +1,384 @@
+// Parser combinators for describing a line format declaratively rather than slicing it apart with
+// before(), after() and split_2() from the parent module, which panic or quietly return the wrong
+// piece when a delimiter is missing. Each parser takes an Input, which knows its position in the
+// whole text, and on failure returns a ParseError saying which column it failed at and what it
+// expected there. For example:
+//
+//     let tags = delimited(literal("["), separated_list(take_while1("a tag", char::is_alphanumeric), literal(",")), literal("]"));
+//     let line = pair(terminated(take_until(" "), spaces()), tags);
+//     let (name, tags) = line.parse_all_r("Rome [history,italy]")?;
+
+use std::fmt::{self, Display};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Input<'a> {
+    text: &'a str,
+    // A byte offset into text.
+    position: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    // The byte offset into the text and the one-based column in characters.
+    pub position: usize,
+    pub column: usize,
+    pub expected: String,
+    // A little of the text at the position, or "end of text".
+    pub found: String,
+}
+
+pub type ParseResult<'a, T> = Result<(T, Input<'a>), ParseError>;
+
+// How much of the remaining text to show in an error.
+const FOUND_MAX_CHARS: usize = 12;
+
+pub trait Parser<'a, T> {
+    fn parse(&self, input: Input<'a>) -> ParseResult<'a, T>;
+
+    fn parse_all(&self, text: &'a str) -> Result<T, ParseError> {
+        // Parse the whole text. Anything left over is an error.
+        let (value, rest) = self.parse(Input::new(text))?;
+        if rest.is_at_end() {
+            Ok(value)
+        } else {
+            Err(rest.error("end of text"))
+        }
+    }
+
+    fn parse_all_r(&self, text: &'a str) -> Result<T, String> {
+        // The error message includes the text with a caret under the column where parsing failed.
+        self.parse_all(text).map_err(|error| error.describe(text))
+    }
+}
+
+impl <'a, T, F> Parser<'a, T> for F
+    where F: Fn(Input<'a>) -> ParseResult<'a, T>
+{
+    fn parse(&self, input: Input<'a>) -> ParseResult<'a, T> {
+        self(input)
+    }
+}
+
+impl <'a> Input<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Self { text, position: 0 }
+    }
+
+    pub fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn column(&self) -> usize {
+        self.text[..self.position].chars().count() + 1
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.position >= self.text.len()
+    }
+
+    pub fn error(&self, expected: &str) -> ParseError {
+        let rest = self.rest();
+        let found = if rest.is_empty() {
+            "end of text".to_string()
+        } else if rest.chars().count() > FOUND_MAX_CHARS {
+            format!("\"{}...\"", rest.chars().take(FOUND_MAX_CHARS).collect::<String>())
+        } else {
+            format!("\"{}\"", rest)
+        };
+        ParseError { position: self.position, column: self.column(), expected: expected.to_string(), found }
+    }
+
+    fn advance(&self, byte_count: usize) -> (&'a str, Self) {
+        // The text that was consumed and the input after it.
+        let taken = &self.text[self.position..self.position + byte_count];
+        (taken, Self { text: self.text, position: self.position + byte_count })
+    }
+}
+
+impl ParseError {
+    pub fn describe(&self, text: &str) -> String {
+        // Like "Column 5: expected "]" but found end of text." followed by the text and a caret.
+        format!("{}\n{}\n{}^", self, text, " ".repeat(self.column - 1))
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Column {}: expected {} but found {}.", self.column, self.expected, self.found)
+    }
+}
+
+pub fn literal<'a>(expected: &str) -> impl Parser<'a, &'a str> {
+    let expected = expected.to_string();
+    move |input: Input<'a>| {
+        if input.rest().starts_with(&expected) {
+            Ok(input.advance(expected.len()))
+        } else {
+            Err(input.error(&format!("\"{}\"", expected)))
+        }
+    }
+}
+
+pub fn take_until<'a>(pat: &str) -> impl Parser<'a, &'a str> {
+    // Everything up to but not including the next occurrence of the pattern, which must be there.
+    let pat = pat.to_string();
+    move |input: Input<'a>| {
+        match input.rest().find(&pat) {
+            Some(index) => Ok(input.advance(index)),
+            None => Err(input.error(&format!("text followed by \"{}\"", pat))),
+        }
+    }
+}
+
+pub fn take_while<'a, F>(predicate: F) -> impl Parser<'a, &'a str>
+    where F: Fn(char) -> bool
+{
+    // Zero or more characters, so this never fails.
+    move |input: Input<'a>| {
+        let byte_count = input.rest().char_indices().find(|(_, c)| !predicate(*c)).map_or(input.rest().len(), |(index, _)| index);
+        Ok(input.advance(byte_count))
+    }
+}
+
+pub fn take_while1<'a, F>(description: &str, predicate: F) -> impl Parser<'a, &'a str>
+    where F: Fn(char) -> bool
+{
+    // At least one character. The description is used in the error, as in "expected a digit".
+    let description = description.to_string();
+    let inner = take_while(predicate);
+    move |input: Input<'a>| {
+        let (taken, rest) = inner.parse(input)?;
+        if taken.is_empty() {
+            Err(input.error(&description))
+        } else {
+            Ok((taken, rest))
+        }
+    }
+}
+
+pub fn rest<'a>() -> impl Parser<'a, &'a str> {
+    |input: Input<'a>| Ok(input.advance(input.rest().len()))
+}
+
+pub fn end<'a>() -> impl Parser<'a, ()> {
+    |input: Input<'a>| {
+        if input.is_at_end() {
+            Ok(((), input))
+        } else {
+            Err(input.error("end of text"))
+        }
+    }
+}
+
+pub fn spaces<'a>() -> impl Parser<'a, &'a str> {
+    take_while(char::is_whitespace)
+}
+
+pub fn trimmed<'a, P, T>(parser: P) -> impl Parser<'a, T>
+    where P: Parser<'a, T>
+{
+    // Skip whitespace before and after.
+    delimited(spaces(), parser, spaces())
+}
+
+pub fn usize_number<'a>() -> impl Parser<'a, usize> {
+    map_r(take_while1("a number", |c| c.is_ascii_digit()), |digits: &str| digits.parse::<usize>().map_err(|e| e.to_string()))
+}
+
+pub fn pair<'a, A, B, TA, TB>(first: A, second: B) -> impl Parser<'a, (TA, TB)>
+    where
+        A: Parser<'a, TA>,
+        B: Parser<'a, TB>,
+{
+    move |input: Input<'a>| {
+        let (value_a, input) = first.parse(input)?;
+        let (value_b, input) = second.parse(input)?;
+        Ok(((value_a, value_b), input))
+    }
+}
+
+pub fn preceded<'a, A, B, TA, TB>(first: A, second: B) -> impl Parser<'a, TB>
+    where
+        A: Parser<'a, TA>,
+        B: Parser<'a, TB>,
+{
+    // Only the value from the second parser is kept.
+    map(pair(first, second), |(_, value)| value)
+}
+
+pub fn terminated<'a, A, B, TA, TB>(first: A, second: B) -> impl Parser<'a, TA>
+    where
+        A: Parser<'a, TA>,
+        B: Parser<'a, TB>,
+{
+    // Only the value from the first parser is kept.
+    map(pair(first, second), |(value, _)| value)
+}
+
+pub fn delimited<'a, L, P, R, TL, T, TR>(left: L, inner: P, right: R) -> impl Parser<'a, T>
+    where
+        L: Parser<'a, TL>,
+        P: Parser<'a, T>,
+        R: Parser<'a, TR>,
+{
+    move |input: Input<'a>| {
+        let (_, input) = left.parse(input)?;
+        let (value, input) = inner.parse(input)?;
+        let (_, input) = right.parse(input)?;
+        Ok((value, input))
+    }
+}
+
+pub fn optional<'a, P, T>(parser: P) -> impl Parser<'a, Option<T>>
+    where P: Parser<'a, T>
+{
+    // If the parser fails, nothing is consumed and the result is None.
+    move |input: Input<'a>| {
+        match parser.parse(input) {
+            Ok((value, rest)) => Ok((Some(value), rest)),
+            Err(_) => Ok((None, input)),
+        }
+    }
+}
+
+pub fn either<'a, A, B, T>(first: A, second: B) -> impl Parser<'a, T>
+    where
+        A: Parser<'a, T>,
+        B: Parser<'a, T>,
+{
+    // Try the first parser, then the second. If both fail, the error is from whichever got further
+    // since that's usually the more useful one.
+    move |input: Input<'a>| {
+        match first.parse(input) {
+            Ok(result) => Ok(result),
+            Err(error_first) => match second.parse(input) {
+                Ok(result) => Ok(result),
+                Err(error_second) if error_second.position > error_first.position => Err(error_second),
+                Err(_) => Err(error_first),
+            },
+        }
+    }
+}
+
+pub fn many<'a, P, T>(parser: P) -> impl Parser<'a, Vec<T>>
+    where P: Parser<'a, T>
+{
+    // Zero or more times. Stops if the parser succeeds without consuming anything so that it
+    // can't loop forever.
+    move |input: Input<'a>| {
+        let mut values = vec![];
+        let mut input = input;
+        while let Ok((value, rest)) = parser.parse(input) {
+            if rest.position == input.position {
+                break;
+            }
+            values.push(value);
+            input = rest;
+        }
+        Ok((values, input))
+    }
+}
+
+pub fn separated_list<'a, P, S, T, TS>(item: P, separator: S) -> impl Parser<'a, Vec<T>>
+    where
+        P: Parser<'a, T>,
+        S: Parser<'a, TS>,
+{
+    // Zero or more items. Once a separator has been found an item has to follow it, so "a,b," is
+    // an error at the end rather than a list of two items followed by a stray comma.
+    move |input: Input<'a>| {
+        let mut values = vec![];
+        let (first, mut input) = match item.parse(input) {
+            Ok(result) => result,
+            Err(_) => return Ok((values, input)),
+        };
+        values.push(first);
+        while let Ok((_, after_separator)) = separator.parse(input) {
+            let (value, rest) = item.parse(after_separator)?;
+            values.push(value);
+            input = rest;
+        }
+        Ok((values, input))
+    }
+}
+
+pub fn map<'a, P, F, T, U>(parser: P, func: F) -> impl Parser<'a, U>
+    where
+        P: Parser<'a, T>,
+        F: Fn(T) -> U,
+{
+    move |input: Input<'a>| {
+        let (value, rest) = parser.parse(input)?;
+        Ok((func(value), rest))
+    }
+}
+
+pub fn map_r<'a, P, F, T, U>(parser: P, func: F) -> impl Parser<'a, U>
+    where
+        P: Parser<'a, T>,
+        F: Fn(T) -> Result<U, String>,
+{
+    // Like map() but the function can fail, such as when converting text to a number. The error
+    // points to where the parser started.
+    move |input: Input<'a>| {
+        let (value, rest) = parser.parse(input)?;
+        match func(value) {
+            Ok(value) => Ok((value, rest)),
+            Err(message) => Err(input.error(&format!("a valid value ({})", message))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_basic_parsers() {
+        assert_eq!(Ok("ab"), literal("ab").parse_all("ab"));
+        let error = literal("ab").parse_all("ax").unwrap_err();
+        assert_eq!((0, 1), (error.position, error.column));
+        assert_eq!("Column 1: expected \"ab\" but found \"ax\".", error.to_string());
+
+        let (taken, rest) = take_until(": ").parse(Input::new("key: value")).unwrap();
+        assert_eq!(("key", ": value"), (taken, rest.rest()));
+        assert!(take_until(": ").parse(Input::new("no delimiter")).is_err());
+
+        assert_eq!(Ok(""), take_while(|c: char| c.is_ascii_digit()).parse_all(""));
+        assert_eq!(Ok(42), usize_number().parse_all("42"));
+        assert_eq!("Column 1: expected a number but found \"x\".", usize_number().parse_all("x").unwrap_err().to_string());
+        assert!(usize_number().parse_all("99999999999999999999999").is_err());
+        // Leftover text is an error.
+        assert_eq!(3, usize_number().parse_all("12 ").unwrap_err().column);
+        // Columns count characters rather than bytes.
+        assert_eq!(4, preceded(literal("été"), end()).parse_all("étéx").unwrap_err().column);
+    }
+
+    #[test]
+    fn test_combinators() {
+        let tag = take_while1("a tag", char::is_alphanumeric);
+        let tags = delimited(literal("["), separated_list(trimmed(tag), literal(",")), literal("]"));
+        let line = pair(terminated(take_until(" "), spaces()), tags);
+        assert_eq!(Ok(("Rome", vec!["history", "italy"])), line.parse_all("Rome [history, italy]"));
+        assert_eq!(Ok(("Rome", vec![])), line.parse_all("Rome []"));
+        // A separator with nothing after it.
+        assert_eq!(15, line.parse_all("Rome [history,]").unwrap_err().column);
+        let message = line.parse_all_r("Rome [history").unwrap_err();
+        assert_eq!("Column 14: expected \"]\" but found end of text.\nRome [history\n             ^", message);
+
+        let count = optional(delimited(literal("("), usize_number(), literal(")")));
+        let item = pair(take_while1("a name", char::is_alphabetic), count);
+        assert_eq!(Ok(("abc", Some(12))), item.parse_all("abc(12)"));
+        assert_eq!(Ok(("abc", None)), item.parse_all("abc"));
+
+        let yes_no = either(map(literal("yes"), |_| true), map(literal("no"), |_| false));
+        assert_eq!(Ok(vec![true, false, true]), many(terminated(yes_no, spaces())).parse_all("yes no yes"));
+        let size = map_r(usize_number(), |number| if number > 0 { Ok(number) } else { Err("zero".to_string()) });
+        assert_eq!("Column 1: expected a valid value (zero) but found \"0\".", size.parse_all("0").unwrap_err().to_string());
+        assert_eq!(Ok("anything"), rest().parse_all("anything"));
+    }
+}
@@ -0,0 +1,202 @@
+// Path handling that works the same way regardless of the platform the code happens to be running
+// on. Paths are treated as strings with either kind of separator so that a Windows path like
+// "E:\Temp\Bookmarks.html" can be normalized and compared on Linux and vice versa. Nothing here
+// touches the disk.
+
+use std::path::{PathBuf, MAIN_SEPARATOR_STR};
+
+use crate::file::canonical_path_name;
+
+// On Windows these are reserved as file names with or without an extension.
+const WINDOWS_RESERVED_NAMES: [&str; 22] = ["CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9"];
+const WINDOWS_INVALID_CHARS: &str = "<>:\"/\\|?*";
+const MAX_FILE_NAME_LEN: usize = 255;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileNamePlatform {
+    Windows,
+    MacOs,
+    Linux,
+    // A name that's valid under the rules of all three.
+    Portable,
+}
+
+pub fn normalize_separators(path: &str) -> String {
+    canonical_path_name(path)
+}
+
+pub fn native_path(path: &str) -> PathBuf {
+    // Turn a path written with either separator into one using this platform's separator.
+    PathBuf::from(normalize_separators(path).replace('/', MAIN_SEPARATOR_STR))
+}
+
+pub fn normalize_path(path: &str) -> String {
+    // Resolve "." and ".." lexically and remove doubled separators. A ".." that would go above the
+    // root of an absolute path is dropped, while one at the start of a relative path is kept since
+    // there's no way to know what it refers to.
+    let path = normalize_separators(path);
+    let (prefix, rest) = split_prefix(&path);
+    let mut components: Vec<&str> = vec![];
+    for component in rest.split('/') {
+        match component {
+            "" | "." => {},
+            ".." => {
+                match components.last() {
+                    Some(&last) if last != ".." => { components.pop(); },
+                    _ => if prefix.is_empty() { components.push(".."); },
+                }
+            },
+            _ => components.push(component),
+        }
+    }
+    let joined = components.join("/");
+    if prefix.is_empty() && joined.is_empty() {
+        ".".to_string()
+    } else {
+        format!("{}{}", prefix, joined)
+    }
+}
+
+pub fn relative_path_r(path_from_folder: &str, path_to: &str) -> Result<String, String> {
+    // The path that leads from the given folder to the target, as in a link from one file to
+    // another. Both paths have to be absolute or both relative, and on the same drive.
+    let from = normalize_path(path_from_folder);
+    let to = normalize_path(path_to);
+    let (from_prefix, from_rest) = split_prefix(&from);
+    let (to_prefix, to_rest) = split_prefix(&to);
+    if !from_prefix.eq_ignore_ascii_case(to_prefix) {
+        return Err(format!("relative_path_r: \"{}\" and \"{}\" don't share a root.", path_from_folder, path_to));
+    }
+    let from_components = from_rest.split('/').filter(|x| !x.is_empty() && *x != ".").collect::<Vec<_>>();
+    let to_components = to_rest.split('/').filter(|x| !x.is_empty() && *x != ".").collect::<Vec<_>>();
+    let common_count = from_components.iter().zip(to_components.iter()).take_while(|(a, b)| a == b).count();
+    if from_components[common_count..].contains(&"..") {
+        return Err(format!("relative_path_r: can't find the way from \"{}\" since it goes above its starting point.", path_from_folder));
+    }
+    let mut parts = vec![".."; from_components.len() - common_count];
+    parts.extend_from_slice(&to_components[common_count..]);
+    if parts.is_empty() {
+        Ok(".".to_string())
+    } else {
+        Ok(parts.join("/"))
+    }
+}
+
+fn split_prefix(path: &str) -> (&str, &str) {
+    // Split off the part of an already-normalized path that marks it as absolute: a drive like
+    // "C:/" or "C:", a UNC server and share like "//server/share/", or a plain "/".
+    let bytes = path.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        let len = if bytes.get(2) == Some(&b'/') { 3 } else { 2 };
+        return path.split_at(len);
+    }
+    if let Some(rest) = path.strip_prefix("//") {
+        // Include the server and share names.
+        let len = rest.match_indices('/').nth(1).map(|(index, _)| index + 1).unwrap_or(rest.len());
+        return path.split_at(2 + len);
+    }
+    if path.starts_with('/') {
+        return path.split_at(1);
+    }
+    ("", path)
+}
+
+pub fn sanitize_file_name(name: &str, platform: FileNamePlatform, substitute: &str) -> String {
+    // Make a single file name (not a path) valid for the given platform, replacing invalid
+    // characters with the substitute. This is a stricter version of format::windows_file_name().
+    let windows = matches!(platform, FileNamePlatform::Windows | FileNamePlatform::Portable);
+    let mac = matches!(platform, FileNamePlatform::MacOs | FileNamePlatform::Portable);
+    let mut sanitized = String::new();
+    for c in name.chars() {
+        let invalid = c == '/' || c == '\0'
+            || (windows && (WINDOWS_INVALID_CHARS.contains(c) || (c as u32) < 32))
+            || (mac && c == ':');
+        if invalid {
+            sanitized.push_str(substitute);
+        } else {
+            sanitized.push(c);
+        }
+    }
+    if windows {
+        // Windows silently drops trailing dots and spaces, so "notes." and "notes" would be the
+        // same file.
+        sanitized = sanitized.trim_end_matches(['.', ' ']).to_string();
+        let stem = sanitized.split('.').next().unwrap_or("").trim_end();
+        if WINDOWS_RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem)) {
+            sanitized.insert(stem.len(), '_');
+        }
+    }
+    if sanitized.is_empty() || sanitized == "." || sanitized == ".." {
+        sanitized = "_".to_string();
+    }
+    limit_file_name_len(&sanitized, MAX_FILE_NAME_LEN)
+}
+
+pub fn is_valid_file_name(name: &str, platform: FileNamePlatform) -> bool {
+    sanitize_file_name(name, platform, "_") == name
+}
+
+fn limit_file_name_len(name: &str, max_bytes: usize) -> String {
+    // Shorten the name to fit in the given number of bytes (which is the stricter of the limits
+    // on the three platforms) while keeping the extension and not splitting a character.
+    if name.len() <= max_bytes {
+        return name.to_string();
+    }
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && extension.len() < max_bytes / 2 => (stem, format!(".{}", extension)),
+        _ => (name, "".to_string()),
+    };
+    let mut stem_len = max_bytes - extension.len();
+    while !stem.is_char_boundary(stem_len) {
+        stem_len -= 1;
+    }
+    format!("{}{}", &stem[..stem_len], extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!("E:/Temp/Bookmarks.html", normalize_path(r"E:\Temp\.\Old\..\Bookmarks.html"));
+        assert_eq!("C:/", normalize_path(r"C:\Users\.."));
+        assert_eq!("/", normalize_path("/../.."));
+        assert_eq!("/a/c", normalize_path("/a//b/../c/"));
+        assert_eq!("../../a", normalize_path("../x/../../a"));
+        assert_eq!(".", normalize_path("a/.."));
+        assert_eq!("//server/share/b", normalize_path(r"\\server\share\a\..\b"));
+    }
+
+    #[test]
+    fn test_relative_path_r() {
+        assert_eq!("../Pages/Home.html", relative_path_r("/site/css", "/site/Pages/Home.html").unwrap());
+        assert_eq!("Home.html", relative_path_r(r"C:\site", "c:/site/Home.html").unwrap());
+        assert_eq!(".", relative_path_r("a/b/", "a/./b").unwrap());
+        assert!(relative_path_r("C:/a", "D:/a").is_err());
+        assert!(relative_path_r("/a", "a").is_err());
+        assert!(relative_path_r("..", "a").is_err());
+    }
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!("What_ Why_.txt", sanitize_file_name("What? Why*.txt", FileNamePlatform::Windows, "_"));
+        assert_eq!("What? Why*.txt", sanitize_file_name("What? Why*.txt", FileNamePlatform::Linux, "_"));
+        assert_eq!("a-b", sanitize_file_name("a:b", FileNamePlatform::MacOs, "-"));
+        assert_eq!("CON_.txt", sanitize_file_name("CON.txt", FileNamePlatform::Portable, "_"));
+        assert_eq!("lpt1_", sanitize_file_name("lpt1", FileNamePlatform::Windows, "_"));
+        assert_eq!("Console.txt", sanitize_file_name("Console.txt", FileNamePlatform::Windows, "_"));
+        assert_eq!("notes", sanitize_file_name("notes. .", FileNamePlatform::Windows, "_"));
+        assert_eq!("_", sanitize_file_name("..", FileNamePlatform::Linux, "_"));
+
+        let long_name = format!("{}.txt", "\u{e9}".repeat(200));
+        let sanitized = sanitize_file_name(&long_name, FileNamePlatform::Portable, "_");
+        assert!(sanitized.len() <= MAX_FILE_NAME_LEN);
+        assert!(sanitized.ends_with("\u{e9}.txt"));
+
+        assert!(is_valid_file_name("Report 2021.xlsx", FileNamePlatform::Portable));
+        assert!(!is_valid_file_name("aux", FileNamePlatform::Portable));
+    }
+}
@@ -0,0 +1,385 @@
+// Batch renaming of the files in a folder. A plan is built by running each file name through a
+// pipeline of transforms (title case, Windows-safe characters, regex replacements, numbering and
+// so on), checked for collisions, previewed, and then carried out all at once. Each batch that's
+// carried out is recorded in a journal file so that it can be undone later.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::*;
+use crate::file::{path_entries_r, path_exists, path_exists_r, path_file_name_r, path_file_next_number_r, path_is_file_r, path_name, read_file_as_lines_r, write_file_atomic_r};
+use crate::format::{format_count, format_zeros, remove_repeated, substitute_characters, title_case, title_case_file_name, windows_file_name};
+use crate::parse::split_2_r;
+
+const JOURNAL_PREFIX: &str = "Rename Journal";
+const JOURNAL_EXTENSION: &str = "txt";
+const JOURNAL_DIGITS: usize = 3;
+const TEMP_SUFFIX: &str = ".rename_tmp";
+
+#[derive(Clone, Debug)]
+pub enum RenameTransform {
+    TitleCase {
+        force_case_strings: Vec<String>,
+    },
+    WindowsFileName {
+        substitute: String,
+    },
+    SubstituteCharacters {
+        chars_to_remove: String,
+        substitute: String,
+    },
+    RemoveRepeated {
+        substring: String,
+    },
+    RegexReplace {
+        regex: Regex,
+        replacement: String,
+    },
+    // Replace the name (but not the extension) with the prefix and a number, like "Photo 007.jpg".
+    // Files are numbered in order of their original names.
+    Number {
+        prefix: String,
+        start: usize,
+        digits: usize,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct RenamePlan {
+    pub path_folder: PathBuf,
+    pub entries: Vec<RenameEntry>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RenameEntry {
+    pub from: String,
+    pub to: String,
+    pub collision: Option<String>,
+}
+
+impl RenameTransform {
+    pub fn regex_replace_r(pattern: &str, replacement: &str) -> Result<Self, String> {
+        let regex = rse!(Regex::new(pattern))?;
+        Ok(RenameTransform::RegexReplace { regex, replacement: replacement.to_string() })
+    }
+
+    pub fn apply(&self, file_name: &str, index: usize) -> String {
+        match self {
+            RenameTransform::TitleCase { force_case_strings } => {
+                let force_case_strings = force_case_strings.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+                // title_case_file_name() expects an extension.
+                if file_name.contains('.') {
+                    title_case_file_name(file_name, Some(&force_case_strings))
+                } else {
+                    title_case(file_name, Some(&force_case_strings))
+                }
+            },
+            RenameTransform::WindowsFileName { substitute } => windows_file_name(file_name, substitute),
+            RenameTransform::SubstituteCharacters { chars_to_remove, substitute } => substitute_characters(file_name, chars_to_remove, substitute),
+            RenameTransform::RemoveRepeated { substring } => remove_repeated(file_name, substring),
+            RenameTransform::RegexReplace { regex, replacement } => regex.replace_all(file_name, replacement.as_str()).to_string(),
+            RenameTransform::Number { prefix, start, digits } => {
+                let number = format_zeros(start + index, *digits);
+                match file_name.rsplit_once('.') {
+                    Some((_, extension)) => format!("{} {}.{}", prefix, number, extension),
+                    None => format!("{} {}", prefix, number),
+                }
+            },
+        }
+    }
+}
+
+impl RenamePlan {
+    pub fn new_r<P>(path_folder: P, transforms: &[RenameTransform]) -> Result<Self, String>
+        where P: AsRef<Path>
+    {
+        // Plan the renaming of every file (not subfolder) in the folder. Nothing is changed on disk.
+        path_exists_r(&path_folder)?;
+        let mut file_names = vec![];
+        for entry in path_entries_r(&path_folder)? {
+            if path_is_file_r(&entry)? {
+                file_names.push(path_file_name_r(&entry)?);
+            }
+        }
+        file_names.sort();
+        let pairs = file_names.iter()
+            .enumerate()
+            .map(|(index, file_name)| {
+                let new_name = transforms.iter().fold(file_name.clone(), |name, transform| transform.apply(&name, index));
+                (file_name.clone(), new_name)
+            })
+            .collect::<Vec<_>>();
+        Ok(Self::from_pairs(path_folder, pairs))
+    }
+
+    fn from_pairs<P>(path_folder: P, pairs: Vec<(String, String)>) -> Self
+        where P: AsRef<Path>
+    {
+        let mut plan = Self {
+            path_folder: path_folder.as_ref().to_path_buf(),
+            entries: pairs.into_iter().map(|(from, to)| RenameEntry { from, to, collision: None }).collect(),
+        };
+        plan.find_collisions();
+        plan
+    }
+
+    fn find_collisions(&mut self) {
+        // Names are compared ignoring case since the file systems on Windows and macOS do.
+        let mut target_counts = BTreeMap::new();
+        for entry in self.entries.iter() {
+            *target_counts.entry(entry.to.to_lowercase()).or_insert(0) += 1;
+        }
+        let source_names = self.entries.iter().map(|entry| entry.from.to_lowercase()).collect::<Vec<_>>();
+        let path_folder = self.path_folder.clone();
+        for entry in self.entries.iter_mut() {
+            let to_lower = entry.to.to_lowercase();
+            entry.collision = if entry.to.trim().is_empty() {
+                Some("The new name is blank.".to_string())
+            } else if entry.to.contains('/') || entry.to.contains('\\') {
+                Some("The new name contains a path separator.".to_string())
+            } else if target_counts[&to_lower] > 1 {
+                Some(format!("{} files would be renamed to \"{}\".", target_counts[&to_lower], entry.to))
+            } else if entry.is_changed() && !source_names.contains(&to_lower) && path_exists(path_folder.join(&entry.to)) {
+                // Something else in the folder, such as a subfolder, already has this name. A file
+                // from the batch that has this name is fine since it's being renamed too.
+                Some(format!("\"{}\" already exists.", entry.to))
+            } else {
+                None
+            };
+        }
+    }
+
+    pub fn changed_entries(&self) -> Vec<&RenameEntry> {
+        self.entries.iter().filter(|entry| entry.is_changed()).collect()
+    }
+
+    pub fn collisions(&self) -> Vec<&RenameEntry> {
+        self.entries.iter().filter(|entry| entry.collision.is_some()).collect()
+    }
+
+    pub fn has_collisions(&self) -> bool {
+        self.entries.iter().any(|entry| entry.collision.is_some())
+    }
+
+    pub fn preview_lines(&self) -> Vec<String> {
+        let changed = self.changed_entries();
+        let from_width = changed.iter().map(|entry| entry.from.chars().count()).max().unwrap_or(0).max("From".len());
+        let to_width = changed.iter().map(|entry| entry.to.chars().count()).max().unwrap_or(0).max("To".len());
+        let mut lines = vec![
+            format!("{:<from_width$}   {:<to_width$}   {}", "From", "To", "Problem", from_width = from_width, to_width = to_width),
+            format!("{}   {}   {}", "-".repeat(from_width), "-".repeat(to_width), "-".repeat("Problem".len())),
+        ];
+        for entry in changed {
+            let line = format!("{:<from_width$} > {:<to_width$}   {}", entry.from, entry.to, entry.collision.as_deref().unwrap_or(""),
+                               from_width = from_width, to_width = to_width);
+            lines.push(line.trim_end().to_string());
+        }
+        lines
+    }
+
+    pub fn print_preview(&self) {
+        println!("\nRename plan for \"{}\": {} of {} files change, {} problems.", path_name(&self.path_folder),
+                 format_count(self.changed_entries().len()), format_count(self.entries.len()), format_count(self.collisions().len()));
+        for line in self.preview_lines() {
+            println!("{}", line);
+        }
+    }
+
+    pub fn execute_r<J>(&self, path_journal_folder: J) -> Result<PathBuf, String>
+        where J: AsRef<Path>
+    {
+        // Write a journal file to the given folder and carry out the renames, returning the path
+        // of the journal. This is all or nothing: the journal is written first so that there's
+        // never a batch of renames that can't be undone, and if any rename fails, the ones
+        // already done are reversed and the journal is deleted.
+        if self.has_collisions() {
+            return Err(format!("RenamePlan::execute_r: The plan for \"{}\" has {} problems. Nothing was renamed.",
+                               path_name(&self.path_folder), self.collisions().len()));
+        }
+        let changed = self.changed_entries();
+        let renames = changed.iter().map(|entry| (entry.from.clone(), entry.to.clone())).collect::<Vec<_>>();
+        let path_journal = path_file_next_number_r(&path_journal_folder, JOURNAL_PREFIX, JOURNAL_EXTENSION, JOURNAL_DIGITS)?;
+        let mut journal = vec![path_name(&self.path_folder)];
+        journal.extend(renames.iter().map(|(from, to)| format!("{}\t{}", from, to)));
+        write_file_atomic_r(&path_journal, &format!("{}\n", journal.join("\n")))
+            .map_err(|msg| format!("RenamePlan::execute_r: {} Nothing was renamed.", msg))?;
+        if let Err(msg) = rename_all_r(&self.path_folder, &renames) {
+            let _ = fs::remove_file(&path_journal);
+            return Err(format!("RenamePlan::execute_r: {}", msg));
+        }
+        Ok(path_journal)
+    }
+}
+
+impl RenameEntry {
+    pub fn is_changed(&self) -> bool {
+        self.from != self.to
+    }
+}
+
+pub fn undo_rename_journal_r<P>(path_journal: P) -> Result<(), String>
+    where P: AsRef<Path>
+{
+    // Reverse a batch of renames recorded by RenamePlan::execute_r(). The first line of the journal
+    // is the folder and each remaining line is the original and new names separated by a tab.
+    let lines = read_file_as_lines_r(&path_journal)?;
+    let context = format!("undo_rename_journal_r: \"{}\":", path_name(&path_journal));
+    let path_folder = match lines.first() {
+        Some(line) if !line.trim().is_empty() => PathBuf::from(line.trim()),
+        _ => return Err(format!("{} The journal is empty.", context)),
+    };
+    let mut renames = vec![];
+    for line in lines.iter().skip(1).filter(|line| !line.is_empty()) {
+        let (from, to) = err_context(split_2_r(line, "\t"), &context)?;
+        renames.push((to.to_string(), from.to_string()));
+    }
+    for (from, to) in renames.iter() {
+        if !path_exists(path_folder.join(from)) {
+            return Err(format!("{} \"{}\" no longer exists, so the batch can't be undone.", context, from));
+        }
+        if path_exists(path_folder.join(to)) && !renames.iter().any(|(other_from, _)| other_from.eq_ignore_ascii_case(to)) {
+            return Err(format!("{} \"{}\" already exists, so the batch can't be undone.", context, to));
+        }
+    }
+    rename_all_r(&path_folder, &renames)
+}
+
+fn rename_all_r(path_folder: &Path, renames: &[(String, String)]) -> Result<(), String> {
+    // Rename in two passes, first to temporary names and then to the final names, so that swaps
+    // and chains like "B" to "C" and "A" to "B" work regardless of order, as do changes of case
+    // only on file systems that ignore case.
+    // The temporary names include the process ID and skip any name that's already taken, such
+    // as by a file left behind by a run that was killed partway through.
+    let mut temp_names: Vec<String> = vec![];
+    for index in 0..renames.len() {
+        let mut number = 0;
+        let temp_name = loop {
+            let name = if number == 0 {
+                format!(".{}.{}{}", index, std::process::id(), TEMP_SUFFIX)
+            } else {
+                format!(".{}.{}.{}{}", index, std::process::id(), number, TEMP_SUFFIX)
+            };
+            if !path_exists(path_folder.join(&name)) && !temp_names.contains(&name) {
+                break name;
+            }
+            number += 1;
+        };
+        temp_names.push(temp_name);
+    }
+    let mut moves: Vec<(PathBuf, PathBuf)> = vec![];
+    for ((from, _), temp_name) in renames.iter().zip(temp_names.iter()) {
+        moves.push((path_folder.join(from), path_folder.join(temp_name)));
+    }
+    for ((_, to), temp_name) in renames.iter().zip(temp_names.iter()) {
+        moves.push((path_folder.join(temp_name), path_folder.join(to)));
+    }
+    for (done_count, (path_from, path_to)) in moves.iter().enumerate() {
+        if let Err(err) = fs::rename(path_from, path_to) {
+            // Put back everything done so far, newest first.
+            let mut undo_errors = vec![];
+            for (path_undo_to, path_undo_from) in moves[..done_count].iter().rev() {
+                if let Err(undo_err) = fs::rename(path_undo_from, path_undo_to) {
+                    undo_errors.push(format!("\"{}\" to \"{}\": {}", path_name(path_undo_from), path_name(path_undo_to), undo_err));
+                }
+            }
+            let outcome = if undo_errors.is_empty() {
+                "All renames in the batch were reversed.".to_string()
+            } else {
+                format!("These renames couldn't be reversed: {}.", undo_errors.join("; "))
+            };
+            return Err(format!("Couldn't rename \"{}\" to \"{}\": {}. {}", path_name(path_from), path_name(path_to), err, outcome));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::path_file_names_r;
+    use crate::temp_workspace::{TempWorkspace, spec_file, spec_folder};
+
+    #[test]
+    fn test_transforms() {
+        let transforms = vec![
+            RenameTransform::regex_replace_r(r"^\d+[-_ ]+", "").unwrap(),
+            RenameTransform::SubstituteCharacters { chars_to_remove: "_".to_string(), substitute: " ".to_string() },
+            RenameTransform::RemoveRepeated { substring: " ".to_string() },
+            RenameTransform::WindowsFileName { substitute: "-".to_string() },
+            RenameTransform::TitleCase { force_case_strings: vec!["TV".to_string()] },
+        ];
+        let apply = |name: &str| transforms.iter().fold(name.to_string(), |name, transform| transform.apply(&name, 0));
+        assert_eq!("History of Rome - TV Notes.txt", apply("012 - history_of__rome_-_tv notes.txt"));
+        assert_eq!("What- Why.txt", apply("what?_why.txt"));
+        assert_eq!("No Extension", apply("no_extension"));
+
+        let number = RenameTransform::Number { prefix: "Photo".to_string(), start: 1, digits: 3 };
+        assert_eq!("Photo 001.jpg", number.apply("IMG_4411.jpg", 0));
+        assert_eq!("Photo 012", number.apply("scan", 11));
+    }
+
+    #[test]
+    fn test_execute_and_undo() {
+        let workspace = TempWorkspace::with_spec("test_execute_and_undo", &[
+            spec_folder("Files", vec![spec_file("b.txt", "b.txt"), spec_file("c.txt", "c.txt"), spec_file("a.txt", "a.txt")]),
+        ]);
+        let path_folder = workspace.join("Files");
+        let path_journal_folder = workspace.join("Journals");
+
+        // Number the files, which shifts "b.txt" into the old name of "a.txt" and so on.
+        let transforms = vec![RenameTransform::Number { prefix: "File".to_string(), start: 1, digits: 2 }];
+        let plan = RenamePlan::new_r(&path_folder, &transforms).unwrap();
+        assert!(!plan.has_collisions());
+        assert_eq!(3, plan.changed_entries().len());
+        let path_journal = plan.execute_r(&path_journal_folder).unwrap();
+        assert_eq!(str_to_string_vector(&["File 01.txt", "File 02.txt", "File 03.txt"]), path_file_names_r(&path_folder).unwrap());
+        assert_eq!("b.txt", fs::read_to_string(path_folder.join("File 02.txt")).unwrap());
+
+        undo_rename_journal_r(&path_journal).unwrap();
+        assert_eq!(str_to_string_vector(&["a.txt", "b.txt", "c.txt"]), path_file_names_r(&path_folder).unwrap());
+        assert_eq!("b.txt", fs::read_to_string(path_folder.join("b.txt")).unwrap());
+
+        // Two files would end up with the same name, so nothing is renamed.
+        let transforms = vec![RenameTransform::regex_replace_r(r"[ab]", "x").unwrap()];
+        let plan = RenamePlan::new_r(&path_folder, &transforms).unwrap();
+        assert_eq!(2, plan.collisions().len());
+        assert!(plan.execute_r(&path_journal_folder).is_err());
+        assert_eq!(str_to_string_vector(&["a.txt", "b.txt", "c.txt"]), path_file_names_r(&path_folder).unwrap());
+    }
+
+    #[test]
+    fn test_rename_all_keeps_existing_temp_files() {
+        // A file with the first choice of temporary name, as from a run that was killed, is left
+        // alone.
+        let leftover = format!(".0.{}{}", std::process::id(), TEMP_SUFFIX);
+        let workspace = TempWorkspace::with_spec("test_rename_all_keeps_existing_temp_files", &[
+            spec_file("a.txt", "a"),
+            spec_file("b.txt", "b"),
+            spec_file(&leftover, "leftover"),
+        ]);
+        let renames = vec![("a.txt".to_string(), "b.txt".to_string()), ("b.txt".to_string(), "a.txt".to_string())];
+        rename_all_r(workspace.path(), &renames).unwrap();
+        assert_eq!("b", fs::read_to_string(workspace.join("a.txt")).unwrap());
+        assert_eq!("a", fs::read_to_string(workspace.join("b.txt")).unwrap());
+        assert_eq!("leftover", fs::read_to_string(workspace.join(&leftover)).unwrap());
+    }
+
+    #[test]
+    fn test_execute_journal_write_fails() {
+        // A folder sitting where the next journal file would go makes the journal write fail, and
+        // then nothing should be renamed.
+        let workspace = TempWorkspace::with_spec("test_execute_journal_write_fails", &[
+            spec_folder("Files", vec![spec_file("a.txt", "a.txt"), spec_file("b.txt", "b.txt")]),
+            spec_folder("Journals", vec![spec_folder("Rename Journal 001.txt", vec![spec_file("x.txt", "x")])]),
+        ]);
+        let path_folder = workspace.join("Files");
+        let transforms = vec![RenameTransform::Number { prefix: "File".to_string(), start: 1, digits: 2 }];
+        let plan = RenamePlan::new_r(&path_folder, &transforms).unwrap();
+        let err = plan.execute_r(workspace.join("Journals")).unwrap_err();
+        assert!(err.contains("Nothing was renamed."));
+        assert_eq!(str_to_string_vector(&["a.txt", "b.txt"]), path_file_names_r(&path_folder).unwrap());
+    }
+}
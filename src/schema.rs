@@ -0,0 +1,460 @@
+// A declarative description of the columns in a table, such as one read with
+// tab::DelimitedReader, so that each consumer doesn't have to work out which tab::cell_as_*
+// function goes with which column. Rows are mapped to DynamicRow values or, through FromRow, to
+// typed records. All of the problems in a table are collected along with where they are, rather
+// than stopping at the first bad cell.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use chrono::NaiveDate;
+
+use crate::tab::{self, CellConfig, DelimitedRecord};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellType {
+    String,
+    Usize,
+    F32,
+    // An f32 that may have a currency symbol. Since CellConfig handles currency symbols for any
+    // number this is the same as F32, but it documents what the column holds.
+    Price,
+    Date,
+    Bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CellValue {
+    Empty,
+    String(String),
+    Usize(usize),
+    F32(f32),
+    Date(NaiveDate),
+    Bool(bool),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ColumnRef {
+    Index(usize),
+    // Matched against the header row ignoring case.
+    Name(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct ColumnSpec {
+    // The name the value goes by in a DynamicRow.
+    pub name: String,
+    pub column: ColumnRef,
+    pub cell_type: CellType,
+    // An empty cell is allowed and becomes CellValue::Empty.
+    pub optional: bool,
+    // A number that's zero is treated like an empty cell.
+    pub zero_is_none: bool,
+    // Used in place of an empty cell.
+    pub default: Option<CellValue>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TableSchema {
+    pub columns: Vec<ColumnSpec>,
+    // How numbers and dates are written in the table.
+    pub config: CellConfig,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DynamicRow {
+    pub line_number: usize,
+    pub values: BTreeMap<String, CellValue>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CellError {
+    pub line_number: usize,
+    // One-based as in a spreadsheet. None for a problem with the row as a whole.
+    pub column_number: Option<usize>,
+    pub column_name: String,
+    pub message: String,
+}
+
+pub trait FromRow: Sized {
+    fn from_row_r(row: &DynamicRow) -> Result<Self, String>;
+}
+
+impl ColumnSpec {
+    pub fn new(name: &str, cell_type: CellType) -> Self {
+        // A required column found by its name in the header. Set the other fields with struct
+        // update syntax, as in ColumnSpec { optional: true, ..ColumnSpec::new("Price", CellType::Price) }.
+        Self {
+            name: name.to_string(),
+            column: ColumnRef::Name(name.to_string()),
+            cell_type,
+            optional: false,
+            zero_is_none: false,
+            default: None,
+        }
+    }
+
+    pub fn at_index(name: &str, column_index: usize, cell_type: CellType) -> Self {
+        Self { column: ColumnRef::Index(column_index), ..Self::new(name, cell_type) }
+    }
+
+    pub fn parse_cell_r(&self, val: &str, config: &CellConfig) -> Result<CellValue, String> {
+        let value = match self.cell_type {
+            CellType::String => tab::cell_as_string_optional(val).map(CellValue::String),
+            CellType::Usize => config.cell_as_usize_optional_r(val)?
+                .filter(|number| !self.zero_is_none || *number != 0)
+                .map(CellValue::Usize),
+            CellType::F32 | CellType::Price => config.cell_as_f32_optional_r(val)?
+                .filter(|number| !self.zero_is_none || *number != 0.0)
+                .map(CellValue::F32),
+            CellType::Date => config.cell_as_date_optional_r(val)?.map(CellValue::Date),
+            CellType::Bool => {
+                // cell_as_bool_result() reads an empty cell as false, but here it's missing.
+                if val.trim().is_empty() {
+                    None
+                } else {
+                    Some(CellValue::Bool(tab::cell_as_bool_result(val)?))
+                }
+            },
+        };
+        match (value, &self.default) {
+            (Some(value), _) => Ok(value),
+            (None, Some(default)) => Ok(default.clone()),
+            (None, None) if self.optional => Ok(CellValue::Empty),
+            (None, None) => Err("A value is required.".to_string()),
+        }
+    }
+}
+
+impl TableSchema {
+    pub fn new(columns: Vec<ColumnSpec>) -> Self {
+        Self::with_config(columns, CellConfig::default())
+    }
+
+    pub fn with_config(columns: Vec<ColumnSpec>, config: CellConfig) -> Self {
+        Self { columns, config }
+    }
+
+    pub fn column_indexes_r(&self, header: Option<&[String]>) -> Result<Vec<usize>, String> {
+        // Find the position of each column, which for named columns means looking in the header.
+        let mut missing = vec![];
+        let mut indexes = vec![];
+        for column in self.columns.iter() {
+            match &column.column {
+                ColumnRef::Index(index) => indexes.push(*index),
+                ColumnRef::Name(name) => {
+                    let position = header.and_then(|header| header.iter().position(|field| field.trim().eq_ignore_ascii_case(name.trim())));
+                    match position {
+                        Some(index) => indexes.push(index),
+                        None => missing.push(format!("\"{}\"", name)),
+                    }
+                },
+            }
+        }
+        if missing.is_empty() {
+            Ok(indexes)
+        } else {
+            Err(format!("TableSchema: the header has no column named {}.", missing.join(", ")))
+        }
+    }
+
+    pub fn map_record(&self, column_indexes: &[usize], record: &DelimitedRecord, errors: &mut Vec<CellError>) -> Option<DynamicRow> {
+        // Parse every cell in the row. If any fail, the errors are added to the list and there's
+        // no row.
+        let error_count = errors.len();
+        let mut values = BTreeMap::new();
+        for (column, column_index) in self.columns.iter().zip(column_indexes.iter()) {
+            match column.parse_cell_r(record.cell(*column_index), &self.config) {
+                Ok(value) => { values.insert(column.name.clone(), value); },
+                Err(message) => errors.push(CellError {
+                    line_number: record.line_number,
+                    column_number: Some(column_index + 1),
+                    column_name: column.name.clone(),
+                    message,
+                }),
+            }
+        }
+        if errors.len() > error_count {
+            None
+        } else {
+            Some(DynamicRow { line_number: record.line_number, values })
+        }
+    }
+
+    pub fn map_records<'a, I>(&self, header: Option<&[String]>, records: I) -> Result<(Vec<DynamicRow>, Vec<CellError>), String>
+        where I: IntoIterator<Item = &'a DelimitedRecord>
+    {
+        // The outer error is for a problem with the table as a whole, such as a missing column.
+        // Otherwise the good rows come back along with the errors from the bad ones.
+        let column_indexes = self.column_indexes_r(header)?;
+        let mut rows = vec![];
+        let mut errors = vec![];
+        for record in records {
+            if let Some(row) = self.map_record(&column_indexes, record, &mut errors) {
+                rows.push(row);
+            }
+        }
+        Ok((rows, errors))
+    }
+
+    pub fn map_typed_records<'a, T, I>(&self, header: Option<&[String]>, records: I) -> Result<(Vec<T>, Vec<CellError>), String>
+        where
+            T: FromRow,
+            I: IntoIterator<Item = &'a DelimitedRecord>,
+    {
+        let (rows, mut errors) = self.map_records(header, records)?;
+        let mut items = vec![];
+        for row in rows.iter() {
+            match T::from_row_r(row) {
+                Ok(item) => items.push(item),
+                Err(message) => errors.push(CellError {
+                    line_number: row.line_number,
+                    column_number: None,
+                    column_name: "".to_string(),
+                    message,
+                }),
+            }
+        }
+        errors.sort_by_key(|error| (error.line_number, error.column_number));
+        Ok((items, errors))
+    }
+
+    pub fn map_typed_records_r<'a, T, I>(&self, header: Option<&[String]>, records: I) -> Result<Vec<T>, String>
+        where
+            T: FromRow,
+            I: IntoIterator<Item = &'a DelimitedRecord>,
+    {
+        // All or nothing, with every error listed in the message.
+        let (items, errors) = self.map_typed_records(header, records)?;
+        if errors.is_empty() {
+            Ok(items)
+        } else {
+            Err(errors_to_string(&errors))
+        }
+    }
+}
+
+impl CellValue {
+    pub fn is_empty(&self) -> bool {
+        *self == CellValue::Empty
+    }
+
+    pub fn as_string(&self) -> Option<String> {
+        match self {
+            CellValue::String(val) => Some(val.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            CellValue::Usize(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            CellValue::F32(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    pub fn as_date(&self) -> Option<NaiveDate> {
+        match self {
+            CellValue::Date(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            CellValue::Bool(val) => Some(*val),
+            _ => None,
+        }
+    }
+}
+
+impl Display for CellValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CellValue::Empty => write!(f, ""),
+            CellValue::String(val) => write!(f, "{}", val),
+            CellValue::Usize(val) => write!(f, "{}", val),
+            CellValue::F32(val) => write!(f, "{}", val),
+            CellValue::Date(val) => write!(f, "{}", val),
+            CellValue::Bool(val) => write!(f, "{}", val),
+        }
+    }
+}
+
+impl DynamicRow {
+    pub fn get(&self, name: &str) -> Option<&CellValue> {
+        self.values.get(name)
+    }
+
+    fn get_r(&self, name: &str) -> Result<&CellValue, String> {
+        self.values.get(name).ok_or_else(|| format!("The row has no column named \"{}\".", name))
+    }
+
+    // For use in FromRow implementations. The plain versions fail if the value is empty while the
+    // _opt versions return None.
+
+    pub fn string_r(&self, name: &str) -> Result<String, String> {
+        self.string_opt_r(name)?.ok_or_else(|| format!("\"{}\" is empty.", name))
+    }
+
+    pub fn string_opt_r(&self, name: &str) -> Result<Option<String>, String> {
+        typed_value_r(name, self.get_r(name)?, CellValue::as_string, "text")
+    }
+
+    pub fn usize_r(&self, name: &str) -> Result<usize, String> {
+        self.usize_opt_r(name)?.ok_or_else(|| format!("\"{}\" is empty.", name))
+    }
+
+    pub fn usize_opt_r(&self, name: &str) -> Result<Option<usize>, String> {
+        typed_value_r(name, self.get_r(name)?, CellValue::as_usize, "a whole number")
+    }
+
+    pub fn f32_r(&self, name: &str) -> Result<f32, String> {
+        self.f32_opt_r(name)?.ok_or_else(|| format!("\"{}\" is empty.", name))
+    }
+
+    pub fn f32_opt_r(&self, name: &str) -> Result<Option<f32>, String> {
+        typed_value_r(name, self.get_r(name)?, CellValue::as_f32, "a number")
+    }
+
+    pub fn date_r(&self, name: &str) -> Result<NaiveDate, String> {
+        self.date_opt_r(name)?.ok_or_else(|| format!("\"{}\" is empty.", name))
+    }
+
+    pub fn date_opt_r(&self, name: &str) -> Result<Option<NaiveDate>, String> {
+        typed_value_r(name, self.get_r(name)?, CellValue::as_date, "a date")
+    }
+
+    pub fn bool_r(&self, name: &str) -> Result<bool, String> {
+        self.bool_opt_r(name)?.ok_or_else(|| format!("\"{}\" is empty.", name))
+    }
+
+    pub fn bool_opt_r(&self, name: &str) -> Result<Option<bool>, String> {
+        typed_value_r(name, self.get_r(name)?, CellValue::as_bool, "true or false")
+    }
+}
+
+fn typed_value_r<T, F>(name: &str, value: &CellValue, as_type: F, type_description: &str) -> Result<Option<T>, String>
+    where F: Fn(&CellValue) -> Option<T>
+{
+    if value.is_empty() {
+        return Ok(None);
+    }
+    match as_type(value) {
+        Some(typed_value) => Ok(Some(typed_value)),
+        None => Err(format!("\"{}\" is {:?}, not {}.", name, value, type_description)),
+    }
+}
+
+impl Display for CellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.column_number {
+            Some(column_number) => write!(f, "Line {}, column {} ({}): {}", self.line_number, column_number, self.column_name, self.message),
+            None => write!(f, "Line {}: {}", self.line_number, self.message),
+        }
+    }
+}
+
+pub fn errors_to_string(errors: &[CellError]) -> String {
+    errors.iter().map(|error| error.to_string()).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tab::{DelimitedFormat, DelimitedReader};
+
+    #[derive(Debug, PartialEq)]
+    struct Purchase {
+        item: String,
+        date: NaiveDate,
+        price: Option<f32>,
+        quantity: usize,
+    }
+
+    impl FromRow for Purchase {
+        fn from_row_r(row: &DynamicRow) -> Result<Self, String> {
+            Ok(Self {
+                item: row.string_r("Item")?,
+                date: row.date_r("Date")?,
+                price: row.f32_opt_r("Price")?,
+                quantity: row.usize_r("Quantity")?,
+            })
+        }
+    }
+
+    fn schema() -> TableSchema {
+        TableSchema::new(vec![
+            ColumnSpec::new("Item", CellType::String),
+            ColumnSpec::new("Date", CellType::Date),
+            ColumnSpec { optional: true, zero_is_none: true, ..ColumnSpec::new("Price", CellType::Price) },
+            ColumnSpec { default: Some(CellValue::Usize(1)), ..ColumnSpec::at_index("Quantity", 3, CellType::Usize) },
+        ])
+    }
+
+    #[test]
+    fn test_map_typed_records() {
+        let text = "Item,Date,Price,Qty\n\
+            Paper,03/15/21,$4.50,3\n\
+            Pens,03/16/21,$0.00,\n\
+            ,03/17/21,$1.00,2\n\
+            Ink,someday,lots,2";
+        let reader = DelimitedReader::from_text(text, DelimitedFormat::csv()).unwrap();
+        let header = reader.header().map(|header| header.to_vec());
+        let records = reader.collect::<Result<Vec<_>, _>>().unwrap();
+
+        let (purchases, errors) = schema().map_typed_records::<Purchase, _>(header.as_deref(), &records).unwrap();
+        let expected = vec![
+            Purchase { item: "Paper".to_string(), date: NaiveDate::from_ymd_opt(2021, 3, 15).unwrap(), price: Some(4.5), quantity: 3 },
+            Purchase { item: "Pens".to_string(), date: NaiveDate::from_ymd_opt(2021, 3, 16).unwrap(), price: None, quantity: 1 },
+        ];
+        assert_eq!(expected, purchases);
+
+        // Every bad cell is reported, including both in the last row.
+        let locations = errors.iter().map(|error| (error.line_number, error.column_number, error.column_name.as_str())).collect::<Vec<_>>();
+        assert_eq!(vec![(4, Some(1), "Item"), (5, Some(2), "Date"), (5, Some(3), "Price")], locations);
+        assert!(errors[0].to_string().starts_with("Line 4, column 1 (Item): "));
+
+        assert!(schema().map_typed_records_r::<Purchase, _>(header.as_deref(), &records).is_err());
+        assert!(schema().map_records(Some(&["Item".to_string()]), &records).is_err());
+    }
+
+    #[test]
+    fn test_schema_with_config() {
+        let config = CellConfig { date_formats: vec!["%d.%m.%Y".to_string()], ..CellConfig::for_locale(&num_format::Locale::de) };
+        let schema = TableSchema::with_config(vec![
+            ColumnSpec::at_index("Date", 0, CellType::Date),
+            ColumnSpec::at_index("Amount", 1, CellType::Price),
+        ], config);
+        let record = DelimitedRecord { line_number: 2, fields: vec!["15.03.2021".to_string(), "(1.234,50 \u{20AC})".to_string()] };
+        let (rows, errors) = schema.map_records(None, &[record]).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(NaiveDate::from_ymd_opt(2021, 3, 15).unwrap(), rows[0].date_r("Date").unwrap());
+        assert_eq!(-1_234.5, rows[0].f32_r("Amount").unwrap());
+    }
+
+    #[test]
+    fn test_dynamic_row() {
+        let record = DelimitedRecord { line_number: 7, fields: vec!["Paper".to_string(), "03/15/21".to_string()] };
+        let schema = TableSchema::new(vec![
+            ColumnSpec::at_index("Item", 0, CellType::String),
+            ColumnSpec::at_index("Date", 1, CellType::Date),
+            ColumnSpec { optional: true, ..ColumnSpec::at_index("Done", 2, CellType::Bool) },
+        ]);
+        let mut errors = vec![];
+        let row = schema.map_record(&schema.column_indexes_r(None).unwrap(), &record, &mut errors).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(Some(&CellValue::String("Paper".to_string())), row.get("Item"));
+        assert_eq!(None, row.bool_opt_r("Done").unwrap());
+        assert!(row.bool_r("Done").is_err());
+        assert!(row.usize_r("Item").is_err());
+        assert!(row.string_r("Missing").is_err());
+    }
+}
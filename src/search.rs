@@ -0,0 +1,211 @@
+// A grep-like search of the contents of files. Unlike parse::find_in_files_ci(), which returns the
+// text between two fixed patterns, this finds every match of a regular expression or a literal
+// string and reports where it is along with a few lines of context.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::*;
+use crate::file::{path_exists_r, path_name};
+use crate::format::{format_count, format_indent_tab};
+use crate::group::Grouper;
+use crate::line_reader::LineReader;
+use crate::parse::get_files_ci;
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchOptions {
+    pub case_insensitive: bool,
+    // Treat the pattern as plain text rather than a regular expression.
+    pub literal: bool,
+    // The number of lines to show before and after each matching line.
+    pub context_lines: usize,
+}
+
+pub struct ContentSearch {
+    regex: Regex,
+    options: SearchOptions,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub path: PathBuf,
+    // Both one-based as in an editor, with the column counted in characters.
+    pub line_number: usize,
+    pub column: usize,
+    pub line: String,
+    pub matched: String,
+    pub context_before: Vec<String>,
+    pub context_after: Vec<String>,
+}
+
+impl ContentSearch {
+    pub fn new_r(pattern: &str, options: SearchOptions) -> Result<Self, String> {
+        let pattern = if options.literal { regex::escape(pattern) } else { pattern.to_string() };
+        let regex = rse!(RegexBuilder::new(&pattern).case_insensitive(options.case_insensitive).build())?;
+        Ok(Self { regex, options })
+    }
+
+    pub fn search_lines<I>(&self, path: &Path, lines: I) -> Vec<SearchMatch>
+        where I: IntoIterator<Item = String>
+    {
+        // Matches whose lines of context after them haven't all been seen yet wait in pending.
+        let context_lines = self.options.context_lines;
+        let mut before: VecDeque<String> = VecDeque::new();
+        let mut pending: Vec<SearchMatch> = vec![];
+        let mut matches = vec![];
+        for (index, line) in lines.into_iter().enumerate() {
+            for search_match in pending.iter_mut() {
+                search_match.context_after.push(line.clone());
+            }
+            let (done, waiting): (Vec<_>, Vec<_>) = pending.into_iter().partition(|search_match| search_match.context_after.len() >= context_lines);
+            matches.extend(done);
+            pending = waiting;
+            for found in self.regex.find_iter(&line) {
+                let search_match = SearchMatch {
+                    path: path.to_path_buf(),
+                    line_number: index + 1,
+                    column: line[..found.start()].chars().count() + 1,
+                    line: line.clone(),
+                    matched: found.as_str().to_string(),
+                    context_before: before.iter().cloned().collect(),
+                    context_after: vec![],
+                };
+                if context_lines == 0 {
+                    matches.push(search_match);
+                } else {
+                    pending.push(search_match);
+                }
+            }
+            before.push_back(line);
+            if before.len() > context_lines {
+                before.pop_front();
+            }
+        }
+        matches.extend(pending);
+        matches
+    }
+
+    pub fn search_text(&self, path: &Path, text: &str) -> Vec<SearchMatch> {
+        // The path is only used to label the matches.
+        let lines = LineReader::from_text(text).filter_map(|line| line.ok()).map(|line| line.text);
+        self.search_lines(path, lines)
+    }
+
+    pub fn search_file_r<P>(&self, path: P) -> Result<Vec<SearchMatch>, String>
+        where P: AsRef<Path>
+    {
+        // Reading through LineReader means files in UTF-16 and other encodings are searched as text.
+        let mut lines = vec![];
+        for line in LineReader::open_r(&path)? {
+            lines.push(line?.text);
+        }
+        Ok(self.search_lines(path.as_ref(), lines))
+    }
+
+    pub fn search_files_r<P>(&self, path_folder: P, wildcard: &str) -> Result<Vec<SearchMatch>, String>
+        where P: AsRef<Path>
+    {
+        // The wildcard is matched ignoring case. Use something like "**/*.txt" to include
+        // subfolders.
+        path_exists_r(&path_folder)?;
+        let mut matches = vec![];
+        for path in rse!(get_files_ci(path_folder.as_ref(), wildcard))? {
+            if path.is_file() {
+                matches.append(&mut self.search_file_r(&path)?);
+            }
+        }
+        Ok(matches)
+    }
+}
+
+pub fn group_matches_by_file(matches: &[SearchMatch]) -> Grouper<String> {
+    let mut grouper = Grouper::new("Matches by File");
+    for search_match in matches.iter() {
+        grouper.record_entry(&path_name(&search_match.path));
+    }
+    grouper
+}
+
+pub fn print_matches(matches: &[SearchMatch]) {
+    for line in match_lines(matches) {
+        println!("{}", line);
+    }
+}
+
+pub fn match_lines(matches: &[SearchMatch]) -> Vec<String> {
+    // Format like grep, with ":" after the line number of a matching line and "-" after the line
+    // numbers of the context lines.
+    let mut lines = vec![];
+    let mut path_prev = None;
+    for search_match in matches.iter() {
+        if path_prev != Some(&search_match.path) {
+            lines.push("".to_string());
+            lines.push(path_name(&search_match.path));
+            path_prev = Some(&search_match.path);
+        }
+        let first_line_number = search_match.line_number - search_match.context_before.len();
+        for (index, line) in search_match.context_before.iter().enumerate() {
+            lines.push(format_indent_tab(1, &format!("{}-{}", first_line_number + index, line)));
+        }
+        lines.push(format_indent_tab(1, &format!("{}:{}:{}", search_match.line_number, search_match.column, search_match.line)));
+        for (index, line) in search_match.context_after.iter().enumerate() {
+            lines.push(format_indent_tab(1, &format!("{}-{}", search_match.line_number + 1 + index, line)));
+        }
+    }
+    lines.push("".to_string());
+    lines.push(if matches.len() == 1 { "1 match.".to_string() } else { format!("{} matches.", format_count(matches.len())) });
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_workspace::{TempWorkspace, spec_file, spec_folder};
+
+    #[test]
+    fn test_search_text() {
+        let text = "one\nTwo two\nthree\nfour\nfive two";
+        let path = Path::new("test.txt");
+
+        let options = SearchOptions { case_insensitive: true, literal: false, context_lines: 1 };
+        let matches = ContentSearch::new_r(r"t\w+o", options).unwrap().search_text(path, text);
+        assert_eq!(vec![(2, 1), (2, 5), (5, 6)], matches.iter().map(|x| (x.line_number, x.column)).collect::<Vec<_>>());
+        assert_eq!(vec!["Two", "two", "two"], matches.iter().map(|x| x.matched.as_str()).collect::<Vec<_>>());
+        assert_eq!(str_to_string_vector(&["one"]), matches[0].context_before);
+        assert_eq!(str_to_string_vector(&["three"]), matches[0].context_after);
+        // The last line has no lines after it.
+        assert_eq!(str_to_string_vector(&["four"]), matches[2].context_before);
+        assert!(matches[2].context_after.is_empty());
+
+        // A literal search doesn't treat "." as a wildcard, and case matters by default.
+        let options = SearchOptions { literal: true, ..Default::default() };
+        let matches = ContentSearch::new_r("a.b", options).unwrap().search_text(path, "axb\nA.B\na.b");
+        assert_eq!(1, matches.len());
+        assert_eq!(3, matches[0].line_number);
+
+        assert!(ContentSearch::new_r("(unclosed", SearchOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_search_files_r() {
+        let workspace = TempWorkspace::with_spec("test_search_files_r", &[
+            spec_file("a.txt", "needle\nhay\nneedle"),
+            spec_folder("Sub", vec![spec_file("b.TXT", "hay\nNeedle")]),
+            spec_file("c.md", "needle"),
+        ]);
+
+        let options = SearchOptions { case_insensitive: true, ..Default::default() };
+        let search = ContentSearch::new_r("needle", options).unwrap();
+        let matches = search.search_files_r(workspace.path(), "**/*.txt").unwrap();
+        assert_eq!(3, matches.len());
+        let grouper = group_matches_by_file(&matches);
+        assert_eq!(2, grouper.entry_count());
+        assert_eq!(Some(2), grouper.max_count());
+
+        let lines = match_lines(&ContentSearch::new_r("hay", SearchOptions { context_lines: 1, ..Default::default() }).unwrap().search_text(Path::new("a.txt"), "needle\nhay\nneedle"));
+        assert_eq!(str_to_string_vector(&["", "a.txt", "\t1-needle", "\t2:1:hay", "\t3-needle", "", "1 match."]), lines);
+        assert_eq!("3 matches.", match_lines(&matches).last().unwrap());
+    }
+}
@@ -0,0 +1,646 @@
+// Reading and writing spreadsheets in .xlsx and .ods format. Reading goes through calamine and
+// gives rows of cells whose text can be passed to the tab::cell_as_* functions, or records for
+// schema::TableSchema. Writing is limited to what reports need: several sheets, each with an
+// optional bold header row, string/number/date/bool cells and column widths. The files are
+// written directly as zipped XML since that's all a simple workbook takes.
+
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use calamine::{Data, Reader};
+use chrono::NaiveDate;
+use zip::{CompressionMethod, ZipWriter};
+use zip::write::SimpleFileOptions;
+
+use crate::*;
+use crate::file::{path_exists_r, path_file_name_r, path_name};
+use crate::group::Grouper;
+use crate::tab::DelimitedRecord;
+
+// Excel's limit on the length of a sheet name.
+const SHEET_NAME_MAX_LEN: usize = 31;
+// Widths are in characters of the default font. These keep auto-fit columns reasonable.
+const COLUMN_WIDTH_MIN: f64 = 8.0;
+const COLUMN_WIDTH_MAX: f64 = 60.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpreadsheetFormat {
+    Xlsx,
+    Ods,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SheetCell {
+    Empty,
+    String(String),
+    Number(f64),
+    Date(NaiveDate),
+    Bool(bool),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sheet {
+    pub name: String,
+    // Written in bold above the rows. When reading, this is only filled in by split_header() since
+    // there's no telling whether the first row is a header.
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<SheetCell>>,
+    // In characters, for each column from the left. Columns past the end get the default width.
+    pub column_widths: Vec<f64>,
+}
+
+impl SpreadsheetFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SpreadsheetFormat::Xlsx => "xlsx",
+            SpreadsheetFormat::Ods => "ods",
+        }
+    }
+
+    pub fn from_path_r<P>(path: P) -> Result<Self, String>
+        where P: AsRef<Path>
+    {
+        let file_name = path_file_name_r(&path)?.to_lowercase();
+        if file_name.ends_with(".xlsx") {
+            Ok(SpreadsheetFormat::Xlsx)
+        } else if file_name.ends_with(".ods") {
+            Ok(SpreadsheetFormat::Ods)
+        } else {
+            Err(format!("\"{}\" isn't a .xlsx or .ods file.", path_name(&path)))
+        }
+    }
+}
+
+impl SheetCell {
+    pub fn string(value: &str) -> Self {
+        // An empty string is an empty cell, as it would be after a round trip through a file.
+        if value.is_empty() {
+            SheetCell::Empty
+        } else {
+            SheetCell::String(value.to_string())
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == SheetCell::Empty
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            SheetCell::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_date(&self) -> Option<NaiveDate> {
+        match self {
+            SheetCell::Date(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        // Text in the form the tab::cell_as_* functions expect, so a date is "%m/%d/%y" like
+        // "03/15/21" and a whole number has no decimal point.
+        match self {
+            SheetCell::Empty => "".to_string(),
+            SheetCell::String(value) => value.clone(),
+            SheetCell::Number(value) => format_number(*value),
+            SheetCell::Date(value) => value.format("%m/%d/%y").to_string(),
+            SheetCell::Bool(value) => value.to_string(),
+        }
+    }
+
+    fn from_data(data: &Data) -> Self {
+        match data {
+            Data::Empty => SheetCell::Empty,
+            Data::String(value) => SheetCell::string(value),
+            Data::Int(value) => SheetCell::Number(*value as f64),
+            Data::Float(value) => SheetCell::Number(*value),
+            Data::Bool(value) => SheetCell::Bool(*value),
+            Data::DateTime(value) => match value.as_datetime() {
+                // A time of day is dropped. A duration is kept as a number of days.
+                Some(date_time) if value.is_datetime() => SheetCell::Date(date_time.date()),
+                _ => SheetCell::Number(value.as_f64()),
+            },
+            Data::DateTimeIso(value) => match NaiveDate::parse_from_str(value.get(..10).unwrap_or(value), "%Y-%m-%d") {
+                Ok(date) => SheetCell::Date(date),
+                Err(_) => SheetCell::String(value.clone()),
+            },
+            Data::DurationIso(value) => SheetCell::String(value.clone()),
+            Data::Error(error) => SheetCell::String(error.to_string()),
+        }
+    }
+}
+
+impl Display for SheetCell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_text())
+    }
+}
+
+impl From<&str> for SheetCell {
+    fn from(value: &str) -> Self {
+        SheetCell::string(value)
+    }
+}
+
+impl From<String> for SheetCell {
+    fn from(value: String) -> Self {
+        SheetCell::string(&value)
+    }
+}
+
+impl From<f64> for SheetCell {
+    fn from(value: f64) -> Self {
+        SheetCell::Number(value)
+    }
+}
+
+impl From<usize> for SheetCell {
+    fn from(value: usize) -> Self {
+        SheetCell::Number(value as f64)
+    }
+}
+
+impl From<NaiveDate> for SheetCell {
+    fn from(value: NaiveDate) -> Self {
+        SheetCell::Date(value)
+    }
+}
+
+impl From<bool> for SheetCell {
+    fn from(value: bool) -> Self {
+        SheetCell::Bool(value)
+    }
+}
+
+impl Sheet {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            header: vec![],
+            rows: vec![],
+            column_widths: vec![],
+        }
+    }
+
+    pub fn with_header(name: &str, header: &[&str]) -> Self {
+        Self { header: str_to_string_vector(header), ..Self::new(name) }
+    }
+
+    pub fn from_grouper<T>(grouper: &Grouper<T>, sort_by_count: bool) -> Self
+        where T: Ord + Display + Clone
+    {
+        // One row for each key with its count, under the grouper's name as the sheet name. The
+        // name is made into a valid sheet name here rather than failing when the file is written.
+        let mut sheet = Self::with_header(&valid_sheet_name(&grouper.name), &[&grouper.name, "Count"]);
+        for (key, count) in grouper.counts(sort_by_count) {
+            sheet.add_row(vec![SheetCell::string(&key.to_string()), SheetCell::from(count)]);
+        }
+        sheet.auto_fit_columns();
+        sheet
+    }
+
+    pub fn add_row(&mut self, row: Vec<SheetCell>) {
+        self.rows.push(row);
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.rows.iter().map(|row| row.len()).chain(std::iter::once(self.header.len())).max().unwrap_or(0)
+    }
+
+    pub fn cell(&self, row_index: usize, column_index: usize) -> &SheetCell {
+        // Like DelimitedRecord::cell(), a cell past the end of a short row is empty.
+        self.rows.get(row_index).and_then(|row| row.get(column_index)).unwrap_or(&SheetCell::Empty)
+    }
+
+    pub fn split_header(&mut self) {
+        // Treat the first row as the header, as when reading a sheet that has one.
+        if !self.rows.is_empty() {
+            self.header = self.rows.remove(0).iter().map(|cell| cell.to_text()).collect();
+        }
+    }
+
+    pub fn text_rows(&self) -> Vec<Vec<String>> {
+        self.rows.iter().map(|row| row.iter().map(|cell| cell.to_text()).collect()).collect()
+    }
+
+    pub fn records(&self) -> Vec<DelimitedRecord> {
+        // The line numbers are the row numbers in the spreadsheet so that errors from
+        // schema::TableSchema point to the right place.
+        let first_line_number = if self.header.is_empty() { 1 } else { 2 };
+        self.text_rows().into_iter().enumerate()
+            .map(|(index, fields)| DelimitedRecord { line_number: first_line_number + index, fields })
+            .collect()
+    }
+
+    pub fn auto_fit_columns(&mut self) {
+        // Set each column's width from the longest text in it.
+        let mut widths = vec![0; self.column_count()];
+        for (index, label) in self.header.iter().enumerate() {
+            widths[index] = widths[index].max(label.chars().count());
+        }
+        for row in self.rows.iter() {
+            for (index, cell) in row.iter().enumerate() {
+                widths[index] = widths[index].max(cell.to_text().chars().count());
+            }
+        }
+        self.column_widths = widths.iter().map(|width| (*width as f64 + 2.0).clamp(COLUMN_WIDTH_MIN, COLUMN_WIDTH_MAX)).collect();
+    }
+
+    fn header_and_rows(&self) -> Vec<Vec<SheetCell>> {
+        // Rows as written, with the header as a row of strings. The caller knows which row is the
+        // header by whether the header is empty.
+        let mut rows = vec![];
+        if !self.header.is_empty() {
+            rows.push(self.header.iter().map(|label| SheetCell::string(label)).collect());
+        }
+        rows.extend(self.rows.iter().cloned());
+        rows
+    }
+}
+
+pub fn sheet_names_r<P>(path: P) -> Result<Vec<String>, String>
+    where P: AsRef<Path>
+{
+    path_exists_r(&path)?;
+    let workbook = err_context(rse!(calamine::open_workbook_auto(&path)), &format!("sheet_names_r: \"{}\":", path_name(&path)))?;
+    Ok(workbook.sheet_names())
+}
+
+pub fn read_spreadsheet_r<P>(path: P) -> Result<Vec<Sheet>, String>
+    where P: AsRef<Path>
+{
+    // Read every sheet in the workbook in order. Works with .xls and .xlsb files as well since
+    // calamine reads those too.
+    let context = format!("read_spreadsheet_r: \"{}\":", path_name(&path));
+    path_exists_r(&path)?;
+    let mut workbook = err_context(rse!(calamine::open_workbook_auto(&path)), &context)?;
+    let mut sheets = vec![];
+    for name in workbook.sheet_names() {
+        let range = err_context(rse!(workbook.worksheet_range(&name)), &context)?;
+        sheets.push(range_to_sheet(&name, &range));
+    }
+    Ok(sheets)
+}
+
+pub fn read_sheet_r<P>(path: P, sheet_name: &str) -> Result<Sheet, String>
+    where P: AsRef<Path>
+{
+    // The sheet name is matched ignoring case.
+    let context = format!("read_sheet_r: \"{}\":", path_name(&path));
+    path_exists_r(&path)?;
+    let mut workbook = err_context(rse!(calamine::open_workbook_auto(&path)), &context)?;
+    let name = workbook.sheet_names().into_iter()
+        .find(|name| name.eq_ignore_ascii_case(sheet_name))
+        .ok_or_else(|| format!("{} no sheet named \"{}\".", context, sheet_name))?;
+    let range = err_context(rse!(workbook.worksheet_range(&name)), &context)?;
+    Ok(range_to_sheet(&name, &range))
+}
+
+pub fn write_spreadsheet_r<P>(path: P, sheets: &[Sheet]) -> Result<(), String>
+    where P: AsRef<Path>
+{
+    // The format comes from the extension. An existing file is replaced. If anything goes wrong
+    // the partly-written file is removed.
+    let context = format!("write_spreadsheet_r: \"{}\":", path_name(&path));
+    let format = SpreadsheetFormat::from_path_r(&path)?;
+    err_context(validate_sheet_names_r(sheets), &context)?;
+    let result = match format {
+        SpreadsheetFormat::Xlsx => write_xlsx_r(path.as_ref(), sheets),
+        SpreadsheetFormat::Ods => write_ods_r(path.as_ref(), sheets),
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(&path);
+    }
+    err_context(result, &context)
+}
+
+fn range_to_sheet(name: &str, range: &calamine::Range<Data>) -> Sheet {
+    // calamine's range starts at the first cell that has anything in it, so put back the empty
+    // rows and columns before that to keep cells where they are in the spreadsheet.
+    let (first_row, first_column) = range.start().unwrap_or((0, 0));
+    let mut sheet = Sheet::new(name);
+    sheet.rows.resize(first_row as usize, vec![]);
+    for data_row in range.rows() {
+        let mut row = vec![SheetCell::Empty; first_column as usize];
+        row.extend(data_row.iter().map(SheetCell::from_data));
+        // Trailing empty cells are only there because some other row is longer.
+        while row.last().is_some_and(|cell| cell.is_empty()) {
+            row.pop();
+        }
+        sheet.rows.push(row);
+    }
+    sheet
+}
+
+fn valid_sheet_name(name: &str) -> String {
+    // Follow the rules checked by validate_sheet_names_r(), replacing characters that aren't
+    // allowed and cutting the name short if it's too long.
+    let name = name.trim().replace(['[', ']', ':', '*', '?', '/', '\\'], "_");
+    let name = name.trim_matches('\'').chars().take(SHEET_NAME_MAX_LEN).collect::<String>();
+    let name = name.trim_end_matches('\'').trim_end().to_string();
+    if name.is_empty() { "Sheet".to_string() } else { name }
+}
+
+fn validate_sheet_names_r(sheets: &[Sheet]) -> Result<(), String> {
+    // Use Excel's rules, which are stricter than those for .ods, so that a workbook can be saved in
+    // either format.
+    if sheets.is_empty() {
+        return Err("A workbook needs at least one sheet.".to_string());
+    }
+    let mut names: Vec<String> = vec![];
+    for sheet in sheets.iter() {
+        let name = &sheet.name;
+        if name.trim().is_empty() || name.chars().count() > SHEET_NAME_MAX_LEN {
+            return Err(format!("The sheet name \"{}\" must be from 1 to {} characters.", name, SHEET_NAME_MAX_LEN));
+        }
+        if name.contains(['[', ']', ':', '*', '?', '/', '\\']) || name.starts_with('\'') || name.ends_with('\'') {
+            return Err(format!("The sheet name \"{}\" has a character that isn't allowed.", name));
+        }
+        if names.iter().any(|other| other.eq_ignore_ascii_case(name)) {
+            return Err(format!("There's more than one sheet named \"{}\".", name));
+        }
+        names.push(name.clone());
+    }
+    Ok(())
+}
+
+fn write_xlsx_r(path: &Path, sheets: &[Sheet]) -> Result<(), String> {
+    let mut zip = ZipWriter::new(BufWriter::new(rse!(File::create(path))?));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut add_file_r = |name: &str, contents: &str| -> Result<(), String> {
+        rse!(zip.start_file(name, options))?;
+        rse!(zip.write_all(contents.as_bytes()))
+    };
+
+    let mut content_types = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/>"#.to_string();
+    let mut workbook = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets>"#.to_string();
+    let mut workbook_rels = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#.to_string();
+    for (index, sheet) in sheets.iter().enumerate() {
+        let number = index + 1;
+        content_types.push_str(&format!(r#"<Override PartName="/xl/worksheets/sheet{}.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#, number));
+        workbook.push_str(&format!(r#"<sheet name="{}" sheetId="{}" r:id="rId{}"/>"#, escape_xml(&sheet.name), number, number));
+        workbook_rels.push_str(&format!(r#"<Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet{}.xml"/>"#, number, number));
+    }
+    content_types.push_str("</Types>");
+    workbook.push_str("</sheets></workbook>");
+    workbook_rels.push_str(&format!(r#"<Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/></Relationships>"#, sheets.len() + 1));
+
+    add_file_r("[Content_Types].xml", &content_types)?;
+    add_file_r("_rels/.rels", r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#)?;
+    add_file_r("xl/workbook.xml", &workbook)?;
+    add_file_r("xl/_rels/workbook.xml.rels", &workbook_rels)?;
+    // Cell style 1 is the bold header and 2 is a date.
+    add_file_r("xl/styles.xml", r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><numFmts count="1"><numFmt numFmtId="164" formatCode="yyyy\-mm\-dd"/></numFmts><fonts count="2"><font><sz val="11"/><name val="Calibri"/></font><font><b/><sz val="11"/><name val="Calibri"/></font></fonts><fills count="2"><fill><patternFill patternType="none"/></fill><fill><patternFill patternType="gray125"/></fill></fills><borders count="1"><border><left/><right/><top/><bottom/><diagonal/></border></borders><cellStyleXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0"/></cellStyleXfs><cellXfs count="3"><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/><xf numFmtId="0" fontId="1" fillId="0" borderId="0" xfId="0" applyFont="1"/><xf numFmtId="164" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/></cellXfs><cellStyles count="1"><cellStyle name="Normal" xfId="0" builtinId="0"/></cellStyles></styleSheet>"#)?;
+    for (index, sheet) in sheets.iter().enumerate() {
+        add_file_r(&format!("xl/worksheets/sheet{}.xml", index + 1), &xlsx_sheet_xml(sheet))?;
+    }
+    let mut writer = rse!(zip.finish())?;
+    rse!(writer.flush())
+}
+
+fn xlsx_sheet_xml(sheet: &Sheet) -> String {
+    let mut xml = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#.to_string();
+    if !sheet.column_widths.is_empty() {
+        xml.push_str("<cols>");
+        for (index, width) in sheet.column_widths.iter().enumerate() {
+            xml.push_str(&format!(r#"<col min="{}" max="{}" width="{}" customWidth="1"/>"#, index + 1, index + 1, width));
+        }
+        xml.push_str("</cols>");
+    }
+    xml.push_str("<sheetData>");
+    let has_header = !sheet.header.is_empty();
+    for (row_index, row) in sheet.header_and_rows().iter().enumerate() {
+        xml.push_str(&format!(r#"<row r="{}">"#, row_index + 1));
+        for (column_index, cell) in row.iter().enumerate() {
+            let reference = format!("{}{}", column_letters(column_index), row_index + 1);
+            let style = if has_header && row_index == 0 { r#" s="1""# } else { "" };
+            match cell {
+                SheetCell::Empty => {},
+                SheetCell::String(value) => xml.push_str(&format!(r#"<c r="{}" t="inlineStr"{}><is><t xml:space="preserve">{}</t></is></c>"#, reference, style, escape_xml(value))),
+                // NaN and infinity aren't valid numbers in the file, so they're written as text.
+                SheetCell::Number(value) if !value.is_finite() => xml.push_str(&format!(r#"<c r="{}" t="inlineStr"{}><is><t>{}</t></is></c>"#, reference, style, format_number(*value))),
+                SheetCell::Number(value) => xml.push_str(&format!(r#"<c r="{}"{}><v>{}</v></c>"#, reference, style, value)),
+                SheetCell::Date(value) => xml.push_str(&format!(r#"<c r="{}" s="2"><v>{}</v></c>"#, reference, excel_serial_date(value))),
+                SheetCell::Bool(value) => xml.push_str(&format!(r#"<c r="{}" t="b"{}><v>{}</v></c>"#, reference, style, if *value { 1 } else { 0 })),
+            }
+        }
+        xml.push_str("</row>");
+    }
+    xml.push_str("</sheetData></worksheet>");
+    xml
+}
+
+fn write_ods_r(path: &Path, sheets: &[Sheet]) -> Result<(), String> {
+    let mut zip = ZipWriter::new(BufWriter::new(rse!(File::create(path))?));
+    // The mimetype file has to come first and can't be compressed so that the format can be
+    // recognized from the start of the file.
+    rse!(zip.start_file("mimetype", SimpleFileOptions::default().compression_method(CompressionMethod::Stored)))?;
+    rse!(zip.write_all(b"application/vnd.oasis.opendocument.spreadsheet"))?;
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    rse!(zip.start_file("META-INF/manifest.xml", options))?;
+    rse!(zip.write_all(r#"<?xml version="1.0" encoding="UTF-8"?>
+<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0" manifest:version="1.2"><manifest:file-entry manifest:full-path="/" manifest:version="1.2" manifest:media-type="application/vnd.oasis.opendocument.spreadsheet"/><manifest:file-entry manifest:full-path="content.xml" manifest:media-type="text/xml"/></manifest:manifest>"#.as_bytes()))?;
+    rse!(zip.start_file("content.xml", options))?;
+    rse!(zip.write_all(ods_content_xml(sheets).as_bytes()))?;
+    let mut writer = rse!(zip.finish())?;
+    rse!(writer.flush())
+}
+
+fn ods_content_xml(sheets: &[Sheet]) -> String {
+    // The styles are "ce1" for the bold header, "ce2" for a date, and "co{sheet}_{column}" for
+    // each column width.
+    let mut xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:style="urn:oasis:names:tc:opendocument:xmlns:style:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" xmlns:number="urn:oasis:names:tc:opendocument:xmlns:datastyle:1.0" xmlns:fo="urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0" office:version="1.2"><office:automatic-styles><number:date-style style:name="N1"><number:year number:style="long"/><number:text>-</number:text><number:month number:style="long"/><number:text>-</number:text><number:day number:style="long"/></number:date-style><style:style style:name="ce1" style:family="table-cell"><style:text-properties fo:font-weight="bold"/></style:style><style:style style:name="ce2" style:family="table-cell" style:data-style-name="N1"/>"#.to_string();
+    for (sheet_index, sheet) in sheets.iter().enumerate() {
+        for (column_index, width) in sheet.column_widths.iter().enumerate() {
+            // A character of the default font is about 0.2 cm wide.
+            xml.push_str(&format!(r#"<style:style style:name="co{}_{}" style:family="table-column"><style:table-column-properties style:column-width="{:.3}cm"/></style:style>"#,
+                sheet_index + 1, column_index + 1, width * 0.2));
+        }
+    }
+    xml.push_str("</office:automatic-styles><office:body><office:spreadsheet>");
+    for (sheet_index, sheet) in sheets.iter().enumerate() {
+        xml.push_str(&format!(r#"<table:table table:name="{}">"#, escape_xml(&sheet.name)));
+        // A table needs at least one column element.
+        for column_index in 0..sheet.column_count().max(sheet.column_widths.len()).max(1) {
+            if column_index < sheet.column_widths.len() {
+                xml.push_str(&format!(r#"<table:table-column table:style-name="co{}_{}"/>"#, sheet_index + 1, column_index + 1));
+            } else {
+                xml.push_str("<table:table-column/>");
+            }
+        }
+        let has_header = !sheet.header.is_empty();
+        for (row_index, row) in sheet.header_and_rows().iter().enumerate() {
+            xml.push_str("<table:table-row>");
+            let style = if has_header && row_index == 0 { r#" table:style-name="ce1""# } else { "" };
+            for cell in row.iter() {
+                match cell {
+                    SheetCell::Empty => xml.push_str("<table:table-cell/>"),
+                    SheetCell::String(value) => xml.push_str(&format!(r#"<table:table-cell office:value-type="string"{}><text:p>{}</text:p></table:table-cell>"#, style, escape_xml(value))),
+                    SheetCell::Number(value) if !value.is_finite() => xml.push_str(&format!(r#"<table:table-cell office:value-type="string"{}><text:p>{}</text:p></table:table-cell>"#, style, format_number(*value))),
+                    SheetCell::Number(value) => xml.push_str(&format!(r#"<table:table-cell office:value-type="float" office:value="{}"{}><text:p>{}</text:p></table:table-cell>"#, value, style, value)),
+                    SheetCell::Date(value) => {
+                        let text = value.format("%Y-%m-%d");
+                        xml.push_str(&format!(r#"<table:table-cell table:style-name="ce2" office:value-type="date" office:date-value="{}"><text:p>{}</text:p></table:table-cell>"#, text, text));
+                    },
+                    SheetCell::Bool(value) => xml.push_str(&format!(r#"<table:table-cell office:value-type="boolean" office:boolean-value="{}"{}><text:p>{}</text:p></table:table-cell>"#,
+                        value, style, if *value { "TRUE" } else { "FALSE" })),
+                }
+            }
+            xml.push_str("</table:table-row>");
+        }
+        xml.push_str("</table:table>");
+    }
+    xml.push_str("</office:spreadsheet></office:body></office:document-content>");
+    xml
+}
+
+fn column_letters(column_index: usize) -> String {
+    // Zero-based index to Excel's column name: 0 is "A", 25 is "Z", 26 is "AA".
+    let mut letters = vec![];
+    let mut number = column_index + 1;
+    while number > 0 {
+        let remainder = (number - 1) % 26;
+        letters.push((b'A' + remainder as u8) as char);
+        number = (number - 1) / 26;
+    }
+    letters.iter().rev().collect()
+}
+
+fn excel_serial_date(date: &NaiveDate) -> i64 {
+    // Excel counts days from 1899-12-30, which makes up for it treating 1900 as a leap year.
+    (*date - NaiveDate::from_ymd_opt(1899, 12, 30).unwrap()).num_days()
+}
+
+fn format_number(value: f64) -> String {
+    // Whole numbers such as counts shouldn't turn into "12.0".
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        value.to_string()
+    }
+}
+
+fn escape_xml(value: &str) -> String {
+    // Control characters other than tab and line breaks can't appear in XML 1.0 at all, even
+    // escaped, so they're dropped, as are the two noncharacters at the end of the BMP.
+    value.chars()
+        .filter(|c| matches!(c, '\t' | '\n' | '\r') || (*c >= ' ' && *c != '\u{fffe}' && *c != '\u{ffff}'))
+        .collect::<String>()
+        .replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{CellType, ColumnSpec, TableSchema};
+    use crate::tab;
+    use crate::temp_workspace::TempWorkspace;
+
+    fn report_sheets() -> Vec<Sheet> {
+        let mut sales = Sheet::with_header("Sales", &["Item", "Price", "Sold", "In Stock"]);
+        sales.add_row(vec!["Widget <large> & \"heavy\"".into(), 12.5.into(), NaiveDate::from_ymd_opt(2021, 3, 15).unwrap().into(), true.into()]);
+        sales.add_row(vec!["Gadget".into(), 3usize.into(), SheetCell::Empty, false.into()]);
+        sales.auto_fit_columns();
+        let mut grouper = Grouper::new("Colors");
+        grouper.record_entry(&"red".to_string());
+        grouper.record_entry(&"blue".to_string());
+        grouper.record_entry(&"red".to_string());
+        vec![sales, Sheet::from_grouper(&grouper, true)]
+    }
+
+    #[test]
+    fn test_write_and_read_spreadsheet() {
+        let workspace = TempWorkspace::new("test_write_and_read_spreadsheet");
+        for format in [SpreadsheetFormat::Xlsx, SpreadsheetFormat::Ods] {
+            let path = workspace.join(format!("Report.{}", format.extension()));
+            write_spreadsheet_r(&path, &report_sheets()).unwrap();
+            assert_eq!(vec!["Sales", "Colors"], sheet_names_r(&path).unwrap());
+
+            let sheets = read_spreadsheet_r(&path).unwrap();
+            assert_eq!(2, sheets.len());
+            let mut sales = sheets[0].clone();
+            sales.split_header();
+            assert_eq!(str_to_string_vector(&["Item", "Price", "Sold", "In Stock"]), sales.header);
+            assert_eq!(&SheetCell::string("Widget <large> & \"heavy\""), sales.cell(0, 0));
+            assert_eq!(Some(12.5), sales.cell(0, 1).as_f64());
+            assert_eq!(Some(NaiveDate::from_ymd_opt(2021, 3, 15).unwrap()), sales.cell(0, 2).as_date());
+            assert_eq!(&SheetCell::Bool(true), sales.cell(0, 3));
+            assert!(sales.cell(1, 2).is_empty());
+
+            // The text of each cell works with the tab::cell_as_* functions.
+            let text_rows = sales.text_rows();
+            assert_eq!(12.5, tab::cell_as_f32(&text_rows[0][1]));
+            assert_eq!(3, tab::cell_as_usize(&text_rows[1][1]));
+            assert_eq!(NaiveDate::from_ymd_opt(2021, 3, 15).unwrap(), tab::cell_as_date(&text_rows[0][2]));
+            assert!(!tab::cell_as_bool_result(&text_rows[1][3]).unwrap());
+
+            let colors = read_sheet_r(&path, "colors").unwrap();
+            assert_eq!(vec![vec!["Colors", "Count"], vec!["red", "2"], vec!["blue", "1"]], colors.text_rows());
+            assert!(read_sheet_r(&path, "Missing").is_err());
+        }
+    }
+
+    #[test]
+    fn test_write_unusual_values() {
+        // Numbers that aren't finite become text and characters XML can't hold are dropped, so
+        // the files still open.
+        let workspace = TempWorkspace::new("test_write_unusual_values");
+        let mut sheet = Sheet::new("Odd");
+        sheet.add_row(vec![SheetCell::Number(f64::NAN), SheetCell::Number(f64::INFINITY), SheetCell::string("bell\u{7}\ttab")]);
+        for format in [SpreadsheetFormat::Xlsx, SpreadsheetFormat::Ods] {
+            let path = workspace.join(format!("Odd.{}", format.extension()));
+            write_spreadsheet_r(&path, std::slice::from_ref(&sheet)).unwrap();
+            let read = read_sheet_r(&path, "Odd").unwrap();
+            assert_eq!(vec![vec!["NaN", "inf", "bell\ttab"]], read.text_rows());
+        }
+    }
+
+    #[test]
+    fn test_sheet_records() {
+        let mut sheet = Sheet::new("Data");
+        sheet.rows = vec![
+            vec!["Name".into(), "Count".into()],
+            vec!["a".into(), 1usize.into()],
+            vec!["b".into(), "many".into()],
+        ];
+        sheet.split_header();
+        let schema = TableSchema::new(vec![ColumnSpec::new("Name", CellType::String), ColumnSpec::new("Count", CellType::Usize)]);
+        let (rows, errors) = schema.map_records(Some(&sheet.header), &sheet.records()).unwrap();
+        assert_eq!(1, rows.len());
+        // The error points to the row in the spreadsheet, counting the header.
+        assert_eq!(3, errors[0].line_number);
+    }
+
+    #[test]
+    fn test_sheet_names_and_columns() {
+        assert_eq!("A", column_letters(0));
+        assert_eq!("Z", column_letters(25));
+        assert_eq!("AA", column_letters(26));
+        assert_eq!("BA", column_letters(52));
+        assert_eq!(44270, excel_serial_date(&NaiveDate::from_ymd_opt(2021, 3, 15).unwrap()));
+        assert!(validate_sheet_names_r(&[Sheet::new("Good")]).is_ok());
+        assert!(validate_sheet_names_r(&[]).is_err());
+        assert!(validate_sheet_names_r(&[Sheet::new("Bad/Name")]).is_err());
+        assert!(validate_sheet_names_r(&[Sheet::new("Same"), Sheet::new("SAME")]).is_err());
+        assert!(SpreadsheetFormat::from_path_r("report.csv").is_err());
+
+        let mut grouper = Grouper::new("Files by folder: C:\\Data\\[Archive]/2021 and older");
+        grouper.record_entry(&"a".to_string());
+        let sheet = Sheet::from_grouper(&grouper, false);
+        assert_eq!("Files by folder_ C__Data__Archi", sheet.name);
+        assert!(validate_sheet_names_r(&[sheet]).is_ok());
+        assert_eq!("Sheet", valid_sheet_name("''"));
+    }
+}
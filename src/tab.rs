@@ -1,6 +1,17 @@
 use crate::format;
 use crate::parse;
 use chrono::NaiveDate;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use num_format::Locale;
+
+use crate::*;
+use crate::date_time::{naive_date_from_formats, naive_date_from_multiple_formats};
+use crate::encoding::LineEnding;
+use crate::file::path_name;
+use crate::line_reader::LineReader;
 
 pub fn cell_as_usize(val: &str) -> usize {
     let val = clean_cell(val);
@@ -184,6 +195,163 @@ pub fn cell_as_bool_result(val: &str) -> Result<bool, String> {
     }
 }
 
+// Cell parsing that can be set up for the conventions of a particular file, unlike the
+// cell_as_* functions above which assume US-style numbers and "%m/%d/%y" dates.
+
+#[derive(Clone, Debug)]
+pub struct CellConfig {
+    // Chrono format strings tried in order. If none of them work, the formats known to
+    // date_time::naive_date_from_multiple_formats() are tried.
+    pub date_formats: Vec<String>,
+    pub thousands_separator: String,
+    pub decimal_separator: String,
+    pub minus_sign: String,
+    // Removed wherever they appear, as in "$12.50" or "12,50 €".
+    pub currency_symbols: Vec<String>,
+    // A number in parentheses like "(1,234)" is negative.
+    pub accounting_negatives: bool,
+    // "12.5%" is 0.125 rather than 12.5.
+    pub percent_as_fraction: bool,
+    // A cell with just "-" is zero, as in many accounting reports.
+    pub dash_is_zero: bool,
+}
+
+impl Default for CellConfig {
+    fn default() -> Self {
+        Self {
+            date_formats: vec!["%m/%d/%y".to_string()],
+            thousands_separator: ",".to_string(),
+            decimal_separator: ".".to_string(),
+            minus_sign: "-".to_string(),
+            currency_symbols: str_to_string_vector(&["$", "\u{20AC}", "\u{A3}", "\u{A5}"]),
+            accounting_negatives: true,
+            percent_as_fraction: true,
+            dash_is_zero: true,
+        }
+    }
+}
+
+impl CellConfig {
+    pub fn for_locale(locale: &Locale) -> Self {
+        // Take the separators and minus sign from a num-format locale, such as Locale::de for
+        // numbers like "1.234,5".
+        Self {
+            thousands_separator: locale.separator().to_string(),
+            decimal_separator: locale.decimal().to_string(),
+            minus_sign: locale.minus_sign().to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn cell_as_f64_optional_r(&self, val: &str) -> Result<Option<f64>, String> {
+        let val = clean_cell(val);
+        if val.is_empty() {
+            return Ok(None);
+        }
+        if self.dash_is_zero && val == "-" {
+            return Ok(Some(0.0));
+        }
+        let err = || format!("Can't parse \"{}\" as a number.", val);
+        let mut number = val.as_str();
+        let mut negative = false;
+        if self.accounting_negatives && number.starts_with('(') && number.ends_with(')') {
+            negative = true;
+            number = &number[1..number.len() - 1];
+        }
+        let mut number = number.to_string();
+        for symbol in self.currency_symbols.iter().filter(|symbol| !symbol.is_empty()) {
+            number = number.replace(symbol.as_str(), "");
+        }
+        let mut number = number.trim().to_string();
+        // The minus sign may come before or after a currency symbol, as in "-$5" and "$-5".
+        for minus_sign in [self.minus_sign.as_str(), "-"].iter().filter(|minus_sign| !minus_sign.is_empty()) {
+            if let Some(rest) = number.strip_prefix(minus_sign) {
+                if negative {
+                    return Err(err());
+                }
+                negative = true;
+                number = rest.trim().to_string();
+            }
+        }
+        let percent = match number.strip_suffix('%') {
+            Some(rest) => {
+                number = rest.trim().to_string();
+                true
+            },
+            None => false,
+        };
+        if !self.thousands_separator.is_empty() {
+            number = number.replace(&self.thousands_separator, "");
+        }
+        if !self.decimal_separator.is_empty() && self.decimal_separator != "." {
+            number = number.replace(&self.decimal_separator, ".");
+        }
+        if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            return Err(err());
+        }
+        let mut value = number.parse::<f64>().map_err(|_| err())?;
+        if percent && self.percent_as_fraction {
+            value /= 100.0;
+        }
+        if negative {
+            value = -value;
+        }
+        Ok(Some(value))
+    }
+
+    pub fn cell_as_f64_r(&self, val: &str) -> Result<f64, String> {
+        self.cell_as_f64_optional_r(val)?.ok_or_else(|| "A number is required but the cell is empty.".to_string())
+    }
+
+    pub fn cell_as_f32_optional_r(&self, val: &str) -> Result<Option<f32>, String> {
+        Ok(self.cell_as_f64_optional_r(val)?.map(|value| value as f32))
+    }
+
+    pub fn cell_as_f32_r(&self, val: &str) -> Result<f32, String> {
+        Ok(self.cell_as_f64_r(val)? as f32)
+    }
+
+    pub fn cell_as_i64_optional_r(&self, val: &str) -> Result<Option<i64>, String> {
+        match self.cell_as_f64_optional_r(val)? {
+            Some(value) if value.fract() != 0.0 || value.abs() > i64::MAX as f64 =>
+                Err(format!("Can't parse \"{}\" as a whole number.", clean_cell(val))),
+            Some(value) => Ok(Some(value as i64)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn cell_as_usize_optional_r(&self, val: &str) -> Result<Option<usize>, String> {
+        match self.cell_as_i64_optional_r(val)? {
+            Some(value) if value < 0 => Err(format!("Can't parse \"{}\" as usize since it's negative.", clean_cell(val))),
+            Some(value) => Ok(Some(value as usize)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn cell_as_usize_r(&self, val: &str) -> Result<usize, String> {
+        self.cell_as_usize_optional_r(val)?.ok_or_else(|| "A number is required but the cell is empty.".to_string())
+    }
+
+    pub fn cell_as_date_optional_r(&self, val: &str) -> Result<Option<NaiveDate>, String> {
+        let val = clean_cell(val);
+        if val.is_empty() {
+            return Ok(None);
+        }
+        let formats = self.date_formats.iter().map(|format| format.as_str()).collect::<Vec<_>>();
+        match naive_date_from_formats(&val, &formats) {
+            Ok(date) => Ok(Some(date)),
+            Err(msg) => match naive_date_from_multiple_formats(&val) {
+                Ok(date) => Ok(Some(date)),
+                Err(_) => Err(msg),
+            },
+        }
+    }
+
+    pub fn cell_as_date_r(&self, val: &str) -> Result<NaiveDate, String> {
+        self.cell_as_date_optional_r(val)?.ok_or_else(|| "A date is required but the cell is empty.".to_string())
+    }
+}
+
 fn clean_cell(val: &str) -> String {
     let val = val.replace("\u{0}", "");
     let val = format::remove_surrounding_delimiters(val.trim(), "\"", "\"");
@@ -199,3 +367,356 @@ fn clean_price(val: &str) -> String {
     let val = clean_cell(val);
     clean_number(parse::after(val.trim(), "$")).trim().to_string()
 }
+
+// Reading and writing delimited text such as CSV and TSV following RFC 4180. Fields may be quoted,
+// in which case they can contain the delimiter, line breaks, and quotes written as two quotes.
+// Rows don't all need the same number of fields.
+
+#[derive(Clone, Debug)]
+pub struct DelimitedFormat {
+    pub delimiter: char,
+    pub quote: char,
+    pub has_header: bool,
+    // Used when writing. Any line ending is accepted when reading.
+    pub line_ending: LineEnding,
+}
+
+pub struct DelimitedReader<'a> {
+    lines: LineReader<'a>,
+    format: DelimitedFormat,
+    context: String,
+    header: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DelimitedRecord {
+    // The line where the record starts, which may span several lines if it has quoted line breaks.
+    pub line_number: usize,
+    pub fields: Vec<String>,
+}
+
+pub struct DelimitedWriter<W>
+    where W: Write
+{
+    writer: W,
+    format: DelimitedFormat,
+}
+
+impl DelimitedFormat {
+    pub fn csv() -> Self {
+        Self { delimiter: ',', quote: '"', has_header: true, line_ending: LineEnding::CrLf }
+    }
+
+    pub fn tsv() -> Self {
+        Self { delimiter: '\t', quote: '"', has_header: true, line_ending: LineEnding::CrLf }
+    }
+
+    pub fn quote_field(&self, field: &str) -> String {
+        // Quote only when needed, so that simple values are written as they are.
+        let needs_quotes = field.contains(self.delimiter) || field.contains(self.quote)
+            || field.contains('\n') || field.contains('\r')
+            || field.starts_with(' ') || field.ends_with(' ');
+        if needs_quotes {
+            let quote = self.quote.to_string();
+            format!("{}{}{}", quote, field.replace(&quote, &quote.repeat(2)), quote)
+        } else {
+            field.to_string()
+        }
+    }
+
+    pub fn format_record<S>(&self, fields: &[S]) -> String
+        where S: AsRef<str>
+    {
+        // A record with a single empty field would otherwise be a blank line, which the reader
+        // skips, so that field is quoted.
+        if fields.len() == 1 && fields[0].as_ref().is_empty() {
+            return self.quote.to_string().repeat(2);
+        }
+        fields.iter().map(|field| self.quote_field(field.as_ref())).collect::<Vec<_>>().join(&self.delimiter.to_string())
+    }
+}
+
+impl <'a> DelimitedReader<'a> {
+    pub fn from_reader<R>(reader: R, format: DelimitedFormat, context: &str) -> Result<Self, String>
+        where R: Read + 'a
+    {
+        Self::from_line_reader(LineReader::from_reader(reader, context)?, format, context)
+    }
+
+    pub fn from_text(text: &'a str, format: DelimitedFormat) -> Result<Self, String> {
+        Self::from_line_reader(LineReader::from_text(text), format, "string")
+    }
+
+    fn from_line_reader(lines: LineReader<'a>, format: DelimitedFormat, context: &str) -> Result<Self, String> {
+        let mut reader = Self {
+            lines,
+            format,
+            context: context.to_string(),
+            header: None,
+        };
+        if reader.format.has_header {
+            reader.header = reader.read_record_r()?.map(|record| record.fields);
+        }
+        Ok(reader)
+    }
+
+    pub fn header(&self) -> Option<&[String]> {
+        self.header.as_deref()
+    }
+
+    pub fn column_index(&self, column_name: &str) -> Option<usize> {
+        // Header names are matched ignoring case and surrounding spaces.
+        let column_name = column_name.trim().to_lowercase();
+        self.header.as_ref()?.iter().position(|name| name.trim().to_lowercase() == column_name)
+    }
+
+    pub fn column_index_r(&self, column_name: &str) -> Result<usize, String> {
+        self.column_index(column_name).ok_or_else(|| format!("{}: no column named \"{}\".", self.context, column_name))
+    }
+
+    pub fn read_record_r(&mut self) -> Result<Option<DelimitedRecord>, String> {
+        let delimiter = self.format.delimiter;
+        let quote = self.format.quote;
+        let mut fields = vec![];
+        let mut field = String::new();
+        let mut in_quotes = false;
+        let mut line_number = 0;
+        loop {
+            let line = match self.lines.read_line_r()? {
+                Some(line) => line,
+                None => {
+                    if in_quotes {
+                        return Err(format!("{}: the quoted field in the record starting on line {} is never closed.", self.context, line_number));
+                    }
+                    return Ok(None);
+                },
+            };
+            if line_number == 0 {
+                // Empty lines between records are skipped rather than read as records with one
+                // empty field.
+                if line.text.is_empty() {
+                    continue;
+                }
+                line_number = line.number;
+            }
+            let mut chars = line.text.chars().peekable();
+            while let Some(c) = chars.next() {
+                if in_quotes {
+                    if c == quote {
+                        if chars.peek() == Some(&quote) {
+                            field.push(quote);
+                            chars.next();
+                        } else {
+                            in_quotes = false;
+                        }
+                    } else {
+                        field.push(c);
+                    }
+                } else if c == delimiter {
+                    fields.push(std::mem::take(&mut field));
+                } else if c == quote && field.trim().is_empty() {
+                    // An opening quote, possibly after some spaces which are dropped. A quote
+                    // anywhere else in an unquoted field is taken literally.
+                    field.clear();
+                    in_quotes = true;
+                } else {
+                    field.push(c);
+                }
+            }
+            if in_quotes {
+                // The line break is part of the quoted field.
+                field.push_str(line.line_ending.map_or("\n", |line_ending| line_ending.as_str()));
+            } else {
+                fields.push(field);
+                return Ok(Some(DelimitedRecord { line_number, fields }));
+            }
+        }
+    }
+}
+
+impl DelimitedReader<'static> {
+    pub fn open_r<P>(path: P, format: DelimitedFormat) -> Result<Self, String>
+        where P: AsRef<Path>
+    {
+        Self::from_line_reader(LineReader::open_r(&path)?, format, &path_name(&path))
+    }
+}
+
+impl <'a> Iterator for DelimitedReader<'a> {
+    type Item = Result<DelimitedRecord, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record_r().transpose()
+    }
+}
+
+impl DelimitedRecord {
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn cell(&self, column_index: usize) -> &str {
+        // A column past the end of a short row is treated as an empty cell.
+        self.fields.get(column_index).map_or("", |field| field.as_str())
+    }
+
+    pub fn cell_r<T, F>(&self, column_index: usize, cell_func: F) -> Result<T, String>
+        where F: Fn(&str) -> Result<T, String>
+    {
+        // Run one of the cell_as_*_result() functions on a cell, adding the location to any error.
+        // The column number in the message is one-based as in a spreadsheet.
+        cell_func(self.cell(column_index)).map_err(|msg| format!("Line {}, column {}: {}", self.line_number, column_index + 1, msg))
+    }
+}
+
+impl <W> DelimitedWriter<W>
+    where W: Write
+{
+    pub fn new(writer: W, format: DelimitedFormat) -> Self {
+        Self { writer, format }
+    }
+
+    pub fn write_record_r<S>(&mut self, fields: &[S]) -> Result<(), String>
+        where S: AsRef<str>
+    {
+        let line = format!("{}{}", self.format.format_record(fields), self.format.line_ending.as_str());
+        rse!(self.writer.write_all(line.as_bytes()))
+    }
+
+    pub fn flush_r(&mut self) -> Result<(), String> {
+        rse!(self.writer.flush())
+    }
+}
+
+impl DelimitedWriter<BufWriter<File>> {
+    pub fn create_r<P>(path: P, format: DelimitedFormat) -> Result<Self, String>
+        where P: AsRef<Path>
+    {
+        let file = rse!(File::create(&path))?;
+        Ok(Self::new(BufWriter::new(file), format))
+    }
+}
+
+pub fn read_delimited_file_r<P>(path: P, format: DelimitedFormat) -> Result<(Option<Vec<String>>, Vec<DelimitedRecord>), String>
+    where P: AsRef<Path>
+{
+    let mut reader = DelimitedReader::open_r(path, format)?;
+    let header = reader.header.take();
+    Ok((header, reader.collect::<Result<Vec<_>, _>>()?))
+}
+
+pub fn write_delimited_file_r<P, S>(path: P, format: DelimitedFormat, header: Option<&[S]>, rows: &[Vec<S>]) -> Result<(), String>
+    where
+        P: AsRef<Path>,
+        S: AsRef<str>,
+{
+    let mut writer = DelimitedWriter::create_r(&path, format)?;
+    if let Some(header) = header {
+        writer.write_record_r(header)?;
+    }
+    for row in rows.iter() {
+        writer.write_record_r(row)?;
+    }
+    writer.flush_r()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::temp_workspace::TempWorkspace;
+
+    #[test]
+    fn test_cell_config() {
+        let config = CellConfig::default();
+        assert_eq!(Some(-1_234.0), config.cell_as_f64_optional_r("(1,234)").unwrap());
+        assert_eq!(Some(-5.25), config.cell_as_f64_optional_r("\"-$5.25\"").unwrap());
+        assert_eq!(Some(-5.25), config.cell_as_f64_optional_r("$-5.25").unwrap());
+        assert_eq!(Some(0.125), config.cell_as_f64_optional_r("12.5%").unwrap());
+        assert_eq!(Some(0.0), config.cell_as_f64_optional_r(" - ").unwrap());
+        assert_eq!(None, config.cell_as_f64_optional_r("").unwrap());
+        assert!(config.cell_as_f64_optional_r("12abc").is_err());
+        assert!(config.cell_as_f64_optional_r("(-3)").is_err());
+        assert_eq!(1_234, config.cell_as_usize_r("1,234").unwrap());
+        assert!(config.cell_as_usize_r("(1,234)").is_err());
+        assert!(config.cell_as_usize_r("1.5").is_err());
+        assert_eq!(Some(-7), config.cell_as_i64_optional_r("(7)").unwrap());
+
+        let config = CellConfig { percent_as_fraction: false, ..CellConfig::for_locale(&Locale::de) };
+        assert_eq!(1_234.5, config.cell_as_f64_r("1.234,50 \u{20AC}").unwrap());
+        assert_eq!(12.5, config.cell_as_f64_r("12,5%").unwrap());
+
+        let date = NaiveDate::from_ymd_opt(2021, 3, 15).unwrap();
+        let config = CellConfig { date_formats: str_to_string_vector(&["%d.%m.%Y", "%m/%d/%y"]), ..CellConfig::default() };
+        assert_eq!(date, config.cell_as_date_r("15.03.2021").unwrap());
+        assert_eq!(date, config.cell_as_date_r("03/15/21").unwrap());
+        // Falls back to the standard formats.
+        assert_eq!(date, config.cell_as_date_r("2021-Mar-15").unwrap());
+        assert_eq!(None, config.cell_as_date_optional_r(" ").unwrap());
+        assert!(config.cell_as_date_r("15th of March").is_err());
+    }
+
+    #[test]
+    fn test_delimited_reader() {
+        let text = "Name,Date,Price,Notes\r\n\
+            \"Smith, Jan\",03/15/21,$12.50,\"She said \"\"hi\"\"\"\r\n\
+            \r\n\
+            Jones,04/01/21,\"$1,200.00\",\"Line one\r\nLine two\"\r\n\
+            Short,05/02/21\r\n";
+        let reader = DelimitedReader::from_text(text, DelimitedFormat::csv()).unwrap();
+        assert_eq!(Some(&str_to_string_vector(&["Name", "Date", "Price", "Notes"])[..]), reader.header());
+        assert_eq!(Some(2), reader.column_index(" price "));
+        assert!(reader.column_index_r("Missing").is_err());
+
+        let records = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(3, records.len());
+        assert_eq!(str_to_string_vector(&["Smith, Jan", "03/15/21", "$12.50", "She said \"hi\""]), records[0].fields);
+        assert_eq!(4, records[1].line_number);
+        assert_eq!("Line one\r\nLine two", records[1].cell(3));
+        assert_eq!(1_200.0, records[1].cell_r(2, cell_as_price_result).unwrap());
+        assert_eq!(NaiveDate::from_ymd_opt(2021, 4, 1).unwrap(), records[1].cell_r(1, cell_as_date_result).unwrap());
+
+        // A ragged row reads as empty cells past its end.
+        assert_eq!(2, records[2].len());
+        assert_eq!(None, records[2].cell_r(2, cell_as_price_optional_result).unwrap());
+        let err = records[2].cell_r(0, cell_as_usize_result).unwrap_err();
+        assert!(err.starts_with("Line 6, column 1: "), "{}", err);
+
+        let format = DelimitedFormat { has_header: false, ..DelimitedFormat::csv() };
+        let err = DelimitedReader::from_text("a,\"b\nc", format).unwrap().read_record_r().unwrap_err();
+        assert!(err.contains("line 1"), "{}", err);
+    }
+
+    #[test]
+    fn test_delimited_write_and_read() {
+        let workspace = TempWorkspace::new("test_delimited_write_and_read");
+        let path_file = workspace.join("Table.tsv");
+        let header = vec!["Key", "Value"];
+        let rows = vec![
+            vec!["plain", "tab\there"],
+            vec![" padded ", "quote \" and\nnewline"],
+            vec!["", ""],
+        ];
+        write_delimited_file_r(&path_file, DelimitedFormat::tsv(), Some(&header), &rows).unwrap();
+        let (read_header, records) = read_delimited_file_r(&path_file, DelimitedFormat::tsv()).unwrap();
+        assert_eq!(Some(str_to_string_vector(&header)), read_header);
+        // The row of empty fields is written as a lone tab, so it isn't a blank line.
+        assert_eq!(rows.iter().map(|row| str_to_string_vector(row)).collect::<Vec<_>>(),
+                   records.iter().map(|record| record.fields.clone()).collect::<Vec<_>>());
+
+        assert_eq!("a,\"b,c\",\"d\"\"e\"", DelimitedFormat::csv().format_record(&["a", "b,c", "d\"e"]));
+
+        // In a single column an empty value is written as "" so it isn't lost as a blank line.
+        let path_file = workspace.join("Column.csv");
+        let rows = vec![vec!["a"], vec![""], vec!["b"]];
+        write_delimited_file_r(&path_file, DelimitedFormat::csv(), Some(&["Name"]), &rows).unwrap();
+        assert_eq!("Name\r\na\r\n\"\"\r\nb\r\n", fs::read_to_string(&path_file).unwrap());
+        let (_, records) = read_delimited_file_r(&path_file, DelimitedFormat::csv()).unwrap();
+        assert_eq!(rows.iter().map(|row| str_to_string_vector(row)).collect::<Vec<_>>(),
+                   records.iter().map(|record| record.fields.clone()).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,214 @@
+// A temporary folder for tests that work with real files. Each workspace gets its own uniquely
+// named folder under the system temp folder, so tests can run in parallel without stepping on
+// each other, and the folder is deleted when the workspace is dropped. The contents can be set up
+// from a declarative spec and the resulting tree compared against another spec.
+//
+// The module is only compiled for tests, so problems setting up or reading the workspace panic
+// rather than returning errors.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::file::path_file_name_r;
+
+static NEXT_WORKSPACE_NUMBER: AtomicUsize = AtomicUsize::new(1);
+
+pub struct TempWorkspace {
+    path: PathBuf,
+    keep: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TreeSpec {
+    File {
+        name: String,
+        contents: String,
+    },
+    Folder {
+        name: String,
+        entries: Vec<TreeSpec>,
+    },
+}
+
+pub fn spec_file(name: &str, contents: &str) -> TreeSpec {
+    TreeSpec::File { name: name.to_string(), contents: contents.to_string() }
+}
+
+pub fn spec_folder(name: &str, entries: Vec<TreeSpec>) -> TreeSpec {
+    TreeSpec::Folder { name: name.to_string(), entries }
+}
+
+impl TempWorkspace {
+    pub fn new(label: &str) -> Self {
+        // The label, usually the name of the test, only makes the folder easier to recognize.
+        loop {
+            let number = NEXT_WORKSPACE_NUMBER.fetch_add(1, Ordering::SeqCst);
+            let folder_name = format!("util_{}_{}_{}", label, std::process::id(), number);
+            let path = std::env::temp_dir().join(folder_name);
+            // create_dir() fails if the folder is already there, such as from an earlier run that
+            // had the same process ID and was killed before cleaning up, in which case try the next
+            // number. Any other failure would happen again on every try.
+            match fs::create_dir(&path) {
+                Ok(()) => return Self { path, keep: false },
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {},
+                Err(err) => panic!("TempWorkspace::new: Couldn't create \"{}\": {}", path.to_string_lossy(), err),
+            }
+        }
+    }
+
+    pub fn with_spec(label: &str, specs: &[TreeSpec]) -> Self {
+        let workspace = Self::new(label);
+        workspace.create(specs);
+        workspace
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join<P>(&self, path: P) -> PathBuf
+        where P: AsRef<Path>
+    {
+        self.path.join(path)
+    }
+
+    pub fn keep(&mut self) {
+        // Leave the folder in place when the workspace is dropped, to look at after a failure. The
+        // folder is at path().
+        self.keep = true;
+    }
+
+    pub fn create(&self, specs: &[TreeSpec]) {
+        create_specs(&self.path, specs);
+    }
+
+    pub fn read_tree<P>(&self, relative_path: P) -> Vec<TreeSpec>
+        where P: AsRef<Path>
+    {
+        read_specs(&self.path.join(relative_path))
+    }
+
+    pub fn tree_lines<P>(&self, relative_path: P) -> Vec<String>
+        where P: AsRef<Path>
+    {
+        // The shape of the tree without the file contents, like "Docs/" and "Docs/Notes.txt".
+        let mut lines = vec![];
+        add_tree_lines(&self.read_tree(relative_path), "", &mut lines);
+        lines
+    }
+
+    pub fn assert_tree<P>(&self, relative_path: P, expected: &[TreeSpec])
+        where P: AsRef<Path>
+    {
+        let mut expected = expected.to_vec();
+        sort_specs(&mut expected);
+        assert_eq!(expected, self.read_tree(relative_path));
+    }
+
+    pub fn assert_tree_lines<P>(&self, relative_path: P, expected: &[&str])
+        where P: AsRef<Path>
+    {
+        let mut expected = expected.iter().map(|line| line.to_string()).collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(expected, self.tree_lines(relative_path));
+    }
+}
+
+impl Drop for TempWorkspace {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+impl TreeSpec {
+    pub fn name(&self) -> &str {
+        match self {
+            TreeSpec::File { name, .. } => name,
+            TreeSpec::Folder { name, .. } => name,
+        }
+    }
+}
+
+fn create_specs(path_folder: &Path, specs: &[TreeSpec]) {
+    fs::create_dir_all(path_folder).unwrap();
+    for spec in specs.iter() {
+        match spec {
+            TreeSpec::File { name, contents } => fs::write(path_folder.join(name), contents).unwrap(),
+            TreeSpec::Folder { name, entries } => create_specs(&path_folder.join(name), entries),
+        }
+    }
+}
+
+fn read_specs(path_folder: &Path) -> Vec<TreeSpec> {
+    let mut specs = vec![];
+    for entry in fs::read_dir(path_folder).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path_file_name_r(&path).unwrap();
+        if path.is_dir() {
+            specs.push(TreeSpec::Folder { name, entries: read_specs(&path) });
+        } else {
+            let contents = String::from_utf8_lossy(&fs::read(&path).unwrap()).to_string();
+            specs.push(TreeSpec::File { name, contents });
+        }
+    }
+    sort_specs(&mut specs);
+    specs
+}
+
+fn sort_specs(specs: &mut [TreeSpec]) {
+    // Sort by name at each level so that the order of the spec and of the directory listing don't
+    // matter.
+    specs.sort_by(|a, b| a.name().cmp(b.name()));
+    for spec in specs.iter_mut() {
+        if let TreeSpec::Folder { entries, .. } = spec {
+            sort_specs(entries);
+        }
+    }
+}
+
+fn add_tree_lines(specs: &[TreeSpec], prefix: &str, lines: &mut Vec<String>) {
+    for spec in specs.iter() {
+        match spec {
+            TreeSpec::File { name, .. } => lines.push(format!("{}{}", prefix, name)),
+            TreeSpec::Folder { name, entries } => {
+                let folder_prefix = format!("{}{}/", prefix, name);
+                lines.push(folder_prefix.clone());
+                add_tree_lines(entries, &folder_prefix, lines);
+            },
+        }
+    }
+    lines.sort();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_workspace() {
+        let path;
+        {
+            let workspace = TempWorkspace::with_spec("test_temp_workspace", &[
+                spec_file("b.txt", "B"),
+                spec_folder("Docs", vec![
+                    spec_file("notes.txt", "Notes"),
+                    spec_folder("Empty", vec![]),
+                ]),
+            ]);
+            path = workspace.path().to_path_buf();
+            assert_eq!("Notes", fs::read_to_string(workspace.join("Docs").join("notes.txt")).unwrap());
+            workspace.assert_tree_lines("", &["b.txt", "Docs/", "Docs/Empty/", "Docs/notes.txt"]);
+            workspace.assert_tree("Docs", &[spec_folder("Empty", vec![]), spec_file("notes.txt", "Notes")]);
+
+            // Two workspaces with the same label don't share a folder.
+            let other = TempWorkspace::new("test_temp_workspace");
+            assert_ne!(workspace.path(), other.path());
+            assert!(other.tree_lines("").is_empty());
+        }
+        assert!(!path.exists());
+    }
+}
@@ -68,7 +68,16 @@ impl <T> Tree<T>
      */
 
     pub fn create(pairs: Vec<(T, T)>, do_calculations: bool) -> Self {
+        Self::create_with_items(vec![], pairs, do_calculations)
+    }
+
+    pub fn create_with_items(items: Vec<T>, pairs: Vec<(T, T)>, do_calculations: bool) -> Self {
+        // Like create() but the tree also includes the given items, which matters for items that
+        // don't appear in any parent-child pair. Those become top nodes with no children.
         let mut node_map: BTreeMap<T, Rc<RefCell<TreeNode<T>>>> = BTreeMap::new();
+        for item in items {
+            node_map.entry(item.clone()).or_insert_with(|| r!(TreeNode::new(None, item)));
+        }
         for (parent, child) in pairs.iter() {
             let parent_rc = if node_map.contains_key(parent) {
                 node_map.get(parent).unwrap().clone()
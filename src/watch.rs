@@ -0,0 +1,201 @@
+// A polling file watcher. It doesn't use any OS-specific notification backend, just the file
+// metadata, so it behaves the same everywhere at the cost of a little latency. Each path to watch
+// may be a file, a folder (meaning the files directly in it) or a glob pattern like
+// "data/**/*.txt". Changes are debounced so that an editor that saves a file in several steps
+// produces one event rather than a burst of them.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::*;
+use crate::file::{path_entries_r, path_metadata_r};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WatchEventKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WatchEvent {
+    pub path: PathBuf,
+    pub kind: WatchEventKind,
+}
+
+pub struct FileWatcher {
+    patterns: Vec<String>,
+    poll_interval: Duration,
+    debounce: Duration,
+    // The state of each file as of the last event reported for it, or the first scan.
+    reported: BTreeMap<PathBuf, FileState>,
+    // The state of each file as of the most recent scan.
+    scanned: BTreeMap<PathBuf, FileState>,
+    // Files that have changed since they were last reported, with the time of the latest change.
+    pending: BTreeMap<PathBuf, Instant>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileState {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl FileWatcher {
+    pub fn new_r(patterns: &[&str], poll_interval: Duration, debounce: Duration) -> Result<Self, String> {
+        for pattern in patterns.iter().filter(|pattern| is_glob(pattern)) {
+            rse!(glob::Pattern::new(pattern))?;
+        }
+        let mut watcher = Self {
+            patterns: str_to_string_vector(patterns),
+            poll_interval,
+            debounce,
+            reported: BTreeMap::new(),
+            scanned: BTreeMap::new(),
+            pending: BTreeMap::new(),
+        };
+        // Files that already exist when the watcher is created don't count as created.
+        watcher.scanned = watcher.scan_r()?;
+        watcher.reported = watcher.scanned.clone();
+        Ok(watcher)
+    }
+
+    pub fn watched_paths(&self) -> Vec<PathBuf> {
+        self.scanned.keys().cloned().collect()
+    }
+
+    pub fn poll_r(&mut self) -> Result<Vec<WatchEvent>, String> {
+        // Scan once and return the events for files that have settled, meaning they haven't changed
+        // again for at least the debounce interval. A file that's created and then deleted before
+        // it settles produces no event at all.
+        let now = Instant::now();
+        let scanned = self.scan_r()?;
+        let paths = self.scanned.keys().chain(scanned.keys()).cloned().collect::<BTreeSet<_>>();
+        for path in paths {
+            if self.scanned.get(&path) != scanned.get(&path) {
+                self.pending.insert(path, now);
+            }
+        }
+        self.scanned = scanned;
+
+        let settled = self.pending.iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= self.debounce)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        let mut events = vec![];
+        for path in settled {
+            self.pending.remove(&path);
+            let kind = match (self.reported.get(&path), self.scanned.get(&path)) {
+                (None, Some(_)) => Some(WatchEventKind::Created),
+                (Some(_), None) => Some(WatchEventKind::Deleted),
+                (Some(before), Some(after)) if before != after => Some(WatchEventKind::Modified),
+                _ => None,
+            };
+            match self.scanned.get(&path) {
+                Some(state) => self.reported.insert(path.clone(), *state),
+                None => self.reported.remove(&path),
+            };
+            if let Some(kind) = kind {
+                events.push(WatchEvent { path, kind });
+            }
+        }
+        Ok(events)
+    }
+
+    pub fn watch_r<F>(&mut self, mut callback: F) -> Result<(), String>
+        where F: FnMut(&[WatchEvent]) -> bool
+    {
+        // Poll until the callback returns false. The callback is only called when there's at least
+        // one event.
+        loop {
+            thread::sleep(self.poll_interval);
+            let events = self.poll_r()?;
+            if !events.is_empty() && !callback(&events) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn scan_r(&self) -> Result<BTreeMap<PathBuf, FileState>, String> {
+        let mut paths = vec![];
+        for pattern in self.patterns.iter() {
+            if is_glob(pattern) {
+                // Entries that can't be read are skipped like missing files rather than stopping
+                // the watcher.
+                paths.extend(rse!(glob::glob(pattern))?.filter_map(|entry| entry.ok()));
+            } else {
+                let path = PathBuf::from(pattern);
+                if path.is_dir() {
+                    paths.extend(path_entries_r(&path)?);
+                } else {
+                    paths.push(path);
+                }
+            }
+        }
+        let mut states = BTreeMap::new();
+        for path in paths {
+            // A file may be deleted between being listed and being looked at, in which case it's
+            // simply missing from this scan.
+            if let Ok(metadata) = path_metadata_r(&path) {
+                if metadata.is_file() {
+                    let state = FileState { modified: metadata.modified().ok(), len: metadata.len() };
+                    states.insert(path, state);
+                }
+            }
+        }
+        Ok(states)
+    }
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::temp_workspace::TempWorkspace;
+
+    #[test]
+    fn test_poll_r() {
+        let workspace = TempWorkspace::new("test_poll_r");
+        let path_folder = workspace.path();
+        let path_a = path_folder.join("a.txt");
+        let path_b = path_folder.join("b.txt");
+        let path_c = path_folder.join("c.md");
+        fs::write(&path_a, "a").unwrap();
+
+        let pattern = format!("{}/*.txt", path_folder.to_string_lossy());
+        let mut watcher = FileWatcher::new_r(&[&pattern], Duration::ZERO, Duration::ZERO).unwrap();
+        assert_eq!(vec![path_a.clone()], watcher.watched_paths());
+        assert!(watcher.poll_r().unwrap().is_empty());
+
+        fs::write(&path_a, "a changed").unwrap();
+        fs::write(&path_b, "b").unwrap();
+        fs::write(&path_c, "not watched").unwrap();
+        let expected = vec![
+            WatchEvent { path: path_a.clone(), kind: WatchEventKind::Modified },
+            WatchEvent { path: path_b.clone(), kind: WatchEventKind::Created },
+        ];
+        assert_eq!(expected, watcher.poll_r().unwrap());
+
+        fs::remove_file(&path_a).unwrap();
+        assert_eq!(vec![WatchEvent { path: path_a.clone(), kind: WatchEventKind::Deleted }], watcher.poll_r().unwrap());
+
+        // With a long debounce nothing is reported yet, and a file that comes and goes before it
+        // settles is never reported.
+        let mut watcher = FileWatcher::new_r(&[&pattern], Duration::ZERO, Duration::from_secs(3600)).unwrap();
+        fs::write(&path_a, "a").unwrap();
+        fs::write(&path_b, "b changed").unwrap();
+        assert!(watcher.poll_r().unwrap().is_empty());
+        assert_eq!(2, watcher.pending.len());
+        fs::remove_file(&path_a).unwrap();
+        watcher.debounce = Duration::ZERO;
+        assert_eq!(vec![WatchEvent { path: path_b.clone(), kind: WatchEventKind::Modified }], watcher.poll_r().unwrap());
+
+        assert!(FileWatcher::new_r(&["[unclosed"], Duration::ZERO, Duration::ZERO).is_err());
+    }
+}